@@ -0,0 +1,36 @@
+//! Polls Easee for a minute into memory and prints what was written, using only the prelude.
+//!
+//! Needs the credentials, e.g. `CREDENTIALS_SOURCE=env USERNAME=.. PASSWORD=..`.
+use std::{sync::Arc, time::Duration};
+
+use easee_status::prelude::*;
+use tokio_util::sync::CancellationToken;
+
+#[tokio::main]
+async fn main() {
+    init_http_client();
+    let client = EaseeClient::new();
+    for charger in client
+        .chargers()
+        .await
+        .expect("listing the chargers failed")
+    {
+        println!("{} {}", charger.id, charger.name.unwrap_or_default());
+    }
+
+    let sink = Arc::new(MemorySink::new());
+    let target = DbTarget::with_sink(String::from("memory"), String::from("easee"), sink.clone());
+    let poller = Poller::builder(Arc::new(vec![target]))
+        .client(&client)
+        .interval(Duration::from_secs(15))
+        .build();
+    let shutdown = CancellationToken::new();
+    let running = tokio::spawn(poller.run(shutdown.clone()));
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    shutdown.cancel();
+    running.await.unwrap();
+
+    for line in sink.lines() {
+        println!("{}", line);
+    }
+}
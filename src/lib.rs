@@ -1,3 +1,9 @@
-pub mod v1;
-pub use v1::run::{get_db_info, tick};
-pub use v1::structs::SessionState;
+pub mod prelude;
+mod v1;
+#[cfg(feature = "blocking")]
+pub use v1::blocking;
+pub use v1::routes::{serve, Cache, ServerConfig, ServerError};
+pub use v1::run::{get_db_info, get_interval, get_mode, tick, DbTarget};
+pub use v1::structs::{
+    ChargerInfo, ChargerState, ChargerStateBuilder, EaseeError, Mode, SessionState,
+};
@@ -1,32 +1,107 @@
-use std::{env, sync::Arc};
+use std::sync::Arc;
 
 use tokio::{self, sync::Mutex};
+use tokio_util::sync::CancellationToken;
 use tracing::Level;
 
-use easee_status::{get_db_info, tick};
-use easee_status::{v1::run::get_logger, SessionState};
+use easee_status::prelude::*;
 
 #[tokio::main]
 async fn main() {
-    let (subscriber, _guard) = get_logger();
+    let (subscriber, log_guards) = get_logger();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    log_guards.log_setup_errors();
     tracing::trace!("Log setup complete");
-    let (db_addr, db_name) = get_db_info();
+    // Every InfluxDB target shares the client, so it is built before any of them
+    if let Err(e) = init_http_client() {
+        tracing::error!("{}", e);
+        exit(1, log_guards);
+    }
+
+    match std::env::args().nth(1).as_deref() {
+        Some("check-db") => {
+            let passed = check_db(&get_db_info("check-db")).await;
+            exit(if passed { 0 } else { 1 }, log_guards);
+        }
+        Some("backfill") => {
+            let passed = backfill(
+                &BackfillConfig::from_env(),
+                &get_db_info("backfill"),
+                Arc::new(Mutex::new(SessionState::new())),
+            )
+            .await;
+            exit(if passed { 0 } else { 1 }, log_guards);
+        }
+        Some(command) => {
+            eprintln!("Unknown command: {}", command);
+            eprintln!("Usage: easee_status [check-db|backfill]");
+            exit(2, log_guards);
+        }
+        None => (),
+    }
+
+    // Read before anything is spawned, so missing variables stop the process in both modes
+    let Config { mode, targets, .. } = Config::from_env();
+    if let Err(e) = load_overrides() {
+        tracing::error!("{}", e);
+        exit(1, log_guards);
+    }
+    let server_config = match mode {
+        Mode::Poller => None,
+        Mode::Server | Mode::Both => match ServerConfig::from_env() {
+            Ok(config) => Some(config),
+            Err(e) => {
+                tracing::error!("{}", e);
+                exit(1, log_guards);
+            }
+        },
+    };
+    log_startup_banner(mode);
 
     let s = tracing::span!(Level::TRACE, "main");
     let _guard = s.enter();
 
-    let mut interval_timer = tokio::time::interval(
-        chrono::Duration::minutes(
-            env::var("INTERVAL").map_or(1, |i| i.parse().expect("Illegal interval format")),
-        )
-        .to_std()
-        .unwrap(),
-    );
-    let login_state = Arc::new(Mutex::new(SessionState::new()));
-    loop {
-        interval_timer.tick().await;
-
-        tokio::spawn(tick(login_state.clone(), db_addr.clone(), db_name.clone()));
+    let client = EaseeClient::new();
+    let login_state = client.session();
+    let errors = Arc::new(LastErrors::new());
+    let shutdown = CancellationToken::new();
+    tokio::spawn(client.keep_fresh(shutdown.clone()));
+    tokio::spawn(watch_clock(login_state.clone(), shutdown.clone()));
+    tokio::spawn(watch_drain_signal(shutdown.clone()));
+    let server_config = server_config.map(|config| ServerConfig {
+        shutdown: shutdown.clone(),
+        ..config
+    });
+    let poller = |targets| {
+        Poller::builder(targets)
+            .client(&client)
+            .errors(errors.clone())
+    };
+    match mode {
+        Mode::Poller => poller(targets.unwrap()).build().run(shutdown.clone()).await,
+        Mode::Server => {
+            let cache = Arc::new(Cache::new(get_interval()));
+            if let Err(e) = serve(server_config.unwrap(), login_state, cache, errors).await {
+                tracing::error!("{}, shutting down", e);
+                exit(1, log_guards);
+            }
+        }
+        Mode::Both => {
+            let cache = Arc::new(Cache::fed());
+            let poller = poller(targets.unwrap()).cache(cache.clone()).build();
+            let poller = tokio::spawn(poller.run(shutdown.clone()));
+            let server = tokio::spawn(serve(server_config.unwrap(), login_state, cache, errors));
+            if let Err(e) = run_both(server, poller, shutdown.clone()).await {
+                tracing::error!("{}, shutting down", e);
+                exit(1, log_guards);
+            }
+        }
     }
+    shutdown.cancel();
+}
+
+/// Exits once the logs are written, which `std::process::exit` alone would cut short.
+fn exit(code: i32, log_guards: LoggerGuards) -> ! {
+    drop(log_guards);
+    std::process::exit(code)
 }
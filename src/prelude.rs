@@ -0,0 +1,33 @@
+//! Everything needed to run or embed the service, importable with `use easee_status::prelude::*`.
+//!
+//! The binary is built from this alone, the modules behind it are private to the crate.
+
+pub use crate::v1::{
+    backfill::{backfill, BackfillConfig},
+    backlog::spawn_tick,
+    banner::log_startup_banner,
+    check_db::check_db,
+    client::EaseeClient,
+    clock::watch_clock,
+    config::Config,
+    drain::{is_draining, watch_drain_signal},
+    easee::{
+        clear_session, force_login, get_charger_state, get_charger_states, init_http_client,
+        keep_session_fresh, parse_charger_list, ChargerResult, ConfigResult,
+    },
+    health::LastErrors,
+    logging::{build_logger, get_logger, LoggerGuards},
+    mode::run_both,
+    overrides::load as load_overrides,
+    poller::{Poller, PollerBuilder},
+    record::{recorded_responses, Recorded},
+    routes::{serve, Cache, ServerConfig, ServerError},
+    run::{
+        get_db_info, get_interval, get_mode, get_slow_interval, get_tick_deadline, slow_tick, tick,
+        DbTarget, PollerState,
+    },
+    schema::{schema_annotations_enabled, write_schema_annotations},
+    sink::{InfluxSink, MemorySink, Sink},
+    source::check_other_writers,
+    structs::{ChargerInfo, ChargerState, ChargerStateBuilder, EaseeError, Mode, SessionState},
+};
@@ -0,0 +1,190 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use tracing::{info, warn};
+
+use super::anonymize::log_id;
+
+/// Chargers Easee keeps refusing us, e.g. ones seen through a shared site, or keeps
+/// answering 404 for, e.g. ones factory reset but still listed.
+///
+/// After `NO_ACCESS_AFTER` refusals in a row a charger is marked `no_access` and only
+/// probed again every `NO_ACCESS_REPROBE_HOURS`.
+#[derive(Debug)]
+struct AccessTracker {
+    after: u32,
+    reprobe: Duration,
+    chargers: HashMap<String, ChargerAccess>,
+}
+
+#[derive(Debug, Default)]
+struct ChargerAccess {
+    refused: u32,
+    /// When the charger was last fetched while marked `no_access`.
+    no_access_since: Option<Instant>,
+}
+
+static ACCESS: OnceLock<Mutex<AccessTracker>> = OnceLock::new();
+
+fn access() -> &'static Mutex<AccessTracker> {
+    ACCESS.get_or_init(|| {
+        let after = env::var("NO_ACCESS_AFTER")
+            .map_or(3, |a| a.parse().expect("Illegal no access count format"));
+        tracing::info!("NO_ACCESS_AFTER: {}", after);
+
+        let hours: u64 = env::var("NO_ACCESS_REPROBE_HOURS")
+            .map_or(6, |h| h.parse().expect("Illegal re-probe interval format"));
+        tracing::info!("NO_ACCESS_REPROBE_HOURS: {}", hours);
+
+        Mutex::new(AccessTracker::new(after, Duration::from_secs(hours * 3600)))
+    })
+}
+
+/// Whether the charger is marked `no_access`.
+pub fn no_access(charger_id: &str) -> bool {
+    access().lock().unwrap().no_access(charger_id)
+}
+
+/// Whether to fetch the charger now: always, unless it is marked `no_access` and not due
+/// for a re-probe. A due re-probe restarts the wait.
+pub fn should_fetch(charger_id: &str) -> bool {
+    access()
+        .lock()
+        .unwrap()
+        .should_fetch(charger_id, Instant::now())
+}
+
+/// Drops what is known about the charger.
+pub fn forget(charger_id: &str) {
+    access().lock().unwrap().chargers.remove(charger_id);
+}
+
+/// Records Easee refusing the charger.
+pub fn refused(charger_id: &str) {
+    access().lock().unwrap().refused(charger_id, Instant::now());
+}
+
+/// Records a successful fetch of the charger.
+pub fn granted(charger_id: &str) {
+    access().lock().unwrap().granted(charger_id);
+}
+
+impl AccessTracker {
+    fn new(after: u32, reprobe: Duration) -> Self {
+        AccessTracker {
+            after,
+            reprobe,
+            chargers: HashMap::new(),
+        }
+    }
+
+    fn no_access(&self, charger_id: &str) -> bool {
+        self.chargers
+            .get(charger_id)
+            .is_some_and(|c| c.no_access_since.is_some())
+    }
+
+    fn should_fetch(&mut self, charger_id: &str, now: Instant) -> bool {
+        match self.chargers.get_mut(charger_id) {
+            Some(ChargerAccess {
+                no_access_since: Some(since),
+                ..
+            }) => {
+                if now.duration_since(*since) >= self.reprobe {
+                    *since = now;
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => true,
+        }
+    }
+
+    fn refused(&mut self, charger_id: &str, now: Instant) {
+        let charger = self.chargers.entry(charger_id.to_string()).or_default();
+        charger.refused += 1;
+        if charger.refused >= self.after && charger.no_access_since.is_none() {
+            warn!(
+                "No access to charger {} after {} refusals, fetching it only occasionally",
+                log_id(charger_id),
+                charger.refused
+            );
+            charger.no_access_since = Some(now);
+        }
+    }
+
+    fn granted(&mut self, charger_id: &str) {
+        if let Some(charger) = self.chargers.remove(charger_id) {
+            if charger.no_access_since.is_some() {
+                info!("Access to charger {} is back", log_id(charger_id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn marks_no_access_after_refusals_in_a_row() {
+        let start = Instant::now();
+        let mut tracker = AccessTracker::new(3, HOUR * 6);
+        tracker.refused("EH1", start);
+        tracker.refused("EH1", start);
+        assert!(!tracker.no_access("EH1"));
+        assert!(tracker.should_fetch("EH1", start));
+        tracker.refused("EH1", start);
+        assert!(tracker.no_access("EH1"));
+        assert!(!tracker.should_fetch("EH1", start + HOUR));
+        // Others are unaffected
+        assert!(!tracker.no_access("EH2"));
+        assert!(tracker.should_fetch("EH2", start));
+    }
+
+    #[test]
+    fn a_success_in_between_starts_over() {
+        let start = Instant::now();
+        let mut tracker = AccessTracker::new(3, HOUR * 6);
+        tracker.refused("EH1", start);
+        tracker.refused("EH1", start);
+        tracker.granted("EH1");
+        tracker.refused("EH1", start);
+        tracker.refused("EH1", start);
+        assert!(!tracker.no_access("EH1"));
+    }
+
+    #[test]
+    fn reprobes_every_interval() {
+        let start = Instant::now();
+        let mut tracker = AccessTracker::new(1, HOUR * 6);
+        tracker.refused("EH1", start);
+        assert!(!tracker.should_fetch("EH1", start + HOUR * 5));
+        assert!(tracker.should_fetch("EH1", start + HOUR * 6));
+        // The re-probe restarts the wait
+        assert!(!tracker.should_fetch("EH1", start + HOUR * 7));
+        // Refused again, it stays marked from the first time
+        tracker.refused("EH1", start + HOUR * 6);
+        assert!(tracker.no_access("EH1"));
+        assert!(!tracker.should_fetch("EH1", start + HOUR * 11));
+        assert!(tracker.should_fetch("EH1", start + HOUR * 12));
+    }
+
+    #[test]
+    fn granted_after_a_reprobe_clears_the_mark() {
+        let start = Instant::now();
+        let mut tracker = AccessTracker::new(1, HOUR);
+        tracker.refused("EH1", start);
+        assert!(tracker.should_fetch("EH1", start + HOUR));
+        tracker.granted("EH1");
+        assert!(!tracker.no_access("EH1"));
+        assert!(tracker.should_fetch("EH1", start + HOUR));
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    env,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tracing::{info, instrument};
+
+use super::structs::ChargerState;
+
+/// The polling mode, for `/stats`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IntervalReport {
+    pub fast_mode: bool,
+    pub interval_secs: u64,
+    pub base_interval_secs: u64,
+    pub fast_interval_secs: Option<u64>,
+    /// Idle ticks seen since the last charging one, while polling fast.
+    pub idle_ticks: u32,
+}
+
+/// What the poller of this process last reported, `None` when it runs no poller.
+static LAST_REPORT: OnceLock<Mutex<Option<IntervalReport>>> = OnceLock::new();
+
+fn last_report() -> &'static Mutex<Option<IntervalReport>> {
+    LAST_REPORT.get_or_init(|| Mutex::new(None))
+}
+
+/// Polls at `FAST_INTERVAL` while any charger is charging, and at the base interval
+/// once every charger has been idle for a few ticks.
+#[derive(Debug)]
+pub struct AdaptiveInterval {
+    base: Duration,
+    fast: Option<Duration>,
+    power_threshold: f64,
+    idle_ticks_to_slow: u32,
+    idle_ticks: u32,
+    fast_mode: bool,
+}
+
+impl AdaptiveInterval {
+    pub fn new(
+        base: Duration,
+        fast: Option<Duration>,
+        power_threshold: f64,
+        idle_ticks_to_slow: u32,
+    ) -> Self {
+        let interval = AdaptiveInterval {
+            base,
+            fast,
+            power_threshold,
+            idle_ticks_to_slow,
+            idle_ticks: 0,
+            fast_mode: false,
+        };
+        interval.publish();
+        interval
+    }
+
+    #[instrument(level = "trace")]
+    pub fn from_env(base: Duration) -> Self {
+        let fast = env::var("FAST_INTERVAL")
+            .ok()
+            .map(|f| Duration::from_secs(f.parse().expect("Illegal fast interval format")));
+        tracing::info!("FAST_INTERVAL: {:?}", fast.map(|f| f.as_secs()));
+
+        let power_threshold = env::var("FAST_POWER_THRESHOLD").map_or(0.1, |p| {
+            p.parse().expect("Illegal fast power threshold format")
+        });
+        tracing::info!("FAST_POWER_THRESHOLD: {}", power_threshold);
+
+        let idle_ticks_to_slow = env::var("FAST_IDLE_TICKS")
+            .map_or(5, |t| t.parse().expect("Illegal fast idle ticks format"));
+        tracing::info!("FAST_IDLE_TICKS: {}", idle_ticks_to_slow);
+
+        AdaptiveInterval::new(base, fast, power_threshold, idle_ticks_to_slow)
+    }
+
+    /// Records the chargers seen by a tick.
+    pub fn observe(&mut self, chargers: &[ChargerState]) {
+        if self.fast.is_none() {
+            return;
+        }
+        let charging = chargers.iter().any(|c| c.power > self.power_threshold);
+        if charging {
+            self.idle_ticks = 0;
+            if !self.fast_mode {
+                info!("A charger is charging, polling fast");
+                self.fast_mode = true;
+            }
+        } else if self.fast_mode {
+            self.idle_ticks += 1;
+            if self.idle_ticks >= self.idle_ticks_to_slow {
+                info!(
+                    "No charger charged for {} ticks, polling at the base interval",
+                    self.idle_ticks
+                );
+                self.fast_mode = false;
+                self.idle_ticks = 0;
+            }
+        }
+        self.publish();
+    }
+
+    pub fn is_fast(&self) -> bool {
+        self.fast_mode
+    }
+
+    /// The interval until the next tick.
+    pub fn current(&self) -> Duration {
+        match self.fast {
+            Some(fast) if self.fast_mode => fast,
+            _ => self.base,
+        }
+    }
+
+    pub fn report(&self) -> IntervalReport {
+        IntervalReport {
+            fast_mode: self.fast_mode,
+            interval_secs: self.current().as_secs(),
+            base_interval_secs: self.base.as_secs(),
+            fast_interval_secs: self.fast.map(|f| f.as_secs()),
+            idle_ticks: self.idle_ticks,
+        }
+    }
+
+    fn publish(&self) {
+        *last_report().lock().unwrap() = Some(self.report());
+    }
+}
+
+/// The polling mode of the poller in this process, `None` in `MODE=server`.
+pub fn report() -> Option<IntervalReport> {
+    last_report().lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Duration = Duration::from_secs(60);
+    const FAST: Duration = Duration::from_secs(15);
+
+    fn tick(power: f64) -> Vec<ChargerState> {
+        let json: serde_json::Value =
+            serde_json::from_str(include_str!("../../tests/fixtures/state_idle.json")).unwrap();
+        let mut state = ChargerState::from_state_json("EH000001", &json).unwrap();
+        state.power = power;
+        vec![state]
+    }
+
+    #[test]
+    fn switches_on_charging_and_back_after_idle_ticks() {
+        // Power seen by each tick, and the interval until the next one
+        let cases = [
+            (0.0, BASE),
+            (11.0, FAST),
+            (0.0, FAST),
+            (0.0, FAST),
+            (7.0, FAST),
+            (0.0, FAST),
+            (0.0, FAST),
+            (0.0, BASE),
+            (0.0, BASE),
+        ];
+        let mut interval = AdaptiveInterval::new(BASE, Some(FAST), 0.1, 3);
+        for (i, (power, expected)) in cases.into_iter().enumerate() {
+            interval.observe(&tick(power));
+            assert_eq!(interval.current(), expected, "tick {}", i);
+            assert_eq!(interval.is_fast(), expected == FAST, "tick {}", i);
+        }
+    }
+
+    #[test]
+    fn power_at_the_threshold_is_idle() {
+        let mut interval = AdaptiveInterval::new(BASE, Some(FAST), 1.0, 1);
+        interval.observe(&tick(1.0));
+        assert_eq!(interval.current(), BASE);
+        interval.observe(&tick(1.5));
+        assert_eq!(interval.current(), FAST);
+    }
+
+    #[test]
+    fn stays_at_the_base_interval_without_a_fast_one() {
+        let mut interval = AdaptiveInterval::new(BASE, None, 0.1, 1);
+        interval.observe(&tick(11.0));
+        assert_eq!(interval.current(), BASE);
+        assert!(!interval.report().fast_mode);
+    }
+
+    #[test]
+    fn reports_the_mode() {
+        let mut interval = AdaptiveInterval::new(BASE, Some(FAST), 0.1, 2);
+        interval.observe(&tick(11.0));
+        interval.observe(&tick(0.0));
+        assert_eq!(
+            interval.report(),
+            IntervalReport {
+                fast_mode: true,
+                interval_secs: 15,
+                base_interval_secs: 60,
+                fast_interval_secs: Some(15),
+                idle_ticks: 1,
+            }
+        );
+    }
+}
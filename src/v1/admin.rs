@@ -0,0 +1,169 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use rocket::{delete, http::Status, post, response::status::Custom, serde::json::Json, State};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use super::{
+    api_key::{ApiKey, Confirmed},
+    commands::{CommandHistory, CommandRecord},
+    drain::set_draining,
+    easee::{clear_session, force_login, override_schedule},
+    routes::{route_error, Cache, RouteError},
+    run::DbTarget,
+    structs::SessionState,
+};
+
+/// Logs in to Easee from scratch. A failed login leaves the current session in place.
+#[utoipa::path(
+    post,
+    path = "/session/login",
+    params(
+        ("X-Api-Key" = String, Header, description = "The API_KEY"),
+    ),
+    responses(
+        (status = 200, description = "Logged in, with when the new token expires"),
+        (status = 401, description = "Missing or wrong X-Api-Key"),
+        (status = 403, description = "No API_KEY configured, the route is closed"),
+        (status = 502, description = "Easee rejected the login, the session is kept"),
+    )
+)]
+#[post("/session/login")]
+pub async fn login_session(
+    _key: ApiKey,
+    session: &State<Arc<Mutex<SessionState>>>,
+) -> Result<Json<serde_json::Value>, RouteError> {
+    match force_login(session.inner().clone()).await {
+        Ok(expires) => Ok(Json(serde_json::json!({ "expires": expires }))),
+        Err(e) => {
+            warn!("Manual login failed: {}", e);
+            Err(RouteError::Detailed(Custom(
+                e.http_status(),
+                Json(serde_json::json!({ "error": e.user_message() })),
+            )))
+        }
+    }
+}
+
+/// Forgets the session, so the next tick logs in again.
+#[utoipa::path(
+    delete,
+    path = "/session",
+    params(
+        ("X-Api-Key" = String, Header, description = "The API_KEY"),
+    ),
+    responses(
+        (status = 204, description = "The session is forgotten"),
+        (status = 401, description = "Missing or wrong X-Api-Key"),
+        (status = 403, description = "No API_KEY configured, the route is closed"),
+    )
+)]
+#[delete("/session")]
+pub async fn delete_session(_key: ApiKey, session: &State<Arc<Mutex<SessionState>>>) -> Status {
+    clear_session(session.inner().clone()).await;
+    Status::NoContent
+}
+
+/// Stops starting ticks and fails `/readyz`, for handing over to a new instance.
+#[utoipa::path(
+    post,
+    path = "/admin/drain",
+    params(
+        ("X-Api-Key" = String, Header, description = "The API_KEY"),
+    ),
+    responses(
+        (status = 200, description = "Draining, ticks already running finish"),
+        (status = 401, description = "Missing or wrong X-Api-Key"),
+        (status = 403, description = "No API_KEY configured, the route is closed"),
+    )
+)]
+#[post("/admin/drain")]
+pub fn drain(_key: ApiKey) -> Json<serde_json::Value> {
+    set_draining(true);
+    Json(serde_json::json!({ "draining": true }))
+}
+
+/// Starts ticking again after `/admin/drain`.
+#[utoipa::path(
+    post,
+    path = "/admin/undrain",
+    params(
+        ("X-Api-Key" = String, Header, description = "The API_KEY"),
+    ),
+    responses(
+        (status = 200, description = "Ticking again"),
+        (status = 401, description = "Missing or wrong X-Api-Key"),
+        (status = 403, description = "No API_KEY configured, the route is closed"),
+    )
+)]
+#[post("/admin/undrain")]
+pub fn undrain(_key: ApiKey) -> Json<serde_json::Value> {
+    set_draining(false);
+    Json(serde_json::json!({ "draining": false }))
+}
+
+/// Starts charging now, ignoring the schedule, and refreshes the cache in the background.
+#[utoipa::path(
+    post,
+    path = "/charger/{id}/charge_now",
+    params(
+        ("id" = String, Path, description = "Easee id of the charger"),
+        ("X-Api-Key" = String, Header, description = "The API_KEY"),
+        ("X-Confirm" = String, Header, description = "Must be yes"),
+    ),
+    responses(
+        (status = 202, description = "Easee accepted the command, with its answer"),
+        (status = 401, description = "Missing or wrong X-Api-Key"),
+        (status = 403, description = "No API_KEY configured, the route is closed"),
+        (status = 409, description = "Easee refused the command, e.g. as the charger is already charging"),
+        (status = 428, description = "X-Confirm isn't yes"),
+        (status = 502, description = "Easee failed the command"),
+        (status = 503, description = "Easee is under maintenance"),
+    )
+)]
+#[post("/charger/<id>/charge_now")]
+pub async fn charge_now(
+    id: &str,
+    key: ApiKey,
+    _confirmed: Confirmed,
+    session: &State<Arc<Mutex<SessionState>>>,
+    cache: &State<Arc<Cache>>,
+    history: &State<CommandHistory>,
+    influx: &State<Option<DbTarget>>,
+) -> Result<Custom<Json<serde_json::Value>>, RouteError> {
+    let sent = override_schedule(id, session.inner().clone()).await;
+    history
+        .record(
+            CommandRecord {
+                at: Utc::now(),
+                key_id: key.key_id,
+                command: "charge_now",
+                charger_id: id.to_string(),
+                status: sent.as_ref().ok().map(|(status, _)| *status),
+                error: sent.as_ref().err().map(|e| e.to_string()),
+            },
+            influx.inner().as_ref(),
+        )
+        .await;
+    let (status, body) = sent.map_err(|e| route_error(&e))?;
+    let result = serde_json::from_str(&body).unwrap_or(serde_json::Value::Null);
+    let status = match status {
+        200..=299 => {
+            let (cache, session) = (cache.inner().clone(), session.inner().clone());
+            tokio::spawn(async move {
+                if let Err(e) = cache.refresh(session).await {
+                    debug!("Refreshing after charge now failed: {}", e);
+                }
+            });
+            Status::Accepted
+        }
+        // Easee refuses the command when the charger is already charging
+        400 | 409 => Status::Conflict,
+        _ => Status::BadGateway,
+    };
+    Ok(Custom(
+        status,
+        Json(serde_json::json!({ "status": status.code, "result": result })),
+    ))
+}
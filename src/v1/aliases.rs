@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use rocket::{get, http::Status, request::FromParam, response::Redirect, State};
+use tracing::trace;
+
+use super::{base_path::BasePath, rate_limit::RateLimited, routes::Field};
+
+/// Legacy route names and where they redirect to, when `LEGACY_ALIASES` isn't set.
+const DEFAULT_ALIASES: &str =
+    "carChargerUsage=/power/0,easeeLadeMengde=/session/0,easeeEnergyPerHour=/energy/0";
+
+/// Single segment routes redirecting to a field route, from `legacy_aliases`.
+#[derive(Debug, Clone)]
+pub struct LegacyAliases {
+    targets: HashMap<String, String>,
+}
+
+impl Default for LegacyAliases {
+    fn default() -> Self {
+        LegacyAliases::parse(DEFAULT_ALIASES).unwrap()
+    }
+}
+
+impl LegacyAliases {
+    /// Parses `alias=/field/index,...`, targets also being `/charger/<id>/<field>`. Fails
+    /// on targets that aren't field routes, so a typo fails at startup rather than on the
+    /// first request.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut targets = HashMap::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (alias, target) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("No target for {}", entry))?;
+            validate_target(target)?;
+            targets.insert(alias.trim().to_string(), target.trim().to_string());
+        }
+        Ok(LegacyAliases { targets })
+    }
+}
+
+fn validate_target(target: &str) -> Result<(), String> {
+    let segments: Vec<&str> = target.trim().trim_start_matches('/').split('/').collect();
+    match segments.as_slice() {
+        [field, index] if Field::from_param(field).is_ok() && index.parse::<usize>().is_ok() => {
+            Ok(())
+        }
+        ["charger", id, field] if !id.is_empty() && Field::from_param(field).is_ok() => Ok(()),
+        _ => Err(format!(
+            "{} is not a /<field>/<index> or /charger/<id>/<field> route",
+            target
+        )),
+    }
+}
+
+/// Other names for the fields of `/<field>/<index>`, from `field_aliases`.
+#[derive(Debug, Clone, Default)]
+pub struct FieldAliases {
+    fields: HashMap<String, Field>,
+}
+
+impl FieldAliases {
+    /// Parses `alias=field,...`, such as `watts=power`. Fails on fields that don't exist.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut fields = HashMap::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (alias, field) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("No field for {}", entry))?;
+            let field = Field::from_param(field.trim())
+                .map_err(|field| format!("{} is not a field", field))?;
+            fields.insert(alias.trim().to_string(), field);
+        }
+        Ok(FieldAliases { fields })
+    }
+
+    pub fn get(&self, alias: &str) -> Option<Field> {
+        self.fields.get(alias).copied()
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/{alias}",
+    params(
+        ("alias" = String, Path, description = "A name from LEGACY_ALIASES"),
+    ),
+    responses(
+        (status = 303, description = "Redirect to the field route the alias points at"),
+        (status = 404, description = "No such alias"),
+        (status = 429, description = "Rate limit exceeded, see Retry-After"),
+    )
+)]
+// Ranked after the other single segment routes so an alias can't shadow them
+#[get("/<alias>", rank = 20)]
+pub fn legacy_alias(
+    alias: &str,
+    _limit: RateLimited,
+    base: BasePath,
+    aliases: &State<LegacyAliases>,
+) -> Result<Redirect, Status> {
+    let target = aliases.targets.get(alias).ok_or(Status::NotFound)?;
+    trace!("Redirecting {} to {}", alias, target);
+    Ok(Redirect::to(base.join(target)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{alias}/{index}",
+    params(
+        ("alias" = String, Path, description = "A name from FIELD_ALIASES"),
+        ("index" = usize, Path, description = "Position of the charger in the charger list"),
+    ),
+    responses(
+        (status = 303, description = "Redirect to the field route of the field the alias names"),
+        (status = 404, description = "No such alias"),
+        (status = 429, description = "Rate limit exceeded, see Retry-After"),
+    )
+)]
+// Ranked after the field routes, so it only sees what isn't a field
+#[get("/<alias>/<index>", rank = 20)]
+pub fn field_alias(
+    alias: &str,
+    index: usize,
+    _limit: RateLimited,
+    base: BasePath,
+    aliases: &State<FieldAliases>,
+) -> Result<Redirect, Status> {
+    let field = aliases.get(alias).ok_or(Status::NotFound)?;
+    let target = format!("/{}/{}", field.as_str(), index);
+    trace!("Redirecting {} to {}", alias, target);
+    Ok(Redirect::to(base.join(&target)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_are_validated() {
+        let cases = [
+            ("/power/0", true),
+            ("dynamic_current/2", true),
+            ("/charger/EH000001/power", true),
+            ("/charger/EH000001/session", true),
+            ("/charger/EH000001", false),
+            ("/charger//power", false),
+            ("/charger/EH000001/sessions_today", false),
+            ("/voltage/0", false),
+            ("/power/first", false),
+            ("/power/0/1", false),
+        ];
+        for (target, valid) in cases {
+            assert_eq!(validate_target(target).is_ok(), valid, "{}", target);
+        }
+    }
+
+    #[test]
+    fn aliases_point_at_both_kinds_of_route() {
+        let aliases =
+            LegacyAliases::parse("usage=/power/0, garage=/charger/EH000001/power,").unwrap();
+        assert_eq!(aliases.targets["usage"], "/power/0");
+        assert_eq!(aliases.targets["garage"], "/charger/EH000001/power");
+        assert!(LegacyAliases::parse("garage").is_err());
+        assert_eq!(LegacyAliases::default().targets.len(), 3);
+    }
+
+    #[test]
+    fn field_aliases_name_existing_fields() {
+        let aliases = FieldAliases::parse("watts = power, kwh=session,").unwrap();
+        assert_eq!(aliases.get("watts").unwrap().as_str(), "power");
+        assert_eq!(aliases.get("kwh").unwrap().as_str(), "session");
+        assert!(aliases.get("power").is_none());
+        assert!(FieldAliases::parse("watts=voltage").is_err());
+        assert!(FieldAliases::parse("watts").is_err());
+        assert!(FieldAliases::parse("").unwrap().get("watts").is_none());
+    }
+}
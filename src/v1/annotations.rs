@@ -0,0 +1,315 @@
+use std::{collections::HashMap, env};
+
+use chrono::{DateTime, Utc};
+use influxdb::{InfluxDbWriteable, WriteQuery};
+use tracing::instrument;
+
+use super::{
+    names::display_name,
+    structs::{ChargerInfo, ChargerState},
+};
+
+/// Measurement Grafana annotation queries read, one point per event.
+pub const ANNOTATIONS_MEASUREMENT: &str = "annotations";
+
+/// Changes in a charger worth marking on the dashboards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    SessionStart,
+    SessionEnd,
+    Offline,
+    Online,
+    /// Easee's `reasonForNoCurrent` changed to the code.
+    Reason(Option<i64>),
+}
+
+impl Transition {
+    fn key(&self) -> &'static str {
+        match self {
+            Transition::SessionStart => "session_start",
+            Transition::SessionEnd => "session_end",
+            Transition::Offline => "offline",
+            Transition::Online => "online",
+            Transition::Reason(_) => "reason",
+        }
+    }
+
+    /// The template var and its default. `{name}`, `{id}` and `{code}` are filled in.
+    fn template(&self) -> (&'static str, &'static str) {
+        match self {
+            Transition::SessionStart => {
+                ("ANNOTATION_TEXT_SESSION_START", "{name} started charging")
+            }
+            Transition::SessionEnd => ("ANNOTATION_TEXT_SESSION_END", "{name} stopped charging"),
+            Transition::Offline => ("ANNOTATION_TEXT_OFFLINE", "{name} went offline"),
+            Transition::Online => ("ANNOTATION_TEXT_ONLINE", "{name} is back online"),
+            Transition::Reason(_) => (
+                "ANNOTATION_TEXT_REASON",
+                "{name} reason for no current is now {code}",
+            ),
+        }
+    }
+}
+
+/// Title, text and tags are fields, not InfluxDB tags, so the free text can't blow up the
+/// series cardinality.
+#[derive(Debug, Clone, InfluxDbWriteable)]
+struct AnnotationPoint {
+    time: DateTime<Utc>,
+    title: String,
+    text: String,
+    /// Comma separated, as Grafana splits them.
+    tags: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Marks {
+    reachable: bool,
+    charging: bool,
+    reason: Option<i64>,
+}
+
+/// Detects transitions between ticks and renders them as annotation points, enabled by
+/// `WRITE_ANNOTATIONS`.
+#[derive(Debug)]
+pub struct AnnotationTracker {
+    enabled: bool,
+    templates: HashMap<&'static str, String>,
+    last: HashMap<String, Marks>,
+}
+
+impl AnnotationTracker {
+    pub fn new(enabled: bool, templates: HashMap<&'static str, String>) -> Self {
+        AnnotationTracker {
+            enabled,
+            templates,
+            last: HashMap::new(),
+        }
+    }
+
+    #[instrument(level = "trace")]
+    pub fn from_env() -> Self {
+        let enabled = env::var("WRITE_ANNOTATIONS").is_ok_and(|w| w == "true");
+        tracing::info!("WRITE_ANNOTATIONS: {}", enabled);
+        let templates = [
+            Transition::SessionStart,
+            Transition::SessionEnd,
+            Transition::Offline,
+            Transition::Online,
+            Transition::Reason(None),
+        ]
+        .iter()
+        .map(|transition| {
+            let (var, default) = transition.template();
+            (
+                transition.key(),
+                env::var(var).unwrap_or_else(|_| default.to_string()),
+            )
+        })
+        .collect();
+        AnnotationTracker::new(enabled, templates)
+    }
+
+    /// Compares the tick with the previous one. The first sight of a charger marks nothing.
+    pub fn observe(
+        &mut self,
+        state: &[ChargerState],
+        unreachable: &[ChargerInfo],
+        time: DateTime<Utc>,
+    ) -> Vec<WriteQuery> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let mut points = Vec::new();
+        for charger in state {
+            let marks = Marks {
+                reachable: true,
+                charging: charger.power > 0.0,
+                reason: charger.reason_for_no_current,
+            };
+            let previous = self.last.insert(charger.id.clone(), marks);
+            let transitions = match previous {
+                Some(previous) => transitions(previous, marks),
+                None => Vec::new(),
+            };
+            for transition in transitions {
+                points.push(self.point(transition, &charger.id, &charger.name, time));
+            }
+        }
+        for charger in unreachable {
+            let went_offline = match self.last.get_mut(&charger.id) {
+                Some(previous) if previous.reachable => {
+                    previous.reachable = false;
+                    true
+                }
+                _ => false,
+            };
+            if went_offline {
+                let name = display_name(&charger.id, charger.name.as_deref());
+                points.push(self.point(Transition::Offline, &charger.id, &name, time));
+            }
+        }
+        points
+    }
+
+    pub fn forget(&mut self, charger_id: &str) {
+        self.last.remove(charger_id);
+    }
+
+    fn point(
+        &self,
+        transition: Transition,
+        id: &str,
+        name: &str,
+        time: DateTime<Utc>,
+    ) -> WriteQuery {
+        let code = match transition {
+            Transition::Reason(Some(code)) => code.to_string(),
+            _ => String::from("none"),
+        };
+        let text = self.templates[transition.key()]
+            .replace("{name}", name)
+            .replace("{id}", id)
+            .replace("{code}", &code);
+        AnnotationPoint {
+            time,
+            title: format!("{}: {}", name, transition.key().replace('_', " ")),
+            text,
+            tags: format!("easee,{},{}", id, transition.key()),
+        }
+        .into_query(ANNOTATIONS_MEASUREMENT)
+    }
+}
+
+fn transitions(previous: Marks, current: Marks) -> Vec<Transition> {
+    let mut transitions = Vec::new();
+    if !previous.reachable && current.reachable {
+        transitions.push(Transition::Online);
+    }
+    match (previous.charging, current.charging) {
+        (false, true) => transitions.push(Transition::SessionStart),
+        (true, false) => transitions.push(Transition::SessionEnd),
+        _ => (),
+    }
+    if previous.reason != current.reason {
+        transitions.push(Transition::Reason(current.reason));
+    }
+    transitions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Transition; 5] = [
+        Transition::SessionStart,
+        Transition::SessionEnd,
+        Transition::Offline,
+        Transition::Online,
+        Transition::Reason(None),
+    ];
+
+    /// 2023-11-14 22:13:20 UTC, as written by the points.
+    const NANOS: &str = "1700000000000000000";
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn tracker() -> AnnotationTracker {
+        let templates = ALL
+            .iter()
+            .map(|t| (t.key(), t.template().1.to_string()))
+            .collect();
+        AnnotationTracker::new(true, templates)
+    }
+
+    fn charger(power: f64, reason: i64) -> ChargerState {
+        ChargerState::builder("EH000001")
+            .name("Garage")
+            .power(power)
+            .reason_for_no_current(reason)
+            .build()
+    }
+
+    fn offline() -> ChargerInfo {
+        ChargerInfo {
+            id: String::from("EH000001"),
+            name: Some(String::from("Garage")),
+        }
+    }
+
+    fn lines(points: Vec<WriteQuery>) -> Vec<String> {
+        points
+            .into_iter()
+            .map(|point| point.build().unwrap().get())
+            .collect()
+    }
+
+    fn line(title: &str, text: &str, key: &str) -> String {
+        format!(
+            r#"annotations title="Garage: {}",text="Garage {}",tags="easee,EH000001,{}" {}"#,
+            title, text, key, NANOS
+        )
+    }
+
+    #[test]
+    fn each_transition_is_a_point() {
+        let mut tracker = tracker();
+        assert!(tracker.observe(&[charger(0.0, 2)], &[], at(-60)).is_empty());
+
+        assert_eq!(
+            lines(tracker.observe(&[charger(7.0, 2)], &[], at(0))),
+            vec![line("session start", "started charging", "session_start")]
+        );
+        assert_eq!(
+            lines(tracker.observe(&[charger(0.0, 2)], &[], at(0))),
+            vec![line("session end", "stopped charging", "session_end")]
+        );
+        assert_eq!(
+            lines(tracker.observe(&[charger(0.0, 50)], &[], at(0))),
+            vec![line("reason", "reason for no current is now 50", "reason")]
+        );
+        assert_eq!(
+            lines(tracker.observe(&[], &[offline()], at(0))),
+            vec![line("offline", "went offline", "offline")]
+        );
+        // Offline once, not every tick it stays away
+        assert!(tracker.observe(&[], &[offline()], at(60)).is_empty());
+        assert_eq!(
+            lines(tracker.observe(&[charger(0.0, 50)], &[], at(0))),
+            vec![line("online", "is back online", "online")]
+        );
+    }
+
+    #[test]
+    fn nothing_is_marked_unless_enabled_and_seen_before() {
+        let mut tracker = tracker();
+        assert!(tracker.observe(&[], &[offline()], at(0)).is_empty());
+        assert!(tracker.observe(&[charger(7.0, 2)], &[], at(0)).is_empty());
+        tracker.forget("EH000001");
+        assert!(tracker.observe(&[charger(0.0, 2)], &[], at(0)).is_empty());
+
+        let mut disabled = AnnotationTracker::new(false, HashMap::new());
+        assert!(disabled.observe(&[charger(0.0, 2)], &[], at(0)).is_empty());
+        assert!(disabled.observe(&[charger(7.0, 2)], &[], at(0)).is_empty());
+    }
+
+    #[test]
+    fn templates_fill_in_the_id_and_code() {
+        let mut templates: HashMap<&'static str, String> = ALL
+            .iter()
+            .map(|t| (t.key(), t.template().1.to_string()))
+            .collect();
+        templates.insert("reason", String::from("{id} ({name}): code {code}"));
+        let mut tracker = AnnotationTracker::new(true, templates);
+        tracker.observe(&[charger(0.0, 2)], &[], at(0));
+
+        let lines = lines(tracker.observe(&[charger(0.0, 50)], &[], at(0)));
+        assert!(
+            lines[0].contains(r#"text="EH000001 (Garage): code 50""#),
+            "{}",
+            lines[0]
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use std::{collections::hash_map::RandomState, env, fmt::Debug, hash::BuildHasher, sync::OnceLock};
+
+/// Salt of the pseudonyms, random per process so they can't be traced back to an id.
+static SALT: OnceLock<Option<RandomState>> = OnceLock::new();
+
+fn salt() -> Option<&'static RandomState> {
+    SALT.get_or_init(|| {
+        let enabled = env::var("ANONYMIZE_IDS").is_ok_and(|a| a == "true");
+        tracing::info!("ANONYMIZE_IDS: {}", enabled);
+        enabled.then(RandomState::new)
+    })
+    .as_ref()
+}
+
+/// The charger id to put in logs, a pseudonym stable for the run when `ANONYMIZE_IDS` is set.
+///
+/// InfluxDB and the routes always use the real id.
+pub fn log_id(id: &str) -> String {
+    pseudonym(salt(), id)
+}
+
+fn pseudonym(salt: Option<&RandomState>, id: &str) -> String {
+    match salt {
+        Some(salt) => format!("charger-{:08x}", salt.hash_one(id) as u32),
+        None => id.to_string(),
+    }
+}
+
+/// Formats a value for logs with the charger id in it replaced by its pseudonym.
+pub fn log_debug<T: Debug>(value: &T, id: &str) -> String {
+    redact(salt(), value, id)
+}
+
+fn redact<T: Debug>(salt: Option<&RandomState>, value: &T, id: &str) -> String {
+    let formatted = format!("{:?}", value);
+    match salt {
+        Some(_) => formatted.replace(id, &pseudonym(salt, id)),
+        None => formatted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pseudonyms_are_stable_and_distinct() {
+        let salt = RandomState::new();
+        let first = pseudonym(Some(&salt), "EH000001");
+        assert_eq!(first, pseudonym(Some(&salt), "EH000001"));
+        assert_ne!(first, pseudonym(Some(&salt), "EH000002"));
+        assert!(first.starts_with("charger-"), "{}", first);
+        assert!(!first.contains("EH000001"));
+    }
+
+    #[test]
+    fn real_ids_dont_leak_into_formatted_values() {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct Event {
+            charger_id: &'static str,
+            url: &'static str,
+        }
+        let event = Event {
+            charger_id: "EH000001",
+            url: "/api/chargers/EH000001/state",
+        };
+        let salt = RandomState::new();
+        let formatted = redact(Some(&salt), &event, "EH000001");
+        assert!(!formatted.contains("EH000001"), "{}", formatted);
+        assert_eq!(
+            formatted
+                .matches(&pseudonym(Some(&salt), "EH000001"))
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn disabled_keeps_the_real_id() {
+        assert_eq!(pseudonym(None, "EH000001"), "EH000001");
+        assert_eq!(redact(None, &"EH000001", "EH000001"), "\"EH000001\"");
+    }
+}
@@ -0,0 +1,146 @@
+use hmac::{Hmac, Mac};
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome},
+    Request,
+};
+use sha2::Sha256;
+use tracing::debug;
+
+/// Header carrying the key on protected routes.
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Header that must be `yes` on routes acting on the chargers.
+pub const CONFIRM_HEADER: &str = "X-Confirm";
+
+/// The key protecting the debug and admin routes, from `api_key`. Without one they are closed.
+#[derive(Clone, Default)]
+pub struct ApiKeyConfig {
+    key: Option<String>,
+    /// Keys the hash telling keys apart in the audit log, from `api_key_salt`.
+    salt: Vec<u8>,
+}
+
+impl std::fmt::Debug for ApiKeyConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ApiKeyConfig")
+            .field("key", &self.key.is_some())
+            .field("salt", &!self.salt.is_empty())
+            .finish()
+    }
+}
+
+impl ApiKeyConfig {
+    pub fn new(key: Option<String>, salt: Vec<u8>) -> Self {
+        ApiKeyConfig { key, salt }
+    }
+
+    /// The first 16 hex digits of an HMAC-SHA256 of the key under the salt, to tell keys
+    /// apart in logs without revealing them.
+    pub fn key_id(&self, key: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.salt).expect("HMAC takes any key");
+        mac.update(key.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .take(8)
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn accepts(&self, given: &str) -> bool {
+        match self.key {
+            // Compare every byte so the time taken doesn't reveal how much matched
+            Some(ref key) => {
+                key.len() == given.len()
+                    && key
+                        .bytes()
+                        .zip(given.bytes())
+                        .fold(0, |diff, (a, b)| diff | (a ^ b))
+                        == 0
+            }
+            None => false,
+        }
+    }
+}
+
+/// Request guard admitting requests with the configured `X-Api-Key`.
+pub struct ApiKey {
+    /// A keyed hash of the key, see `ApiKeyConfig::key_id`.
+    pub key_id: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiKey {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = match req.rocket().state::<ApiKeyConfig>() {
+            Some(config) if config.key.is_some() => config,
+            _ => {
+                debug!("Protected route called without an API_KEY configured");
+                return Outcome::Error((Status::Forbidden, ()));
+            }
+        };
+        match req.headers().get_one(API_KEY_HEADER) {
+            Some(given) if config.accepts(given) => Outcome::Success(ApiKey {
+                key_id: config.key_id(given),
+            }),
+            _ => {
+                debug!("Rejected API key");
+                Outcome::Error((Status::Unauthorized, ()))
+            }
+        }
+    }
+}
+
+/// Request guard requiring `X-Confirm: yes`, so a stray request can't act on a charger.
+pub struct Confirmed;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Confirmed {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.headers().get_one(CONFIRM_HEADER) {
+            Some("yes") => Outcome::Success(Confirmed),
+            _ => {
+                debug!("Command without {}: yes", CONFIRM_HEADER);
+                Outcome::Error((Status::PreconditionRequired, ()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_ids_are_hmac_sha256_under_the_salt() {
+        // RFC 4231 test case 2, the salt as the HMAC key
+        let config = ApiKeyConfig::new(None, b"Jefe".to_vec());
+        assert_eq!(
+            config.key_id("what do ya want for nothing?"),
+            "5bdcc146bf60754e"
+        );
+    }
+
+    #[test]
+    fn key_ids_depend_on_key_and_salt() {
+        let config = ApiKeyConfig::new(None, b"salt".to_vec());
+        assert_eq!(config.key_id("key"), config.key_id("key"));
+        assert_ne!(config.key_id("key"), config.key_id("other key"));
+        let resalted = ApiKeyConfig::new(None, b"pepper".to_vec());
+        assert_ne!(config.key_id("key"), resalted.key_id("key"));
+    }
+
+    #[test]
+    fn only_the_configured_key_is_accepted() {
+        let config = ApiKeyConfig::new(Some(String::from("secret")), Vec::new());
+        assert!(config.accepts("secret"));
+        assert!(!config.accepts("secreT"));
+        assert!(!config.accepts("secret "));
+        assert!(!ApiKeyConfig::default().accepts(""));
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use futures::StreamExt;
+use influxdb::InfluxDbWriteable;
+use tokio::sync::Mutex;
+use tracing::{info, instrument, warn};
+
+use super::{
+    anonymize::log_id,
+    easee::{get_charger_listing, get_charging_sessions},
+    names::display_name,
+    run::DbTarget,
+    structs::{SessionState, Variable},
+};
+
+/// What `easee_status backfill` covers and how it paces itself.
+#[derive(Debug, Clone)]
+pub struct BackfillConfig {
+    pub from: DateTime<Utc>,
+    pub window: Duration,
+    /// Pause between windows, sparing the Easee rate limit.
+    pub pause: std::time::Duration,
+    /// Last completed window end per charger, so a rerun resumes after it.
+    pub state_file: PathBuf,
+}
+
+impl BackfillConfig {
+    #[instrument(level = "trace")]
+    pub fn from_env() -> Self {
+        let from = env::var("BACKFILL_FROM").expect("BACKFILL_FROM not set");
+        let from = NaiveDate::parse_from_str(&from, "%Y-%m-%d")
+            .expect("Illegal BACKFILL_FROM format, expected YYYY-MM-DD")
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let from = Utc.from_utc_datetime(&from);
+        tracing::info!("BACKFILL_FROM: {}", from);
+
+        let window_days: i64 = env::var("BACKFILL_WINDOW_DAYS")
+            .map_or(7, |d| d.parse().expect("Illegal backfill window format"));
+        assert!(window_days > 0, "BACKFILL_WINDOW_DAYS must be positive");
+        tracing::info!("BACKFILL_WINDOW_DAYS: {}", window_days);
+
+        let pause_ms: u64 = env::var("BACKFILL_PAUSE_MS")
+            .map_or(1000, |p| p.parse().expect("Illegal backfill pause format"));
+        tracing::info!("BACKFILL_PAUSE_MS: {}", pause_ms);
+
+        let state_file =
+            env::var("BACKFILL_STATE_FILE").unwrap_or_else(|_| String::from("./var/backfill.json"));
+        tracing::info!("BACKFILL_STATE_FILE: {}", state_file);
+
+        BackfillConfig {
+            from,
+            window: Duration::days(window_days),
+            pause: std::time::Duration::from_millis(pause_ms),
+            state_file: PathBuf::from(state_file),
+        }
+    }
+}
+
+/// The windows between `from` and `to`, oldest first, the last one cut short at `to`.
+pub fn windows(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    size: Duration,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut windows = Vec::new();
+    let mut start = from;
+    while start < to {
+        let end = std::cmp::min(start + size, to);
+        windows.push((start, end));
+        start = end;
+    }
+    windows
+}
+
+/// The windows left of a charger, starting after the last one completed. Windows don't move
+/// between runs as long as `from` and the size stay the same.
+pub fn remaining_windows(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    size: Duration,
+    completed: Option<DateTime<Utc>>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    windows(from, to, size)
+        .into_iter()
+        .filter(|(_, end)| completed.is_none_or(|completed| *end > completed))
+        .collect()
+}
+
+fn load_progress(path: &Path) -> HashMap<String, DateTime<Utc>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .unwrap_or_else(|e| panic!("Corrupt backfill state in {}: {}", path.display(), e)),
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// Replaces the state file in one rename, so an interruption can't leave half of it.
+async fn save_progress(
+    path: &Path,
+    progress: &HashMap<String, DateTime<Utc>>,
+) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let partial = path.with_extension("partial");
+    tokio::fs::write(&partial, serde_json::to_string(progress)?).await?;
+    tokio::fs::rename(partial, path).await
+}
+
+/// Writes the energy of every charging session since `BACKFILL_FROM` as `session_kwh`, at the
+/// time the car connected, one window at a time.
+///
+/// Returns whether every window of every charger was written.
+pub async fn backfill(
+    config: &BackfillConfig,
+    targets: &[DbTarget],
+    session: Arc<Mutex<SessionState>>,
+) -> bool {
+    let chargers = match get_charger_listing(session.clone()).await {
+        Ok(chargers) => chargers,
+        Err(e) => {
+            warn!("Listing the chargers failed: {}", e);
+            return false;
+        }
+    };
+    let mut progress = load_progress(&config.state_file);
+    let to = Utc::now();
+    let mut passed = true;
+    for charger in chargers {
+        let name = display_name(&charger.id, charger.name.as_deref());
+        let total = windows(config.from, to, config.window).len();
+        let remaining = remaining_windows(
+            config.from,
+            to,
+            config.window,
+            progress.get(&charger.id).copied(),
+        );
+        if remaining.is_empty() {
+            info!("Backfill of {} already complete", log_id(&charger.id));
+            continue;
+        }
+        let mut done = total - remaining.len();
+        for (start, end) in remaining {
+            let sessions: Vec<_> =
+                get_charging_sessions(charger.id.clone(), start, end, session.clone())
+                    .collect()
+                    .await;
+            let mut queries = Vec::new();
+            let mut failed = false;
+            for result in sessions {
+                match result {
+                    // Each session goes in the window it started in
+                    Ok(s) if s.car_connected >= start && s.car_connected < end => queries.push(
+                        Variable {
+                            time: s.car_connected,
+                            value: s.kwh,
+                            variable: String::from("session_kwh"),
+                        }
+                        .into_query(&charger.id)
+                        .add_tag("name", name.as_str()),
+                    ),
+                    Ok(_) => (),
+                    Err(e) => {
+                        warn!(
+                            "Reading the sessions of {} failed: {}",
+                            log_id(&charger.id),
+                            e
+                        );
+                        failed = true;
+                    }
+                }
+            }
+            if !failed && !queries.is_empty() {
+                for target in targets {
+                    if let Err(e) = target.write(queries.clone()).await {
+                        warn!("Writing to {} failed: {}", target.addr, e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                // The window is left unmarked, so the next run starts over from it
+                passed = false;
+                break;
+            }
+            progress.insert(charger.id.clone(), end);
+            if let Err(e) = save_progress(&config.state_file, &progress).await {
+                warn!(
+                    "Saving the backfill state to {} failed: {}",
+                    config.state_file.display(),
+                    e
+                );
+            }
+            done += 1;
+            info!(
+                "Backfill of {}: {} sessions up to {}, {}%",
+                log_id(&charger.id),
+                queries.len(),
+                end.format("%Y-%m-%d"),
+                done * 100 / total
+            );
+            tokio::time::sleep(config.pause).await;
+        }
+    }
+    passed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(d: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap() + Duration::days(d)
+    }
+
+    #[test]
+    fn windows_cover_the_range_and_cut_the_last_short() {
+        assert_eq!(
+            windows(day(0), day(17), Duration::days(7)),
+            vec![(day(0), day(7)), (day(7), day(14)), (day(14), day(17))]
+        );
+        assert_eq!(
+            windows(day(0), day(14), Duration::days(7)),
+            vec![(day(0), day(7)), (day(7), day(14))]
+        );
+        assert!(windows(day(3), day(3), Duration::days(7)).is_empty());
+    }
+
+    #[test]
+    fn resumes_after_the_last_completed_window() {
+        let all = windows(day(0), day(30), Duration::days(7));
+        assert_eq!(
+            remaining_windows(day(0), day(30), Duration::days(7), None),
+            all
+        );
+        // Interrupted after two windows
+        assert_eq!(
+            remaining_windows(day(0), day(30), Duration::days(7), Some(day(14))),
+            all[2..]
+        );
+        assert!(remaining_windows(day(0), day(30), Duration::days(7), Some(day(30))).is_empty());
+    }
+
+    #[test]
+    fn rerun_later_only_adds_the_new_days() {
+        // Completed up to the end of the last run, which was cut short at day 17
+        assert_eq!(
+            remaining_windows(day(0), day(25), Duration::days(7), Some(day(17))),
+            vec![(day(14), day(21)), (day(21), day(25))]
+        );
+    }
+
+    #[tokio::test]
+    async fn progress_survives_a_restart() {
+        let path = env::temp_dir().join(format!("backfill-test-{}.json", std::process::id()));
+        assert!(load_progress(&path).is_empty());
+
+        let mut progress = HashMap::new();
+        progress.insert(String::from("EH000001"), day(14));
+        save_progress(&path, &progress).await.unwrap();
+        let loaded = load_progress(&path);
+        assert_eq!(loaded, progress);
+        assert!(!path.with_extension("partial").exists());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
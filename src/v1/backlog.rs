@@ -0,0 +1,160 @@
+use std::{
+    env,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        OnceLock,
+    },
+};
+
+use serde::Serialize;
+use tracing::{error, warn};
+
+/// Tick tasks still running, so slow ticks piling up show before they run out of memory.
+#[derive(Debug)]
+struct Backlog {
+    warn_at: usize,
+    max: usize,
+    live: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BacklogReport {
+    pub live: usize,
+    pub max: usize,
+    pub dropped: u64,
+}
+
+static BACKLOG: OnceLock<Backlog> = OnceLock::new();
+
+fn backlog() -> &'static Backlog {
+    BACKLOG.get_or_init(|| {
+        let warn_at = env::var("TICK_BACKLOG_WARN").map_or(3, |w| {
+            w.parse().expect("Illegal tick backlog warning format")
+        });
+        tracing::info!("TICK_BACKLOG_WARN: {}", warn_at);
+
+        let max = env::var("TICK_BACKLOG_MAX")
+            .map_or(10, |m| m.parse().expect("Illegal tick backlog format"));
+        tracing::info!("TICK_BACKLOG_MAX: {}", max);
+
+        Backlog {
+            warn_at,
+            max,
+            live: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    })
+}
+
+/// Decrements the live count when the task ends, also if it panics or is cancelled.
+struct LiveTask(&'static Backlog);
+
+impl Drop for LiveTask {
+    fn drop(&mut self) {
+        self.0.live.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Spawns a tick unless `TICK_BACKLOG_MAX` ticks are still running, returning whether it did.
+pub fn spawn_tick<F>(name: &str, tick: F) -> bool
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    backlog().spawn(name, tick)
+}
+
+impl Backlog {
+    fn spawn<F>(&'static self, name: &str, tick: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let live = self.live.fetch_add(1, Ordering::SeqCst) + 1;
+        if live > self.max {
+            self.live.fetch_sub(1, Ordering::SeqCst);
+            let dropped = self.dropped.fetch_add(1, Ordering::SeqCst) + 1;
+            error!(
+                "Dropping {}, {} ticks are still running ({} dropped so far)",
+                name, self.max, dropped
+            );
+            return false;
+        }
+        if live > self.warn_at {
+            warn!("{} ticks are running at once", live);
+        }
+        let guard = LiveTask(self);
+        tokio::spawn(async move {
+            let _guard = guard;
+            tick.await;
+        });
+        true
+    }
+
+    fn report(&self) -> BacklogReport {
+        BacklogReport {
+            live: self.live.load(Ordering::SeqCst),
+            max: self.max,
+            dropped: self.dropped.load(Ordering::SeqCst),
+        }
+    }
+}
+
+pub fn report() -> BacklogReport {
+    backlog().report()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::Semaphore;
+
+    use super::*;
+
+    fn backlog(max: usize) -> &'static Backlog {
+        Box::leak(Box::new(Backlog {
+            warn_at: 1,
+            max,
+            live: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+        }))
+    }
+
+    #[tokio::test]
+    async fn blocked_ticks_fill_the_backlog_and_drops_are_counted() {
+        let backlog = backlog(3);
+        let release = std::sync::Arc::new(Semaphore::new(0));
+        for i in 0..5 {
+            let release = release.clone();
+            let spawned = backlog.spawn("tick", async move {
+                let _ = release.acquire().await;
+            });
+            assert_eq!(spawned, i < 3, "tick {}", i);
+        }
+        let report = backlog.report();
+        assert_eq!((report.live, report.max, report.dropped), (3, 3, 2));
+
+        release.close();
+        for _ in 0..100 {
+            if backlog.report().live == 0 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        let report = backlog.report();
+        assert_eq!((report.live, report.dropped), (0, 2));
+        assert!(backlog.spawn("tick", async {}));
+    }
+
+    #[tokio::test]
+    async fn panicking_ticks_leave_the_backlog() {
+        let backlog = backlog(1);
+        assert!(backlog.spawn("tick", async { panic!("tick failed") }));
+        for _ in 0..100 {
+            if backlog.report().live == 0 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(backlog.report().live, 0);
+    }
+}
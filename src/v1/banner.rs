@@ -0,0 +1,154 @@
+use std::env;
+
+use super::{
+    credentials,
+    easee::easee_base,
+    run::{get_interval, try_get_db_info, DbTarget},
+    structs::Mode,
+};
+
+/// Features this binary was built with.
+fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "blocking") {
+        features.push("blocking");
+    }
+    if cfg!(feature = "docs") {
+        features.push("docs");
+    }
+    if cfg!(feature = "keyring") {
+        features.push("keyring");
+    }
+    if cfg!(feature = "spotprice") {
+        features.push("spotprice");
+    }
+    features
+}
+
+/// How the targets show in the banner, with their auth mode but never the password.
+fn describe_targets(targets: &[DbTarget]) -> Vec<String> {
+    targets
+        .iter()
+        .map(|target| {
+            format!(
+                "{} ({}, auth: {})",
+                target.addr,
+                target.name,
+                target.auth_mode()
+            )
+        })
+        .collect()
+}
+
+/// The configuration that took effect, as the banner shows it.
+struct Banner {
+    mode: Mode,
+    interval_secs: i64,
+    /// Where the credentials are read from, never the credentials themselves.
+    credentials: String,
+    easee: String,
+    influxdb: Vec<String>,
+    log_dir: String,
+    log_level: String,
+}
+
+impl Banner {
+    fn gather(mode: Mode) -> Self {
+        Banner {
+            mode,
+            interval_secs: get_interval().num_seconds(),
+            credentials: credentials::source().to_string(),
+            easee: easee_base(),
+            influxdb: describe_targets(&try_get_db_info().unwrap_or_default()),
+            log_dir: env::var("LOG_DIR").unwrap_or_else(|_| String::from("./var/log")),
+            log_level: env::var("LOG_LEVEL").unwrap_or_else(|_| String::from("info")),
+        }
+    }
+
+    fn log(&self) {
+        let mode = self.mode;
+        tracing::info!(
+            version = env!("CARGO_PKG_VERSION"),
+            mode = ?mode,
+            interval_secs = self.interval_secs,
+            polling = matches!(mode, Mode::Poller | Mode::Both),
+            serving = matches!(mode, Mode::Server | Mode::Both),
+            cache = if mode == Mode::Server { "refreshed on request after the interval" } else { "fed by the poller" },
+            credentials = %self.credentials,
+            easee = %self.easee,
+            influxdb = ?self.influxdb,
+            log_dir = %self.log_dir,
+            log_level = %self.log_level,
+            features = ?features(),
+            "Starting easee_status"
+        );
+    }
+}
+
+/// Logs the configuration that took effect, once, at startup.
+pub fn log_startup_banner(mode: Mode) {
+    Banner::gather(mode).log();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tracing::Level;
+
+    use super::*;
+    use crate::v1::{credentials::CredentialsSource, logging::build_logger};
+
+    #[test]
+    fn the_banner_shows_the_config_but_no_secrets() {
+        let dir = env::temp_dir().join(format!("banner-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let banner = Banner {
+            mode: Mode::Both,
+            interval_secs: 60,
+            credentials: CredentialsSource::File(Some(String::from("/run/secrets/easee")))
+                .to_string(),
+            easee: String::from("https://api.easee.example"),
+            influxdb: describe_targets(&[
+                DbTarget::new(
+                    String::from("http://influx:8086"),
+                    String::from("easee"),
+                    Some((String::from("writer"), String::from("influx-secret"))),
+                ),
+                DbTarget::new(
+                    String::from("http://backup:8086"),
+                    String::from("easee"),
+                    None,
+                ),
+            ]),
+            log_dir: dir.to_string_lossy().to_string(),
+            log_level: String::from("info"),
+        };
+
+        let (subscriber, guards) = build_logger(dir.to_str().unwrap(), Level::INFO);
+        tracing::subscriber::with_default(subscriber, || banner.log());
+        drop(guards);
+
+        let mut logs = String::new();
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.to_string_lossy().contains("easee-status-server") {
+                logs.push_str(&fs::read_to_string(path).unwrap());
+            }
+        }
+        for shown in [
+            "Starting easee_status",
+            env!("CARGO_PKG_VERSION"),
+            "interval_secs=60",
+            "polling=true",
+            "serving=true",
+            "file /run/secrets/easee",
+            "https://api.easee.example",
+            "http://influx:8086 (easee, auth: username and password)",
+            "http://backup:8086 (easee, auth: none)",
+        ] {
+            assert!(logs.contains(shown), "{} not in {}", shown, logs);
+        }
+        assert!(!logs.contains("influx-secret"), "{}", logs);
+    }
+}
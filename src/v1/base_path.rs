@@ -0,0 +1,121 @@
+use rocket::{
+    request::{FromRequest, Outcome},
+    Request,
+};
+
+/// Header a reverse proxy serving the routes under a prefix can announce it with.
+pub const FORWARDED_PREFIX_HEADER: &str = "X-Forwarded-Prefix";
+
+/// The path the routes are served under externally, from `public_base_path`.
+#[derive(Debug, Clone, Default)]
+pub struct PublicBasePath {
+    path: Option<String>,
+}
+
+impl PublicBasePath {
+    pub fn new(path: Option<String>) -> Self {
+        PublicBasePath {
+            path: path.and_then(|p| normalize(&p)),
+        }
+    }
+}
+
+/// `/prefix` without a trailing slash, `None` for the root. Anything that isn't a plain path
+/// is ignored, so a forwarded header can't turn a redirect into one to another host.
+fn normalize(prefix: &str) -> Option<String> {
+    let prefix = prefix.trim().trim_end_matches('/');
+    if prefix.is_empty()
+        || !prefix.starts_with('/')
+        || prefix.starts_with("//")
+        || prefix.contains(['\\', '?', '#', ':'])
+    {
+        return None;
+    }
+    Some(prefix.to_string())
+}
+
+/// Request guard giving the prefix to put before the absolute paths of redirects and links,
+/// `public_base_path` if set, else `X-Forwarded-Prefix`.
+#[derive(Debug, Clone, Default)]
+pub struct BasePath(Option<String>);
+
+impl BasePath {
+    /// The path as seen from outside the proxy.
+    pub fn join(&self, path: &str) -> String {
+        match self.0 {
+            Some(ref prefix) => format!("{}{}", prefix, path),
+            None => path.to_string(),
+        }
+    }
+
+    /// The prefix, `/` when there is none.
+    pub fn root(&self) -> &str {
+        self.0.as_deref().unwrap_or("/")
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BasePath {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let configured = req
+            .rocket()
+            .state::<PublicBasePath>()
+            .and_then(|base| base.path.clone());
+        let prefix = configured.or_else(|| {
+            req.headers()
+                .get_one(FORWARDED_PREFIX_HEADER)
+                .and_then(normalize)
+        });
+        Outcome::Success(BasePath(prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_plain_paths() {
+        let cases = [
+            ("/status", Some("/status")),
+            ("/status/", Some("/status")),
+            ("  /status//  ", Some("/status")),
+            ("/a/b/c", Some("/a/b/c")),
+            ("/", None),
+            ("", None),
+            ("   ", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(normalize(input).as_deref(), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn refuses_anything_that_could_leave_the_host() {
+        for input in [
+            "status",
+            "//evil.example",
+            "//evil.example/status",
+            "/\\evil.example",
+            "/status?x=1",
+            "/status#top",
+            "https://evil.example",
+            "/a:b",
+        ] {
+            assert_eq!(normalize(input), None, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn joins_paths_under_the_prefix() {
+        let base = BasePath(normalize("/status/"));
+        assert_eq!(base.join("/docs"), "/status/docs");
+        assert_eq!(base.root(), "/status");
+
+        let root = BasePath::default();
+        assert_eq!(root.join("/docs"), "/docs");
+        assert_eq!(root.root(), "/");
+    }
+}
@@ -0,0 +1,41 @@
+//! Synchronous wrappers around the Easee client, for callers without a Tokio runtime.
+//!
+//! Each call runs on its own current-thread runtime, so these must not be used from async code:
+//! called from within a Tokio runtime, they panic.
+
+use std::{future::Future, sync::Arc};
+
+use tokio::{runtime::Handle, sync::Mutex};
+
+use super::{
+    easee::{external_request_charger_state, get_charger_list, get_charger_state},
+    structs::{ChargerState, EaseeError, SessionState},
+};
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    if Handle::try_current().is_ok() {
+        panic!("easee_status::blocking can't be used from within an async runtime, use the async functions instead");
+    }
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build blocking runtime")
+        .block_on(future)
+}
+
+pub fn charger_ids(session: Arc<Mutex<SessionState>>) -> Result<Vec<String>, EaseeError> {
+    block_on(get_charger_list(session))
+}
+
+pub fn charger_state(
+    charger_id: &str,
+    session: Arc<Mutex<SessionState>>,
+) -> Result<ChargerState, EaseeError> {
+    block_on(external_request_charger_state(charger_id, session))
+}
+
+pub fn all_charger_states(
+    session: Arc<Mutex<SessionState>>,
+) -> Result<Vec<ChargerState>, EaseeError> {
+    block_on(get_charger_state(session))
+}
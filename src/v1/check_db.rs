@@ -0,0 +1,176 @@
+use std::time::Instant;
+
+use chrono::Utc;
+use influxdb::{Client, InfluxDbWriteable, ReadQuery};
+
+use super::{report::parse_single_value, run::DbTarget, structs::Variable};
+
+/// Measurement the synthetic point is written to and deleted from.
+pub const SELFTEST_MEASUREMENT: &str = "selftest";
+
+/// Writes, reads back and deletes a point on every target, printing a report.
+///
+/// Returns whether every target passed.
+pub async fn check_db(targets: &[DbTarget]) -> bool {
+    let mut passed = true;
+    for target in targets {
+        println!("InfluxDB {}", target.addr);
+        println!("  database: {}", target.name);
+        println!("  auth:     {}", target.auth_mode());
+        match check_target(target.client()).await {
+            Ok(()) => println!("  result:   ok"),
+            Err(e) => {
+                println!("  result:   FAILED, {}", e);
+                passed = false;
+            }
+        }
+    }
+    passed
+}
+
+async fn check_target(client: &Client) -> Result<(), String> {
+    let (build, version) = client
+        .ping()
+        .await
+        .map_err(|e| format!("ping failed: {}", e))?;
+    println!("  server:   {} {}", build, version);
+
+    let value = Utc::now().timestamp_subsec_nanos() as f64;
+    let point = Variable {
+        time: Utc::now(),
+        value,
+        variable: String::from("selftest"),
+    };
+    let started = Instant::now();
+    client
+        .query(point.into_query(SELFTEST_MEASUREMENT))
+        .await
+        .map_err(|e| format!("write failed: {}", e))?;
+    println!("  write:    {} ms", started.elapsed().as_millis());
+
+    let read = client
+        .query(ReadQuery::new(format!(
+            "SELECT LAST(\"value\") FROM \"{}\"",
+            SELFTEST_MEASUREMENT
+        )))
+        .await
+        .map_err(|e| format!("read failed: {}", e))?;
+    match parse_single_value(&read) {
+        Some(v) if v == value => println!("  read:     ok"),
+        other => return Err(format!("read back {:?}, wrote {}", other, value)),
+    }
+
+    match client
+        .query(ReadQuery::new(format!(
+            "DROP MEASUREMENT \"{}\"",
+            SELFTEST_MEASUREMENT
+        )))
+        .await
+    {
+        Ok(_) => println!("  cleanup:  deleted"),
+        Err(e) => println!(
+            "  cleanup:  left in {} as delete failed: {}",
+            SELFTEST_MEASUREMENT, e
+        ),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, Request, ResponseTemplate,
+    };
+
+    use super::*;
+
+    /// An InfluxDB answering the ping and the drop, reading back `read(written)`.
+    async fn influx(read: fn(&str) -> String) -> MockServer {
+        let influx = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ping"))
+            .respond_with(
+                ResponseTemplate::new(204)
+                    .insert_header("X-Influxdb-Build", "OSS")
+                    .insert_header("X-Influxdb-Version", "1.8.10"),
+            )
+            .mount(&influx)
+            .await;
+        let written = Arc::new(Mutex::new(String::new()));
+        let write = written.clone();
+        Mock::given(method("POST"))
+            .and(path("/write"))
+            .respond_with(move |request: &Request| {
+                let body = String::from_utf8_lossy(&request.body).to_string();
+                let value = body
+                    .split("value=")
+                    .nth(1)
+                    .and_then(|rest| rest.split([' ', ',']).next())
+                    .unwrap_or_default()
+                    .to_string();
+                *write.lock().unwrap() = value;
+                ResponseTemplate::new(204)
+            })
+            .mount(&influx)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/query"))
+            .respond_with(move |_: &Request| {
+                let value = read(&written.lock().unwrap());
+                ResponseTemplate::new(200).set_body_string(format!(
+                    r#"{{"results":[{{"statement_id":0,"series":[{{"name":"selftest","columns":["time","last"],"values":[[0,{}]]}}]}}]}}"#,
+                    value
+                ))
+            })
+            .mount(&influx)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/query"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"{"results":[{"statement_id":0}]}"#),
+            )
+            .mount(&influx)
+            .await;
+        influx
+    }
+
+    fn drops(requests: &[Request]) -> usize {
+        requests
+            .iter()
+            .filter(|r| r.url.path() == "/query")
+            .filter_map(|r| r.url.query_pairs().find(|(k, _)| k == "q"))
+            .filter(|(_, q)| q.starts_with("DROP MEASUREMENT"))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn the_point_is_written_read_back_and_deleted() {
+        let influx = influx(str::to_string).await;
+        let target = DbTarget::new(influx.uri(), String::from("easee"), None);
+
+        assert!(check_db(&[target]).await);
+        let requests = influx.received_requests().await.unwrap();
+        let write = requests.iter().find(|r| r.url.path() == "/write").unwrap();
+        assert!(String::from_utf8_lossy(&write.body).starts_with(SELFTEST_MEASUREMENT));
+        assert_eq!(drops(&requests), 1);
+    }
+
+    #[tokio::test]
+    async fn a_wrong_read_or_an_unreachable_target_fails() {
+        let influx = influx(|_| String::from("-1")).await;
+        let target = DbTarget::new(influx.uri(), String::from("easee"), None);
+        assert!(!check_db(&[target]).await);
+        // Nothing to clean up when the read failed
+        assert_eq!(drops(&influx.received_requests().await.unwrap()), 0);
+
+        let unreachable = DbTarget::new(
+            String::from("http://127.0.0.1:1"),
+            String::from("easee"),
+            None,
+        );
+        assert!(!check_db(&[unreachable]).await);
+    }
+}
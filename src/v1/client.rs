@@ -0,0 +1,63 @@
+use std::{future::Future, sync::Arc};
+
+use chrono::{DateTime, Local};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    easee::{
+        clear_session, external_request_charger_state, force_login, get_charger_listing,
+        get_charger_states, keep_session_fresh, ChargerResult,
+    },
+    structs::{ChargerInfo, ChargerState, EaseeError, SessionState},
+};
+
+/// An Easee account, logged in on the first request. Clones share the session.
+#[derive(Debug, Clone, Default)]
+pub struct EaseeClient {
+    session: Arc<Mutex<SessionState>>,
+}
+
+impl EaseeClient {
+    pub fn new() -> Self {
+        EaseeClient::default()
+    }
+
+    /// A client on a session shared with the poller or the server.
+    pub fn with_session(session: Arc<Mutex<SessionState>>) -> Self {
+        EaseeClient { session }
+    }
+
+    pub fn session(&self) -> Arc<Mutex<SessionState>> {
+        self.session.clone()
+    }
+
+    /// The chargers of the account, in the order the index routes count them in.
+    pub async fn chargers(&self) -> Result<Vec<ChargerInfo>, EaseeError> {
+        get_charger_listing(self.session()).await
+    }
+
+    /// The state of every charger, failing only when the chargers can't be listed.
+    pub async fn charger_states(&self) -> Result<Vec<ChargerResult>, EaseeError> {
+        get_charger_states(self.session()).await
+    }
+
+    pub async fn charger_state(&self, charger_id: &str) -> Result<ChargerState, EaseeError> {
+        external_request_charger_state(charger_id, self.session()).await
+    }
+
+    /// Logs in from scratch, keeping the current session if that fails.
+    pub async fn login(&self) -> Result<Option<DateTime<Local>>, EaseeError> {
+        force_login(self.session()).await
+    }
+
+    /// Forgets the session, so the next request logs in again.
+    pub async fn logout(&self) {
+        clear_session(self.session()).await
+    }
+
+    /// Refreshes the token ahead of its expiry until `shutdown`, to be spawned.
+    pub fn keep_fresh(&self, shutdown: CancellationToken) -> impl Future<Output = ()> {
+        keep_session_fresh(self.session(), shutdown)
+    }
+}
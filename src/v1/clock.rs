@@ -0,0 +1,94 @@
+use std::{sync::Arc, time::Instant};
+
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{instrument, trace, warn};
+
+use super::{easee::expire_session, structs::SessionState};
+
+/// How often the wall clock is compared to the monotonic one.
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Wall clock steps smaller than this are drift, not a jump.
+const JUMP_THRESHOLD_SECS: i64 = 30;
+
+/// Notices the wall clock being stepped, e.g. by NTP after boot, by comparing how far it
+/// moved with how far the monotonic clock moved.
+#[derive(Debug, Default)]
+pub struct ClockWatch {
+    last: Option<(Instant, DateTime<Utc>)>,
+}
+
+impl ClockWatch {
+    /// Returns how far the wall clock jumped since the last call, if it did.
+    pub fn observe(&mut self, monotonic: Instant, wall: DateTime<Utc>) -> Option<Duration> {
+        let jump = self.last.and_then(|(last_monotonic, last_wall)| {
+            let elapsed = Duration::from_std(monotonic - last_monotonic).ok()?;
+            let jump = (wall - last_wall) - elapsed;
+            (jump.num_seconds().abs() >= JUMP_THRESHOLD_SECS).then_some(jump)
+        });
+        self.last = Some((monotonic, wall));
+        jump
+    }
+}
+
+/// Checks the wall clock every minute. A jump makes the token expiry, which is wall clock
+/// time, untrustworthy, so the token is refreshed on the next request.
+#[instrument(skip_all, level = "trace")]
+pub async fn watch_clock(session: Arc<Mutex<SessionState>>, shutdown: CancellationToken) {
+    let mut watch = ClockWatch::default();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown.cancelled() => return,
+        }
+        trace!("Checking the wall clock");
+        if let Some(jump) = watch.observe(Instant::now(), Utc::now()) {
+            warn!(
+                "Wall clock jumped {}s, re-checking the token",
+                jump.num_seconds()
+            );
+            expire_session(&session).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wall(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn steady_clocks_dont_jump() {
+        let start = Instant::now();
+        let mut watch = ClockWatch::default();
+        assert_eq!(watch.observe(start, wall(0)), None);
+        let minute = std::time::Duration::from_secs(60);
+        assert_eq!(watch.observe(start + minute, wall(60)), None);
+        // Drift below the threshold
+        assert_eq!(watch.observe(start + minute * 2, wall(149)), None);
+    }
+
+    #[test]
+    fn notices_jumps_both_ways() {
+        let start = Instant::now();
+        let minute = std::time::Duration::from_secs(60);
+        let mut watch = ClockWatch::default();
+        watch.observe(start, wall(0));
+        assert_eq!(
+            watch.observe(start + minute, wall(60 + 3600)),
+            Some(Duration::hours(1))
+        );
+        assert_eq!(
+            watch.observe(start + minute * 2, wall(120 + 3600 - 30)),
+            Some(Duration::seconds(-30))
+        );
+        // Compared with the last observation, not the first
+        assert_eq!(watch.observe(start + minute * 3, wall(180 + 3570)), None);
+    }
+}
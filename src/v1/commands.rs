@@ -0,0 +1,197 @@
+use std::{collections::VecDeque, env, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use influxdb::InfluxDbWriteable;
+use rocket::{get, serde::json::Json, State};
+use serde::Serialize;
+use tracing::{info, instrument, warn};
+
+use super::{anonymize::log_id, api_key::ApiKey, easee::AUTH_TARGET, run::DbTarget};
+
+/// Measurement each command sent to a charger is written to.
+pub const COMMANDS_MEASUREMENT: &str = "commands";
+
+/// A command sent to a charger through the admin routes.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandRecord {
+    pub at: DateTime<Utc>,
+    /// Identifies the API key used without revealing it.
+    pub key_id: String,
+    pub command: &'static str,
+    pub charger_id: String,
+    /// What Easee answered, `None` when the request failed before it did.
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, InfluxDbWriteable)]
+struct CommandPoint {
+    time: DateTime<Utc>,
+    status: i64,
+    key_id: String,
+    #[influxdb(tag)]
+    command: String,
+}
+
+/// The latest commands, at most `COMMAND_HISTORY_SIZE`, oldest dropped first.
+#[derive(Debug)]
+pub struct CommandHistory {
+    capacity: usize,
+    records: Mutex<VecDeque<CommandRecord>>,
+}
+
+impl CommandHistory {
+    pub fn new(capacity: usize) -> Self {
+        CommandHistory {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    #[instrument(level = "trace")]
+    pub fn from_env() -> Result<Self, String> {
+        let capacity = match env::var("COMMAND_HISTORY_SIZE") {
+            Ok(c) => c
+                .parse()
+                .map_err(|_| format!("Illegal command history size format: {}", c))?,
+            Err(_) => 100,
+        };
+        tracing::info!("COMMAND_HISTORY_SIZE: {}", capacity);
+        Ok(CommandHistory::new(capacity))
+    }
+
+    /// Keeps the command, logs it to the audit log and writes it to InfluxDB when there is one.
+    pub async fn record(&self, record: CommandRecord, influx: Option<&DbTarget>) {
+        info!(
+            target: AUTH_TARGET,
+            key_id = %record.key_id,
+            command = record.command,
+            charger_id = %log_id(&record.charger_id),
+            status = ?record.status,
+            "Command sent"
+        );
+        if let Some(target) = influx {
+            let query = CommandPoint {
+                time: record.at,
+                status: record.status.map_or(-1, i64::from),
+                key_id: record.key_id.clone(),
+                command: record.command.to_string(),
+            }
+            .into_query(COMMANDS_MEASUREMENT)
+            .add_tag("charger_id", record.charger_id.as_str());
+            if let Err(e) = target.write(query).await {
+                warn!("Writing command to InfluxDB failed: {}", e);
+            }
+        }
+        let mut records = self.records.lock().unwrap();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        if self.capacity > 0 {
+            records.push_back(record);
+        }
+    }
+
+    /// The latest commands, newest first.
+    pub fn latest(&self, limit: usize) -> Vec<CommandRecord> {
+        let records = self.records.lock().unwrap();
+        records.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+/// The latest commands sent through the admin routes, newest first.
+#[utoipa::path(
+    get,
+    path = "/commands/history",
+    params(
+        ("limit" = Option<usize>, Query, description = "Most commands to return, all kept by default"),
+        ("X-Api-Key" = String, Header, description = "The API_KEY"),
+    ),
+    responses(
+        (status = 200, description = "The commands, newest first"),
+        (status = 401, description = "Missing or wrong X-Api-Key"),
+        (status = 403, description = "No API_KEY configured, the route is closed"),
+    )
+)]
+#[get("/commands/history?<limit>")]
+pub fn command_history(
+    _key: ApiKey,
+    limit: Option<usize>,
+    history: &State<CommandHistory>,
+) -> Json<Vec<CommandRecord>> {
+    Json(history.latest(limit.unwrap_or(usize::MAX)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::v1::{api_key::ApiKeyConfig, sink::MemorySink};
+
+    fn record(charger_id: &str, status: Option<u16>) -> CommandRecord {
+        CommandRecord {
+            at: Utc::now(),
+            key_id: ApiKeyConfig::new(Some(String::from("key")), b"salt".to_vec()).key_id("key"),
+            command: "charge_now",
+            charger_id: charger_id.to_string(),
+            status,
+            error: status.is_none().then(|| String::from("Http failed")),
+        }
+    }
+
+    fn ids(records: &[CommandRecord]) -> Vec<&str> {
+        records.iter().map(|r| r.charger_id.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn the_oldest_commands_are_dropped_first() {
+        let history = CommandHistory::new(2);
+        for id in ["EH000001", "EH000002", "EH000003"] {
+            history.record(record(id, Some(202)), None).await;
+        }
+        assert_eq!(ids(&history.latest(10)), vec!["EH000003", "EH000002"]);
+        assert_eq!(ids(&history.latest(1)), vec!["EH000003"]);
+
+        let none = CommandHistory::new(0);
+        none.record(record("EH000001", Some(202)), None).await;
+        assert!(none.latest(10).is_empty());
+    }
+
+    #[tokio::test]
+    async fn commands_are_written_with_their_status() {
+        let sink = Arc::new(MemorySink::new());
+        let target = DbTarget::with_sink(
+            String::from("http://influx.invalid"),
+            String::from("easee"),
+            sink.clone(),
+        );
+        let history = CommandHistory::new(10);
+        history
+            .record(record("EH000001", Some(409)), Some(&target))
+            .await;
+        history
+            .record(record("EH000002", None), Some(&target))
+            .await;
+
+        let lines = sink.measurement(COMMANDS_MEASUREMENT);
+        assert_eq!(lines.len(), 2);
+        for part in ["charger_id=EH000001", "command=charge_now", "status=409i"] {
+            assert!(lines[0].contains(part), "{} in {}", part, lines[0]);
+        }
+        assert!(lines[1].contains("status=-1i"), "{}", lines[1]);
+    }
+
+    #[test]
+    fn records_serialize_the_key_id_only() {
+        let json = serde_json::to_value(record("EH000001", None)).unwrap();
+        assert_eq!(json["command"], "charge_now");
+        assert_eq!(json["charger_id"], "EH000001");
+        assert_eq!(json["status"], serde_json::Value::Null);
+        assert_eq!(json["error"], "Http failed");
+        let key_id = json["key_id"].as_str().unwrap();
+        assert_eq!(key_id.len(), 16);
+        assert_ne!(key_id, "key");
+        assert!(json["at"].is_string());
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::Arc;
+
+use super::{
+    run::{get_db_info, get_mode, DbTarget},
+    structs::Mode,
+};
+
+/// What the binary runs and where it writes to.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Config {
+    pub mode: Mode,
+    /// The databases written to, `None` in `MODE=server`.
+    pub targets: Option<Arc<Vec<DbTarget>>>,
+}
+
+impl Config {
+    /// Reads `MODE` and, when polling, the database settings, so missing variables stop
+    /// the process before anything is started.
+    pub fn from_env() -> Self {
+        let mode = get_mode();
+        let targets = match mode {
+            Mode::Poller | Mode::Both => Some(get_db_info(&format!("MODE={}", mode.as_str()))),
+            Mode::Server => None,
+        };
+        Config { mode, targets }
+    }
+}
@@ -0,0 +1,122 @@
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    Request, Response,
+};
+
+/// Lets browsers on the configured origins read the responses, from `cors_origins`.
+///
+/// Only the simple requests the routes answer are covered, there are no preflight routes.
+#[derive(Debug, Clone, Default)]
+pub struct Cors {
+    origins: Vec<String>,
+}
+
+impl Cors {
+    /// Parses comma separated origins such as `https://grafana.example`, or `*` for any.
+    /// Fails on anything else, so a typo fails at startup rather than in the browser.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut origins = Vec::new();
+        for origin in s.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            let origin = origin.trim_end_matches('/');
+            let is_origin = origin
+                .strip_prefix("https://")
+                .or_else(|| origin.strip_prefix("http://"))
+                .is_some_and(|host| !host.is_empty() && !host.contains('/'));
+            if origin != "*" && !is_origin {
+                return Err(format!("{} is not a scheme://host[:port] origin", origin));
+            }
+            origins.push(origin.to_string());
+        }
+        Ok(Cors { origins })
+    }
+
+    pub fn allows(&self, origin: &str) -> bool {
+        self.origins.iter().any(|o| o == "*" || o == origin)
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(origin) = request.headers().get_one("Origin") else {
+            return;
+        };
+        if self.allows(origin) {
+            response.set_header(Header::new(
+                "Access-Control-Allow-Origin",
+                origin.to_string(),
+            ));
+            response.adjoin_header(Header::new("Vary", "Origin"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{get, local::asynchronous::Client, routes};
+
+    use super::*;
+
+    #[test]
+    fn origins_are_validated() {
+        let cors = Cors::parse(" https://grafana.example/, http://localhost:3000,").unwrap();
+        assert!(cors.allows("https://grafana.example"));
+        assert!(cors.allows("http://localhost:3000"));
+        assert!(!cors.allows("https://evil.example"));
+        assert!(Cors::parse("*").unwrap().allows("https://evil.example"));
+        assert!(!Cors::default().allows("https://grafana.example"));
+        for bad in [
+            "grafana.example",
+            "https://",
+            "https://grafana.example/path",
+        ] {
+            assert!(Cors::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[get("/")]
+    fn index() -> &'static str {
+        "ok"
+    }
+
+    #[rocket::async_test]
+    async fn allowed_origins_get_the_header() {
+        let cors = Cors::parse("https://grafana.example").unwrap();
+        let rocket = rocket::build().mount("/", routes![index]).attach(cors);
+        let client = Client::untracked(rocket).await.unwrap();
+
+        let allowed = client
+            .get("/")
+            .header(Header::new("Origin", "https://grafana.example"))
+            .dispatch()
+            .await;
+        assert_eq!(
+            allowed.headers().get_one("Access-Control-Allow-Origin"),
+            Some("https://grafana.example")
+        );
+        assert_eq!(allowed.headers().get_one("Vary"), Some("Origin"));
+
+        let other = client
+            .get("/")
+            .header(Header::new("Origin", "https://evil.example"))
+            .dispatch()
+            .await;
+        assert!(other
+            .headers()
+            .get_one("Access-Control-Allow-Origin")
+            .is_none());
+        let none = client.get("/").dispatch().await;
+        assert!(none
+            .headers()
+            .get_one("Access-Control-Allow-Origin")
+            .is_none());
+    }
+}
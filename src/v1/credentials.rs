@@ -0,0 +1,153 @@
+use std::{env, sync::OnceLock};
+
+/// Where the Easee credentials are read from, chosen by `CREDENTIALS_SOURCE`.
+///
+/// Without it, `USERNAME` and `PASSWORD` are used when both are set, else the credentials file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialsSource {
+    Env,
+    /// `CREDENTIALS_FILE`, or the default location of `local_credentials`.
+    File(Option<String>),
+    /// The password stored for `USERNAME` under the `easee_status` service, requires the
+    /// `keyring` feature.
+    Keyring,
+}
+
+impl CredentialsSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CredentialsSource::Env => "env",
+            CredentialsSource::File(_) => "file",
+            CredentialsSource::Keyring => "keyring",
+        }
+    }
+}
+
+impl std::fmt::Display for CredentialsSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CredentialsSource::File(Some(file)) => write!(f, "file {}", file),
+            CredentialsSource::File(None) => write!(f, "file (default location)"),
+            source => write!(f, "{}", source.as_str()),
+        }
+    }
+}
+
+/// Picks the source from `CREDENTIALS_SOURCE` and which credential variables are set.
+pub fn resolve(
+    selected: Option<&str>,
+    has_username: bool,
+    has_password: bool,
+    file: Option<String>,
+) -> Result<CredentialsSource, String> {
+    match selected {
+        None if has_username && has_password => Ok(CredentialsSource::Env),
+        None => Ok(CredentialsSource::File(file)),
+        Some("env") if has_username && has_password => Ok(CredentialsSource::Env),
+        Some("env") => Err(String::from(
+            "CREDENTIALS_SOURCE is env, but USERNAME or PASSWORD is not set",
+        )),
+        Some("file") => Ok(CredentialsSource::File(file)),
+        Some("keyring") if !cfg!(feature = "keyring") => Err(String::from(
+            "CREDENTIALS_SOURCE is keyring, but this binary was built without the keyring feature",
+        )),
+        Some("keyring") if !has_username => Err(String::from(
+            "CREDENTIALS_SOURCE is keyring, but USERNAME is not set",
+        )),
+        Some("keyring") => Ok(CredentialsSource::Keyring),
+        Some("token") => Err(String::from(
+            "CREDENTIALS_SOURCE token is not supported, a login needs a username and password",
+        )),
+        Some(other) => Err(format!("Illegal CREDENTIALS_SOURCE: {}", other)),
+    }
+}
+
+/// The source the credentials are read from. Panics if the one selected is unavailable.
+pub fn source() -> &'static CredentialsSource {
+    static SOURCE: OnceLock<CredentialsSource> = OnceLock::new();
+    SOURCE.get_or_init(|| {
+        let selected = env::var("CREDENTIALS_SOURCE").ok();
+        let source = resolve(
+            selected.as_deref(),
+            env::var("USERNAME").is_ok(),
+            env::var("PASSWORD").is_ok(),
+            env::var("CREDENTIALS_FILE").ok(),
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+        tracing::info!("CREDENTIALS_SOURCE: {}", source);
+        source
+    })
+}
+
+/// Reads the username and password from the keyring.
+#[cfg(feature = "keyring")]
+pub fn from_keyring() -> Result<(String, String), String> {
+    let username = env::var("USERNAME").map_err(|_| String::from("USERNAME not set"))?;
+    let password = keyring::Entry::new("easee_status", &username)
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| e.to_string())?;
+    Ok((username, password))
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn from_keyring() -> Result<(String, String), String> {
+    Err(String::from("built without the keyring feature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_to_env_when_both_are_set_else_the_file() {
+        assert_eq!(resolve(None, true, true, None), Ok(CredentialsSource::Env));
+        assert_eq!(
+            resolve(None, true, false, Some(String::from("creds.toml"))),
+            Ok(CredentialsSource::File(Some(String::from("creds.toml"))))
+        );
+        assert_eq!(
+            resolve(None, false, false, None),
+            Ok(CredentialsSource::File(None))
+        );
+    }
+
+    #[test]
+    fn explicit_sources() {
+        assert_eq!(
+            resolve(Some("env"), true, true, None),
+            Ok(CredentialsSource::Env)
+        );
+        assert!(resolve(Some("env"), true, false, None).is_err());
+        assert!(resolve(Some("env"), false, true, None).is_err());
+        // The file wins even with the variables set
+        assert_eq!(
+            resolve(Some("file"), true, true, None),
+            Ok(CredentialsSource::File(None))
+        );
+        assert!(resolve(Some("token"), true, true, None).is_err());
+        assert!(resolve(Some("ENV"), true, true, None).is_err());
+    }
+
+    #[test]
+    fn keyring_needs_the_feature_and_a_username() {
+        assert!(resolve(Some("keyring"), false, false, None).is_err());
+        assert_eq!(
+            resolve(Some("keyring"), true, false, None).is_ok(),
+            cfg!(feature = "keyring")
+        );
+    }
+
+    #[test]
+    fn displays_the_source_not_the_credentials() {
+        assert_eq!(CredentialsSource::Env.to_string(), "env");
+        assert_eq!(
+            CredentialsSource::File(Some(String::from("/run/creds"))).to_string(),
+            "file /run/creds"
+        );
+        assert_eq!(
+            CredentialsSource::File(None).to_string(),
+            "file (default location)"
+        );
+        assert_eq!(CredentialsSource::Keyring.to_string(), "keyring");
+    }
+}
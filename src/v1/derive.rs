@@ -0,0 +1,132 @@
+use std::{collections::HashMap, env};
+
+use chrono::{DateTime, Utc};
+use tracing::{instrument, trace};
+
+use super::{anonymize::log_id, structs::ChargerState};
+
+/// Estimates the energy per hour from the session energy when Easee reports none while
+/// charging, as some firmware does.
+#[derive(Debug)]
+pub struct EnergyDeriver {
+    enabled: bool,
+    last: HashMap<String, (DateTime<Utc>, f64)>,
+}
+
+impl EnergyDeriver {
+    pub fn new(enabled: bool) -> Self {
+        EnergyDeriver {
+            enabled,
+            last: HashMap::new(),
+        }
+    }
+
+    #[instrument(level = "trace")]
+    pub fn from_env() -> Self {
+        let enabled = env::var("DERIVE_ENERGY_PER_HOUR").is_ok_and(|d| d == "true");
+        tracing::info!("DERIVE_ENERGY_PER_HOUR: {}", enabled);
+        EnergyDeriver::new(enabled)
+    }
+
+    pub fn forget(&mut self, charger_id: &str) {
+        self.last.remove(charger_id);
+    }
+
+    /// The session energy gained since the charger was last observed, per hour, when Easee
+    /// reports no energy per hour although the charger draws power.
+    pub fn observe(&mut self, charger: &ChargerState, time: DateTime<Utc>) -> Option<f64> {
+        if !self.enabled {
+            return None;
+        }
+        let last = self
+            .last
+            .insert(charger.id.clone(), (time, charger.session));
+        if charger.energy_per_hour != 0.0 || charger.power <= 0.0 {
+            return None;
+        }
+        let (last_time, last_session) = last?;
+        let hours = (time - last_time).num_milliseconds() as f64 / 3_600_000.0;
+        let gained = charger.session - last_session;
+        // A new session resets the session energy, which says nothing about the rate
+        if hours <= 0.0 || gained < 0.0 {
+            return None;
+        }
+        let derived = gained / hours;
+        trace!(
+            "Derived {} kWh per hour for {}",
+            derived,
+            log_id(&charger.id)
+        );
+        Some(derived)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn charging(session: f64, energy_per_hour: f64) -> ChargerState {
+        ChargerState::builder("EH000001")
+            .power(7.0)
+            .session(session)
+            .energy_per_hour(energy_per_hour)
+            .build()
+    }
+
+    fn close(a: Option<f64>, b: f64) -> bool {
+        a.is_some_and(|a| (a - b).abs() < 1e-9)
+    }
+
+    #[test]
+    fn derives_over_irregular_ticks() {
+        let mut deriver = EnergyDeriver::new(true);
+        assert_eq!(deriver.observe(&charging(10.0, 0.0), at(0)), None);
+        // 0.5 kWh in 4 minutes
+        let derived = deriver.observe(&charging(10.5, 0.0), at(240));
+        assert!(close(derived, 7.5), "{:?}", derived);
+        // 0.1 kWh in 45 seconds
+        let derived = deriver.observe(&charging(10.6, 0.0), at(285));
+        assert!(close(derived, 8.0), "{:?}", derived);
+        // 1.1 kWh in 11 minutes, after a late tick
+        let derived = deriver.observe(&charging(11.7, 0.0), at(945));
+        assert!(close(derived, 6.0), "{:?}", derived);
+    }
+
+    #[test]
+    fn derives_nothing_when_easee_reports_it_or_nothing_is_drawn() {
+        let mut deriver = EnergyDeriver::new(true);
+        deriver.observe(&charging(10.0, 0.0), at(0));
+        assert_eq!(deriver.observe(&charging(10.5, 7.2), at(240)), None);
+        let idle = ChargerState::builder("EH000001").session(10.5).build();
+        assert_eq!(deriver.observe(&idle, at(300)), None);
+        // Both were still remembered
+        let derived = deriver.observe(&charging(10.6, 0.0), at(360));
+        assert!(close(derived, 6.0), "{:?}", derived);
+    }
+
+    #[test]
+    fn new_sessions_and_repeated_times_derive_nothing() {
+        let mut deriver = EnergyDeriver::new(true);
+        deriver.observe(&charging(10.0, 0.0), at(0));
+        assert_eq!(deriver.observe(&charging(0.2, 0.0), at(60)), None);
+        assert_eq!(deriver.observe(&charging(0.3, 0.0), at(60)), None);
+        let derived = deriver.observe(&charging(0.4, 0.0), at(120));
+        assert!(close(derived, 6.0), "{:?}", derived);
+    }
+
+    #[test]
+    fn disabled_and_forgotten_derive_nothing() {
+        let mut disabled = EnergyDeriver::new(false);
+        disabled.observe(&charging(10.0, 0.0), at(0));
+        assert_eq!(disabled.observe(&charging(10.5, 0.0), at(240)), None);
+
+        let mut deriver = EnergyDeriver::new(true);
+        deriver.observe(&charging(10.0, 0.0), at(0));
+        deriver.forget("EH000001");
+        assert_eq!(deriver.observe(&charging(10.5, 0.0), at(240)), None);
+    }
+}
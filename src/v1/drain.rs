@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, warn};
+
+/// Set while draining: no new ticks are started and `/readyz` fails, so a new instance can
+/// take over without both polling Easee. Ticks already running finish.
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::Relaxed)
+}
+
+/// Starts or stops draining, returning whether it changed anything.
+pub fn set_draining(draining: bool) -> bool {
+    let changed = DRAINING.swap(draining, Ordering::Relaxed) != draining;
+    if changed {
+        if draining {
+            info!("Draining, no new ticks are started");
+        } else {
+            info!("No longer draining, ticks resume");
+        }
+    }
+    changed
+}
+
+/// Toggles draining on every SIGUSR1.
+#[cfg(unix)]
+#[instrument(skip_all, level = "trace")]
+pub async fn watch_drain_signal(shutdown: CancellationToken) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            warn!(
+                "Listening for SIGUSR1 failed, draining only by route: {}",
+                e
+            );
+            return;
+        }
+    };
+    loop {
+        tokio::select! {
+            _ = signals.recv() => { set_draining(!is_draining()); }
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn watch_drain_signal(_shutdown: CancellationToken) {}
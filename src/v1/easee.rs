@@ -1,207 +1,1049 @@
-use std::{collections::HashMap, env, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    env, fs,
+    net::SocketAddr,
+    sync::{Arc, OnceLock},
+    time::{Duration as StdDuration, Instant},
+};
 
 use chrono::{prelude::*, Duration};
+use futures::{stream, Stream};
 
 use tokio::sync::Mutex;
-use tracing::{debug, error, instrument, span, trace, warn, Level, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, instrument, span, trace, warn, Level};
 
-use local_credentials;
+use super::{
+    access,
+    anonymize::{log_debug, log_id},
+    credentials::{self, CredentialsSource},
+    lockout, memory,
+    names::{display_name, order_chargers},
+    overrides,
+    record::record_response,
+    roster,
+    run::get_interval,
+    structs::{
+        ChargerConfig, ChargerInfo, ChargerState, ChargingSession, EaseeError, ProductInfo,
+        SessionState,
+    },
+    upstream_limit::{self, EndpointClass},
+};
 
-use super::structs::{ChargerState, EaseeError, SessionState};
+const DEFAULT_EASEE_BASE: &str = "https://api.easee.cloud/api";
 
-const EASEE_BASE: &'static str = "https://api.easee.cloud/api";
-const CHARGERS_ENDPOINT: &'static str = "https://api.easee.cloud/api/chargers";
-const LOGIN_ENDPOINT: &'static str = "https://api.easee.cloud/api/accounts/login";
-const REFRESH_ENDPOINT: &'static str = "https://api.easee.cloud/api/accounts/refresh_token";
+/// The Easee API requests go to, from `EASEE_API_BASE`, e.g. a mock Easee in tests.
+pub(crate) fn easee_base() -> String {
+    env::var("EASEE_API_BASE")
+        .map(|base| base.trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| DEFAULT_EASEE_BASE.to_string())
+}
+
+/// An authentication endpoint Easee has moved between paths. The paths, below the API base,
+/// are tried in order until one isn't a 404 or 405, unless `var` pins a URL.
+struct AuthEndpoint {
+    name: &'static str,
+    var: &'static str,
+    paths: [&'static str; 2],
+    /// The path that last answered, tried first from then on.
+    working: std::sync::Mutex<Option<String>>,
+}
+
+static LOGIN_ENDPOINT: AuthEndpoint = AuthEndpoint {
+    name: "login",
+    var: "EASEE_LOGIN_ENDPOINT",
+    paths: ["/accounts/login", "/accounts/token"],
+    working: std::sync::Mutex::new(None),
+};
+
+static REFRESH_ENDPOINT: AuthEndpoint = AuthEndpoint {
+    name: "token refresh",
+    var: "EASEE_REFRESH_ENDPOINT",
+    paths: ["/accounts/refresh_token", "/accounts/token/refresh"],
+    working: std::sync::Mutex::new(None),
+};
+
+impl AuthEndpoint {
+    fn candidates(&self) -> Vec<String> {
+        if let Ok(pinned) = env::var(self.var) {
+            return vec![pinned];
+        }
+        let working = self.working.lock().unwrap().clone();
+        let mut paths: Vec<String> = working.into_iter().collect();
+        for path in self.paths {
+            if !paths.iter().any(|p| p == path) {
+                paths.push(path.to_string());
+            }
+        }
+        let base = easee_base();
+        paths
+            .into_iter()
+            .map(|path| format!("{}{}", base, path))
+            .collect()
+    }
+
+    /// Posts the payload, falling back to the next path when one is gone.
+    async fn post<T: serde::Serialize>(
+        &self,
+        payload: &T,
+    ) -> Result<reqwest::Response, EaseeError> {
+        let candidates = self.candidates();
+        let mut last = None;
+        for (i, url) in candidates.iter().enumerate() {
+            if i > 0 && !upstream_limit::acquire(EndpointClass::Auth) {
+                return Err(EaseeError::RateLimit);
+            }
+            let response = http_client()
+                .post(url)
+                .json(payload)
+                .header("Content-type", "application/json")
+                .send()
+                .await
+                .map_err(|e| {
+                    tracing::error!("Failed to send {} request: {}", self.name, e);
+                    EaseeError::HttpFailed
+                })?;
+            let status = response.status();
+            if status == reqwest::StatusCode::NOT_FOUND
+                || status == reqwest::StatusCode::METHOD_NOT_ALLOWED
+            {
+                warn!("The {} endpoint {} answered {}", self.name, url, status);
+                last = Some(response);
+                continue;
+            }
+            if i > 0 {
+                info!("Using {} as the {} endpoint", url, self.name);
+            }
+            if let Some(path) = url.strip_prefix(&easee_base()) {
+                *self.working.lock().unwrap() = Some(path.to_string());
+            }
+            return Ok(response);
+        }
+        error!("No {} endpoint answered", self.name);
+        last.ok_or(EaseeError::HttpFailed)
+    }
+}
+
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// The TLS options of the HTTP client.
+#[derive(Debug, Clone, Default)]
+pub struct HttpClientOptions {
+    /// A PEM file with a CA certificate to trust too, from `EASEE_EXTRA_CA_CERT`.
+    pub extra_ca_cert: Option<String>,
+    /// `EASEE_ACCEPT_INVALID_CERTS=true`, turning certificate verification off.
+    pub accept_invalid_certs: bool,
+    /// `host=ip:port,...` pairs skipping the DNS lookup, from `EASEE_RESOLVE_OVERRIDE`.
+    pub resolve_override: Option<String>,
+}
+
+impl HttpClientOptions {
+    pub fn from_env() -> Self {
+        HttpClientOptions {
+            extra_ca_cert: env::var("EASEE_EXTRA_CA_CERT").ok(),
+            accept_invalid_certs: env::var("EASEE_ACCEPT_INVALID_CERTS").is_ok_and(|v| v == "true"),
+            resolve_override: env::var("EASEE_RESOLVE_OVERRIDE").ok(),
+        }
+    }
+}
+
+/// Builds the client used for all Easee and InfluxDB requests. Fails if the TLS options are
+/// unusable, so call it at startup rather than finding out on the first request.
+#[instrument(level = "trace")]
+pub fn init_http_client() -> Result<(), String> {
+    let client = build_http_client(&HttpClientOptions::from_env())?;
+    // Already built if a request came first, with the same options
+    let _ = HTTP_CLIENT.set(client);
+    Ok(())
+}
+
+/// The client shared by every request, to Easee and to InfluxDB. Without
+/// [`init_http_client`] it is built on first use, without the TLS options if they are
+/// unusable.
+pub(crate) fn http_client() -> reqwest::Client {
+    HTTP_CLIENT
+        .get_or_init(|| {
+            build_http_client(&HttpClientOptions::from_env()).unwrap_or_else(|e| {
+                error!("{}, using a client without the TLS options", e);
+                reqwest::Client::new()
+            })
+        })
+        .clone()
+}
+
+pub fn build_http_client(options: &HttpClientOptions) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder();
+
+    if let Some(ref path) = options.extra_ca_cert {
+        let pem = fs::read(path)
+            .map_err(|e| format!("Failed to read EASEE_EXTRA_CA_CERT {}: {}", path, e))?;
+        let cert = reqwest::Certificate::from_pem(&pem)
+            .map_err(|e| format!("Invalid certificate in EASEE_EXTRA_CA_CERT {}: {}", path, e))?;
+        info!("Trusting extra CA certificate from {}", path);
+        builder = builder.add_root_certificate(cert);
+    }
+
+    if options.accept_invalid_certs {
+        warn!("EASEE_ACCEPT_INVALID_CERTS is set, certificates from Easee are NOT verified");
+        warn!("Anyone between this service and Easee can read your credentials, prefer EASEE_EXTRA_CA_CERT");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    if let Some(ref spec) = options.resolve_override {
+        let overrides = parse_resolve_overrides(spec)
+            .map_err(|e| format!("Illegal EASEE_RESOLVE_OVERRIDE: {}", e))?;
+        for (host, addr) in overrides {
+            // Only the lookup is skipped, TLS still verifies the certificate against the host
+            info!("Resolving {} to {}", host, addr);
+            builder = builder.resolve(&host, addr);
+        }
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("Failed to create client: {}", e))
+}
+
+/// Reads comma separated `host=ip:port` pairs, such as `api.easee.cloud=203.0.113.10:443`.
+pub fn parse_resolve_overrides(spec: &str) -> Result<Vec<(String, SocketAddr)>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (host, addr) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("{} is not host=ip:port", pair))?;
+            let host = host.trim();
+            if host.is_empty() {
+                return Err(format!("{} has no host", pair));
+            }
+            let addr = addr
+                .trim()
+                .parse()
+                .map_err(|_| format!("{} is not an ip:port address", addr))?;
+            Ok((host.to_string(), addr))
+        })
+        .collect()
+}
+
+/// Length of the date windows the sessions history is requested in.
+const SESSION_WINDOW_DAYS: i64 = 30;
+
+/// Token lifetimes shorter than this are most likely a mistake on Easee's side.
+const SUSPICIOUS_TOKEN_LIFETIME_SECS: i64 = 60;
+
+/// Requests are not sent for this long after Easee reported maintenance.
+const MAINTENANCE_BACKOFF: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// Maintenance is warned about at most this often.
+const MAINTENANCE_WARN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+#[derive(Debug, Default)]
+struct MaintenanceState {
+    until: Option<Instant>,
+    last_warned: Option<Instant>,
+}
+
+impl MaintenanceState {
+    /// Backs off from `now`, returning whether to warn, at most once per warn interval.
+    fn enter(&mut self, now: Instant) -> bool {
+        self.until = Some(now + MAINTENANCE_BACKOFF);
+        let warn = !self
+            .last_warned
+            .is_some_and(|t| now.duration_since(t) < MAINTENANCE_WARN_INTERVAL);
+        if warn {
+            self.last_warned = Some(now);
+        }
+        warn
+    }
+
+    /// What is left of the backoff at `now`, if any.
+    fn remaining(&self, now: Instant) -> Option<StdDuration> {
+        self.until
+            .filter(|until| now < *until)
+            .map(|until| until - now)
+    }
+}
+
+static MAINTENANCE: std::sync::Mutex<MaintenanceState> = std::sync::Mutex::new(MaintenanceState {
+    until: None,
+    last_warned: None,
+});
+
+/// Tracing target for authentication events, routed to the audit log. Never log secrets to it.
+pub const AUTH_TARGET: &str = "auth";
 
 #[instrument(skip_all, level = "trace")]
 pub async fn get_charger_state(
     session: Arc<Mutex<SessionState>>,
 ) -> Result<Vec<ChargerState>, EaseeError> {
-    let ids = get_charger_list(session.to_owned()).await;
-    if let Err(e) = ids {
+    get_charger_states(session)
+        .await?
+        .into_iter()
+        .filter(|(_, state)| !matches!(state, Err(EaseeError::NoAccess)))
+        .map(|(_, state)| state)
+        .collect()
+}
+
+/// A charger with its state, or why the state couldn't be fetched.
+pub type ChargerResult = (ChargerInfo, Result<ChargerState, EaseeError>);
+
+/// A charger id with its config, or why the config couldn't be fetched.
+pub type ConfigResult = (String, Result<ChargerConfig, EaseeError>);
+
+/// Fetches the state of every charger, keeping the failures next to the charger they are for.
+/// Chargers marked `no_access` are in the list order too, as `EaseeError::NoAccess`.
+///
+/// Only a failure to list the chargers fails as a whole.
+#[instrument(skip_all, level = "trace")]
+pub async fn get_charger_states(
+    session: Arc<Mutex<SessionState>>,
+) -> Result<Vec<ChargerResult>, EaseeError> {
+    let chargers = get_charger_listing(session.to_owned()).await;
+    if let Err(e) = chargers {
         debug!("Bubbling error: {}", e);
         return Err(e);
     }
-    let ids = ids.unwrap();
+    let chargers: Vec<ChargerInfo> = chargers.unwrap();
+    let fetch: Vec<bool> = chargers
+        .iter()
+        .map(|charger| access::should_fetch(&charger.id))
+        .collect();
+    let count = fetch.iter().filter(|fetch| **fetch).count();
+    let started = tokio::time::Instant::now();
     let mut states = Vec::new();
-    for id in ids {
-        trace!("Getting charger state charger: {}", &id);
-        let state = external_request_charger_state(&id, session.to_owned()).await;
-        if let Err(e) = state {
-            return Err(e);
+    let mut index = 0;
+    for (charger, fetch) in chargers.into_iter().zip(fetch) {
+        // Kept in the results, so the chargers after it keep their place in the list
+        if !fetch {
+            trace!("Skipping charger without access: {}", log_id(&charger.id));
+            states.push((charger, Err(EaseeError::NoAccess)));
+            continue;
+        }
+        let offset = spread_offset(index, count, spread_window());
+        index += 1;
+        if !offset.is_zero() {
+            tokio::time::sleep_until(started + offset).await;
         }
-        trace!("Pushing charger state charger: {}", &id);
-        states.push(state.unwrap());
+        trace!("Getting charger state charger: {}", log_id(&charger.id));
+        let state = external_request_charger_state(&charger.id, session.to_owned())
+            .await
+            .map(|mut state| {
+                state.name = display_name(&charger.id, charger.name.as_deref());
+                state
+            });
+        match state {
+            Ok(_) => {
+                access::granted(&charger.id);
+                memory::succeeded(&charger.id);
+                charger_product(&charger.id, session.to_owned()).await;
+            }
+            Err(EaseeError::Forbidden) | Err(EaseeError::NotFound) => access::refused(&charger.id),
+            Err(_) => (),
+        }
+        if let Err(ref e) = state {
+            debug!("Charger {} failed: {}", log_id(&charger.id), e);
+        }
+        trace!("Pushing charger state charger: {}", log_id(&charger.id));
+        states.push((charger, state));
     }
     Ok(states)
 }
 
+/// The window the state requests of a tick are spread over, from `SPREAD_WINDOW_SECS`.
+/// Defaults to 0, sending them back to back.
+pub fn spread_window() -> StdDuration {
+    static WINDOW: OnceLock<StdDuration> = OnceLock::new();
+    *WINDOW.get_or_init(|| {
+        let secs = env::var("SPREAD_WINDOW_SECS")
+            .map_or(0, |s| s.parse().expect("Illegal spread window format"));
+        tracing::info!("SPREAD_WINDOW_SECS: {}", secs);
+        let window = StdDuration::from_secs(secs);
+        if secs > 0 && window >= get_interval().to_std().unwrap() {
+            warn!("SPREAD_WINDOW_SECS is not below the interval, ticks will run into the deadline");
+        }
+        window
+    })
+}
+
+/// How long after the first request of a tick the `index`th of `count` is sent, the requests
+/// spread evenly so the last one goes out a step before the window ends.
+pub fn spread_offset(index: usize, count: usize, window: StdDuration) -> StdDuration {
+    if count == 0 {
+        return StdDuration::ZERO;
+    }
+    window.mul_f64(index as f64 / count as f64)
+}
+
 #[instrument(skip_all, level = "trace")]
-async fn get_charger_list(session: Arc<Mutex<SessionState>>) -> Result<Vec<String>, EaseeError> {
-    refresh_auth(session.to_owned()).await?;
-    let client = reqwest::Client::new();
-    if let Some(ref t) = session.lock().await.token {
-        let res = client
-            .get(CHARGERS_ENDPOINT)
-            .bearer_auth(t)
-            .send()
-            .await
-            .map_err(|_| EaseeError::HttpFailed)?;
-        if res.status().is_success() {
-            let mut charger_ids = Vec::new();
+pub(crate) async fn get_charger_list(
+    session: Arc<Mutex<SessionState>>,
+) -> Result<Vec<String>, EaseeError> {
+    let chargers = get_charger_listing(session).await?;
+    Ok(chargers.into_iter().map(|charger| charger.id).collect())
+}
 
-            let parsing_span = span!(Level::TRACE, "parsing_response");
-            {
-                let _guard = parsing_span.enter();
-
-                let body = res.text().await.map_err(|_| EaseeError::HttpFailed)?;
-
-                let json: serde_json::Value =
-                    serde_json::from_str(&body).map_err(|_| EaseeError::InvalidResponse)?;
-                for charger in json.as_array().unwrap() {
-                    let id = charger
-                        .get("id")
-                        .ok_or(EaseeError::InvalidResponse)?
-                        .as_str()
-                        .ok_or(EaseeError::InvalidResponse)?
-                        .to_string();
-                    trace!("Got charger: {:?}", id);
-                    charger_ids.push(id);
-                }
-                debug!("Got {} chargers", charger_ids.len());
-            }
-            Ok(charger_ids)
+#[instrument(skip_all, level = "trace")]
+pub(crate) async fn get_charger_listing(
+    session: Arc<Mutex<SessionState>>,
+) -> Result<Vec<ChargerInfo>, EaseeError> {
+    let url = format!("{}/chargers", easee_base());
+    let res = authorized_get(&url, session).await?;
+    if res.status().is_success() {
+        let body = res.text().await.map_err(|_| EaseeError::HttpFailed)?;
+        record_response("chargers", None, &body).await;
+
+        let mut chargers = parse_timed(None, &body, parse_charger_list)?;
+        order_chargers(&mut chargers);
+        debug!("Got {} chargers", chargers.len());
+        roster::observe(&chargers);
+        memory::touch(chargers.iter().map(|c| c.id.as_str()));
+        overrides::check_known(chargers.iter().map(|c| c.id.as_str()));
+        Ok(chargers)
+    } else {
+        if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            warn!("Rate limit exceeded");
+            Err(EaseeError::RateLimit)
         } else {
-            if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                warn!("Rate limit exceeded");
-                Err(EaseeError::Unathorized)
+            error!("Request failed: {}", res.status());
+            Err(EaseeError::HttpFailed)
+        }
+    }
+}
+
+/// Parses a response body in a `parsing_response` span carrying its size and how long
+/// parsing took, warning when that exceeds `PARSE_WARN_MS`.
+fn parse_timed<T>(
+    charger_id: Option<&str>,
+    body: &str,
+    parse: impl FnOnce(&str) -> Result<T, EaseeError>,
+) -> Result<T, EaseeError> {
+    let parsing_span = span!(
+        Level::TRACE,
+        "parsing_response",
+        payload_bytes = body.len(),
+        charger_id = %charger_id.map(log_id).unwrap_or_default(),
+        parse_ms = tracing::field::Empty,
+    );
+    let _guard = parsing_span.enter();
+    let started = Instant::now();
+    let parsed = parse(body);
+    let elapsed = started.elapsed();
+    parsing_span.record("parse_ms", elapsed.as_millis() as u64);
+    if elapsed.as_millis() as u64 > parse_warn_ms() {
+        warn!(
+            "Parsing a {} byte response took {}ms",
+            body.len(),
+            elapsed.as_millis()
+        );
+    }
+    parsed
+}
+
+/// Parse time in milliseconds above which a response is logged, from `PARSE_WARN_MS`.
+/// Defaults to 100.
+fn parse_warn_ms() -> u64 {
+    static WARN_MS: OnceLock<u64> = OnceLock::new();
+    *WARN_MS.get_or_init(|| {
+        let warn_ms = env::var("PARSE_WARN_MS")
+            .map_or(100, |w| w.parse().expect("Illegal parse warn format"));
+        tracing::info!("PARSE_WARN_MS: {}", warn_ms);
+        warn_ms
+    })
+}
+
+/// Reads the charger list, either the legacy flat array of chargers or chargers
+/// nested under `products`, `sites`, `circuits` or `chargers`.
+pub fn parse_charger_list(body: &str) -> Result<Vec<ChargerInfo>, EaseeError> {
+    let json: serde_json::Value =
+        serde_json::from_str(body).map_err(|_| EaseeError::InvalidResponse)?;
+    let mut chargers = Vec::new();
+    collect_chargers(&json, &mut chargers)?;
+    Ok(chargers)
+}
+
+fn collect_chargers(
+    json: &serde_json::Value,
+    chargers: &mut Vec<ChargerInfo>,
+) -> Result<(), EaseeError> {
+    const NESTING: [&str; 4] = ["products", "sites", "circuits", "chargers"];
+
+    match json {
+        serde_json::Value::Array(items) => {
+            for item in items {
+                collect_chargers(item, chargers)?;
+            }
+        }
+        serde_json::Value::Object(object) => {
+            let nested: Vec<&serde_json::Value> =
+                NESTING.iter().filter_map(|key| object.get(*key)).collect();
+            if nested.is_empty() {
+                let id = object
+                    .get("id")
+                    .and_then(|id| id.as_str())
+                    .ok_or(EaseeError::InvalidResponse)?
+                    .to_string();
+                let name = object
+                    .get("name")
+                    .and_then(|name| name.as_str())
+                    .map(str::to_string);
+                trace!("Got charger: {:?} {:?}", log_id(&id), name);
+                chargers.push(ChargerInfo { id, name });
             } else {
-                error!("Request failed: {}", res.status());
-                Err(EaseeError::HttpFailed)
+                for value in nested {
+                    collect_chargers(value, chargers)?;
+                }
             }
         }
+        _ => return Err(EaseeError::InvalidResponse),
+    }
+    Ok(())
+}
+
+// Info level so the charger id is attached to warnings and errors as well
+#[instrument(skip_all, fields(charger_id = %log_id(charger_id)), level = "info")]
+pub(crate) async fn external_request_charger_state(
+    charger_id: &str,
+    session: Arc<Mutex<SessionState>>,
+) -> Result<ChargerState, EaseeError> {
+    let url = format!("{}/chargers/{}/state", easee_base(), charger_id);
+    let res = authorized_get(&url, session).await?;
+    if res.status().is_success() {
+        trace!("Request success");
+        let body = res.text().await.map_err(|_| EaseeError::HttpFailed)?;
+        record_response("state", Some(charger_id), &body).await;
+
+        let mut charger_state = parse_timed(Some(charger_id), &body, |body| {
+            let json: serde_json::Value =
+                serde_json::from_str(body).map_err(|_| EaseeError::InvalidResponse)?;
+            ChargerState::from_state_json(charger_id, &json)
+        })?;
+        charger_state.name = display_name(charger_id, None);
+        debug!(
+            "Got charger state: {}",
+            log_debug(&charger_state, charger_id)
+        );
+        Ok(charger_state)
+    } else {
+        if res.status() == reqwest::StatusCode::FORBIDDEN {
+            debug!("Access to the charger refused");
+            Err(EaseeError::Forbidden)
+        } else if res.status() == reqwest::StatusCode::NOT_FOUND {
+            warn!("Easee lists the charger but has no state for it");
+            Err(EaseeError::NotFound)
+        } else if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            warn!("Rate limit exceeded");
+            Err(EaseeError::RateLimit)
+        } else {
+            error!("Request failed: {}", res.status());
+            Err(EaseeError::Unathorized)
+        }
+    }
+}
+
+#[instrument(skip_all, level = "trace")]
+pub async fn get_charger_configs(
+    session: Arc<Mutex<SessionState>>,
+) -> Result<Vec<ConfigResult>, EaseeError> {
+    let ids = get_charger_list(session.to_owned()).await?;
+    let mut configs = Vec::new();
+    for id in ids {
+        // Probing chargers without access is left to the state polling
+        if access::no_access(&id) || !overrides::charger_config(&id).collect {
+            trace!("Skipping charger config charger: {}", log_id(&id));
+            continue;
+        }
+        trace!("Getting charger config charger: {}", log_id(&id));
+        let config = external_request_charger_config(&id, session.to_owned()).await;
+        if let Err(ref e) = config {
+            debug!("Charger {} config failed: {}", log_id(&id), e);
+        }
+        configs.push((id, config));
+    }
+    Ok(configs)
+}
+
+#[instrument(skip_all, fields(charger_id = %log_id(charger_id)), level = "info")]
+pub(crate) async fn external_request_charger_config(
+    charger_id: &str,
+    session: Arc<Mutex<SessionState>>,
+) -> Result<ChargerConfig, EaseeError> {
+    let url = format!("{}/chargers/{}/config", easee_base(), charger_id);
+    let res = authorized_get(&url, session).await?;
+    if res.status().is_success() {
+        let body = res.text().await.map_err(|_| EaseeError::HttpFailed)?;
+        record_response("config", Some(charger_id), &body).await;
+
+        let json: serde_json::Value =
+            serde_json::from_str(&body).map_err(|_| EaseeError::InvalidResponse)?;
+        let config = ChargerConfig {
+            id: charger_id.to_string(),
+            is_enabled: json["isEnabled"].as_bool(),
+            max_charger_current: json["maxChargerCurrent"].as_f64(),
+            phase_mode: json["phaseMode"].as_i64(),
+            smart_charging: json["smartCharging"].as_bool(),
+        };
+        debug!("Got charger config: {}", log_debug(&config, charger_id));
+        Ok(config)
     } else {
-        error!("No token after refresh");
-        unreachable!();
+        if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            warn!("Rate limit exceeded");
+            Err(EaseeError::RateLimit)
+        } else {
+            error!("Request failed: {}", res.status());
+            Err(EaseeError::HttpFailed)
+        }
     }
 }
 
+/// Time before fetching the details of a charger is tried again after it failed.
+const PRODUCT_RETRY_AFTER_MINUTES: i64 = 60;
+
+/// The charger's product info, fetched the first time it is asked for and kept in the
+/// charger memory from then on. A failed fetch is tried again after
+/// `PRODUCT_RETRY_AFTER_MINUTES`.
 #[instrument(skip(session), level = "trace")]
-async fn external_request_charger_state(
+pub(crate) async fn charger_product(
     charger_id: &str,
     session: Arc<Mutex<SessionState>>,
-) -> Result<ChargerState, EaseeError> {
-    refresh_auth(session.to_owned()).await?;
+) -> Option<ProductInfo> {
+    if let Some(product) = memory::product(charger_id) {
+        return Some(product);
+    }
+    if memory::product_failed_within(charger_id, Duration::minutes(PRODUCT_RETRY_AFTER_MINUTES)) {
+        return None;
+    }
+    match external_request_charger_details(charger_id, session).await {
+        Ok(product) => {
+            memory::remember_product(charger_id, product.clone());
+            Some(product)
+        }
+        Err(e) => {
+            debug!("Charger {} details failed: {}", log_id(charger_id), e);
+            memory::product_failed(charger_id);
+            None
+        }
+    }
+}
 
-    let url = format!("{}/chargers/{}/state", EASEE_BASE, charger_id);
-    let client = reqwest::Client::new();
-    if let Some(ref t) = session.lock().await.token {
-        trace!("Using token: {}", t);
-        let res = client
-            .get(&url)
-            .bearer_auth(t)
-            .send()
-            .await
-            .map_err(|_| EaseeError::HttpFailed)?;
-        if res.status().is_success() {
-            trace!("Request success");
-            let charger_state;
+async fn external_request_charger_details(
+    charger_id: &str,
+    session: Arc<Mutex<SessionState>>,
+) -> Result<ProductInfo, EaseeError> {
+    let url = format!("{}/chargers/{}/details", easee_base(), charger_id);
+    let res = authorized_get(&url, session).await?;
+    if res.status().is_success() {
+        let body = res.text().await.map_err(|_| EaseeError::HttpFailed)?;
+        record_response("details", Some(charger_id), &body).await;
 
-            let parsing_span = span!(Level::TRACE, "parsing_response");
+        let json: serde_json::Value =
+            serde_json::from_str(&body).map_err(|_| EaseeError::InvalidResponse)?;
+        let product = ProductInfo::from_details_json(&json);
+        debug!("Got charger details: {}", log_debug(&product, charger_id));
+        Ok(product)
+    } else if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        warn!("Rate limit exceeded");
+        Err(EaseeError::RateLimit)
+    } else {
+        error!("Request failed: {}", res.status());
+        Err(EaseeError::HttpFailed)
+    }
+}
+
+/// Fetches the state of a charger without parsing it, returning the status and the body.
+#[instrument(skip(session), level = "trace")]
+pub(crate) async fn raw_charger_state(
+    charger_id: &str,
+    session: Arc<Mutex<SessionState>>,
+) -> Result<(u16, String), EaseeError> {
+    let url = format!("{}/chargers/{}/state", easee_base(), charger_id);
+    let res = authorized_get(&url, session).await?;
+    let status = res.status().as_u16();
+    let body = res.text().await.map_err(|_| EaseeError::HttpFailed)?;
+    Ok((status, body))
+}
+
+/// Asks Easee to start charging now, ignoring the charger's schedule.
+///
+/// Returns the status and body of Easee's answer, as the command may be refused.
+#[instrument(skip(session), level = "trace")]
+pub async fn override_schedule(
+    charger_id: &str,
+    session: Arc<Mutex<SessionState>>,
+) -> Result<(u16, String), EaseeError> {
+    let url = format!(
+        "{}/chargers/{}/commands/override_schedule",
+        easee_base(),
+        charger_id
+    );
+    info!("Overriding schedule of {}", log_id(charger_id));
+    let res = authorized_request(reqwest::Method::POST, &url, session).await?;
+    let status = res.status().as_u16();
+    let body = res.text().await.map_err(|_| EaseeError::HttpFailed)?;
+    debug!("Override schedule answered {}", status);
+    Ok((status, body))
+}
+
+struct SessionPages {
+    charger_id: String,
+    next_from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    session: Arc<Mutex<SessionState>>,
+    pending: VecDeque<ChargingSession>,
+    done: bool,
+}
+
+/// Streams the charging sessions of a charger between two dates, oldest window first.
+///
+/// The history is requested one date window at a time as the stream is consumed. A failed
+/// request is yielded as an error and ends the stream, keeping the sessions already yielded.
+pub fn get_charging_sessions(
+    charger_id: String,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    session: Arc<Mutex<SessionState>>,
+) -> impl Stream<Item = Result<ChargingSession, EaseeError>> {
+    let pages = SessionPages {
+        charger_id,
+        next_from: from,
+        to,
+        session,
+        pending: VecDeque::new(),
+        done: false,
+    };
+    stream::unfold(pages, |mut pages| async move {
+        loop {
+            if let Some(charging_session) = pages.pending.pop_front() {
+                return Some((Ok(charging_session), pages));
+            }
+            if pages.done || pages.next_from >= pages.to {
+                return None;
+            }
+            let window_end = std::cmp::min(
+                pages.next_from + Duration::days(SESSION_WINDOW_DAYS),
+                pages.to,
+            );
+            match request_charging_sessions(
+                &pages.charger_id,
+                pages.next_from,
+                window_end,
+                pages.session.to_owned(),
+            )
+            .await
             {
-                let _guard = parsing_span.enter();
+                Ok(sessions) => {
+                    pages.pending.extend(sessions);
+                    pages.next_from = window_end;
+                }
+                Err(e) => {
+                    pages.done = true;
+                    return Some((Err(e), pages));
+                }
+            }
+        }
+    })
+}
 
-                let body = res.text().await.map_err(|_| EaseeError::HttpFailed)?;
+#[instrument(skip(session), level = "trace")]
+async fn request_charging_sessions(
+    charger_id: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    session: Arc<Mutex<SessionState>>,
+) -> Result<Vec<ChargingSession>, EaseeError> {
+    let url = format!(
+        "{}/sessions/charger/{}/sessions/{}/{}",
+        easee_base(),
+        charger_id,
+        from.format("%Y-%m-%dT%H:%M:%SZ"),
+        to.format("%Y-%m-%dT%H:%M:%SZ")
+    );
+    let res = authorized_get(&url, session).await?;
+    if res.status().is_success() {
+        let body = res.text().await.map_err(|_| EaseeError::HttpFailed)?;
+        record_response("sessions", Some(charger_id), &body).await;
 
-                let json: serde_json::Value =
-                    serde_json::from_str(&body).map_err(|_| EaseeError::InvalidResponse)?;
-                let power = json["totalPower"]
-                    .as_f64()
-                    .ok_or(EaseeError::InvalidResponse)?;
-                let session = json["sessionEnergy"]
-                    .as_f64()
-                    .ok_or(EaseeError::InvalidResponse)?;
-                let energy_per_hour = json["energyPerHour"]
+        let json: serde_json::Value =
+            serde_json::from_str(&body).map_err(|_| EaseeError::InvalidResponse)?;
+        let mut sessions = Vec::new();
+        for s in json.as_array().ok_or(EaseeError::InvalidResponse)? {
+            sessions.push(ChargingSession {
+                charger_id: charger_id.to_string(),
+                session_id: s["sessionId"].as_i64(),
+                car_connected: s["carConnected"]
+                    .as_str()
+                    .and_then(parse_easee_time)
+                    .ok_or(EaseeError::InvalidResponse)?,
+                car_disconnected: s["carDisconnected"].as_str().and_then(parse_easee_time),
+                kwh: s["kiloWattHours"]
                     .as_f64()
-                    .ok_or(EaseeError::InvalidResponse)?;
-                charger_state = ChargerState {
-                    id: charger_id.to_string(),
-                    power,
-                    session,
-                    energy_per_hour,
-                };
-                debug!("Got charger state: {:?}", charger_state);
-            }
-            return Ok(charger_state);
+                    .ok_or(EaseeError::InvalidResponse)?,
+            });
+        }
+        debug!("Got {} charging sessions", sessions.len());
+        Ok(sessions)
+    } else {
+        if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            warn!("Rate limit exceeded");
+            Err(EaseeError::RateLimit)
         } else {
-            if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                warn!("Rate limit exceeded");
-                Err(EaseeError::RateLimit)
-            } else {
-                error!("Request failed: {}", res.status());
-                Err(EaseeError::Unathorized)
-            }
+            error!("Request failed: {}", res.status());
+            Err(EaseeError::HttpFailed)
         }
+    }
+}
+
+/// Easee timestamps are UTC, but not always suffixed with an offset.
+fn parse_easee_time(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+                .map(|t| Utc.from_utc_datetime(&t))
+        })
+        .ok()
+}
+
+/// During maintenance Easee answers 503 with an HTML page instead of JSON.
+fn is_maintenance(res: &reqwest::Response) -> bool {
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    is_maintenance_response(res.status(), content_type)
+}
+
+fn is_maintenance_response(status: reqwest::StatusCode, content_type: Option<&str>) -> bool {
+    let is_json = content_type.is_some_and(|v| v.contains("json"));
+    status == reqwest::StatusCode::SERVICE_UNAVAILABLE && !is_json
+}
+
+/// Backs off for a while and warns, throttled so a night of maintenance doesn't flood the log.
+fn enter_maintenance() -> EaseeError {
+    if MAINTENANCE.lock().unwrap().enter(Instant::now()) {
+        warn!(
+            "Easee is under maintenance, backing off for {}s",
+            MAINTENANCE_BACKOFF.as_secs()
+        );
     } else {
-        error!("No token after refresh");
-        unreachable!();
+        debug!("Easee is still under maintenance");
+    }
+    EaseeError::Maintenance
+}
+
+/// Seconds left of the maintenance backoff, if requests are held back.
+pub(crate) fn maintenance_remaining_secs() -> Option<u64> {
+    MAINTENANCE
+        .lock()
+        .unwrap()
+        .remaining(Instant::now())
+        .map(|remaining| remaining.as_secs())
+}
+
+fn in_maintenance() -> bool {
+    MAINTENANCE
+        .lock()
+        .unwrap()
+        .remaining(Instant::now())
+        .is_some()
+}
+
+async fn authorized_get(
+    url: &str,
+    session: Arc<Mutex<SessionState>>,
+) -> Result<reqwest::Response, EaseeError> {
+    authorized_request(reqwest::Method::GET, url, session).await
+}
+
+/// Sends an authorized request without holding the session lock across it.
+///
+/// If Easee rejects the token it is marked as expired and the request is retried once.
+/// While Easee is under maintenance no request is sent at all.
+#[instrument(skip(session), level = "trace")]
+async fn authorized_request(
+    method: reqwest::Method,
+    url: &str,
+    session: Arc<Mutex<SessionState>>,
+) -> Result<reqwest::Response, EaseeError> {
+    let client = http_client();
+    let class = match method {
+        reqwest::Method::GET => EndpointClass::Data,
+        _ => EndpointClass::Commands,
+    };
+
+    if in_maintenance() {
+        trace!("Skipping request during maintenance");
+        return Err(EaseeError::Maintenance);
+    }
+
+    refresh_auth(session.to_owned()).await?;
+    let token = current_token(&session).await?;
+    trace!("Using token: {}", token);
+    if !upstream_limit::acquire(class) {
+        return Err(EaseeError::RateLimit);
+    }
+    let res = client
+        .request(method.clone(), url)
+        .bearer_auth(&token)
+        .send()
+        .await
+        .map_err(|_| EaseeError::HttpFailed)?;
+    if is_maintenance(&res) {
+        return Err(enter_maintenance());
+    }
+    if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        upstream_limit::rate_limited(class, res.headers().get(reqwest::header::RETRY_AFTER));
+    }
+    if res.status() != reqwest::StatusCode::UNAUTHORIZED {
+        return Ok(res);
+    }
+
+    warn!("Token rejected, refreshing");
+    {
+        let mut mutex_guard = session.lock().await;
+        // Another task may already have replaced the rejected token
+        if mutex_guard.token.as_ref() == Some(&token) {
+            mutex_guard.lifetime = Some(Local::now());
+        }
+    }
+    refresh_auth(session.to_owned()).await?;
+    let token = current_token(&session).await?;
+    if !upstream_limit::acquire(class) {
+        return Err(EaseeError::RateLimit);
+    }
+    let res = client
+        .request(method, url)
+        .bearer_auth(&token)
+        .send()
+        .await
+        .map_err(|_| EaseeError::HttpFailed)?;
+    if is_maintenance(&res) {
+        return Err(enter_maintenance());
+    }
+    if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        upstream_limit::rate_limited(class, res.headers().get(reqwest::header::RETRY_AFTER));
     }
+    Ok(res)
 }
 
+/// The token after `refresh_auth`, gone if the session was cleared in between.
+async fn current_token(session: &Arc<Mutex<SessionState>>) -> Result<String, EaseeError> {
+    match session.lock().await.token {
+        Some(ref t) => Ok(t.clone()),
+        None => {
+            warn!("Session cleared after refreshing, dropping the request");
+            Err(EaseeError::Unathorized)
+        }
+    }
+}
+
+/// Logs in from scratch, replacing the session only if the login succeeds.
+#[instrument(skip_all, level = "trace")]
+pub async fn force_login(
+    session: Arc<Mutex<SessionState>>,
+) -> Result<Option<DateTime<Local>>, EaseeError> {
+    info!(target: AUTH_TARGET, source = "route", "Manual login requested");
+    let fresh = Arc::new(Mutex::new(SessionState::new()));
+    login(fresh.clone()).await?;
+    let fresh = std::mem::take(&mut *fresh.lock().await);
+    let expires = fresh.lifetime;
+    *session.lock().await = fresh;
+    Ok(expires)
+}
+
+/// Marks the token expired, so the next request refreshes it.
+pub async fn expire_session(session: &Arc<Mutex<SessionState>>) {
+    let mut session = session.lock().await;
+    if session.token.is_some() {
+        session.lifetime = Some(Local::now());
+        info!(target: AUTH_TARGET, "Token expiry no longer trusted");
+    }
+}
+
+/// Forgets the session, so the next request logs in again.
+#[instrument(skip_all, level = "trace")]
+pub async fn clear_session(session: Arc<Mutex<SessionState>>) {
+    *session.lock().await = SessionState::new();
+    info!("Session cleared");
+    info!(target: AUTH_TARGET, source = "route", "Session cleared");
+}
+
+/// Logs in unless too many logins failed in a row, see `lockout`.
 #[instrument(skip_all, ret, level = "trace")]
 async fn login(session: Arc<Mutex<SessionState>>) -> Result<(), EaseeError> {
-    tracing::trace!("Creating client");
-    let client = reqwest::Client::builder();
-    tracing::trace!("building client");
-    let client = client.build();
-    tracing::trace!("client built");
-    if let Err(e) = &client {
-        tracing::error!("Failed to create client: {}", e);
+    if let Some(until) = lockout::check() {
+        debug!("Logins locked out until {}", until);
+        return Err(EaseeError::LoginLockout);
+    }
+    let result = attempt_login(session).await;
+    match result {
+        Ok(()) => lockout::succeeded(),
+        Err(EaseeError::LoginFailed) => lockout::failed(),
+        Err(_) => (),
     }
-    let client = client.unwrap();
+    result
+}
 
+async fn attempt_login(session: Arc<Mutex<SessionState>>) -> Result<(), EaseeError> {
     let mut payload = HashMap::new();
 
-    tracing::trace!("Attempt to get credentials from env");
-    let usr = env::var("USERNAME");
-    let pwd = env::var("PASSWORD");
-    if usr.is_ok() && pwd.is_ok() {
-        tracing::info!("Credentials loaded from env");
-        payload.insert("username", usr.unwrap());
-        payload.insert("password", pwd.unwrap());
-        tracing::trace!("Inserted credentials");
-    } else {
-        tracing::trace!("Credentials not found in env");
-        tracing::trace!("Attempt to load credentials");
-        let file = env::var("CREDENTIALS_FILE").ok();
-        let file_str = (&file).as_ref().map(|x| x.as_str());
-        let creds = local_credentials::async_get_credentials(file_str)
-            .await
-            .map_err(|e| {
-                tracing::error!("Failed to load credentials: {}", e);
-                EaseeError::LoginFailed
-            })?;
-        tracing::info!("Credentials loaded from file");
-        payload.insert("userName", creds.username);
-        payload.insert("password", creds.password);
-        tracing::trace!("Inserted credentials");
+    let source = credentials::source();
+    let unavailable = |e: String| {
+        tracing::error!("Failed to load credentials: {}", e);
+        warn!(
+            target: AUTH_TARGET,
+            source = source.as_str(),
+            outcome = "credentials_unavailable",
+            "Login failed"
+        );
+        EaseeError::LoginFailed
+    };
+    tracing::trace!("Attempt to load credentials from {}", source);
+    match source {
+        CredentialsSource::Env => {
+            let usr = env::var("USERNAME").map_err(|e| unavailable(e.to_string()))?;
+            let pwd = env::var("PASSWORD").map_err(|e| unavailable(e.to_string()))?;
+            payload.insert("username", usr);
+            payload.insert("password", pwd);
+        }
+        CredentialsSource::File(file) => {
+            let creds = local_credentials::async_get_credentials(file.as_deref())
+                .await
+                .map_err(|e| unavailable(e.to_string()))?;
+            payload.insert("userName", creds.username);
+            payload.insert("password", creds.password);
+        }
+        CredentialsSource::Keyring => {
+            let (usr, pwd) = credentials::from_keyring().map_err(unavailable)?;
+            payload.insert("userName", usr);
+            payload.insert("password", pwd);
+        }
     }
+    tracing::info!("Credentials loaded from {}", source);
+    info!(target: AUTH_TARGET, source = source.as_str(), "Login attempt");
+    tracing::trace!("Inserted credentials");
 
+    if !upstream_limit::acquire(EndpointClass::Auth) {
+        warn!(target: AUTH_TARGET, outcome = "rate_limited", "Login failed");
+        return Err(EaseeError::RateLimit);
+    }
     debug!("Sending login request");
-    let response = client
-        .post(LOGIN_ENDPOINT)
-        .json(&payload)
-        .header("Content-type", "application/json")
-        .send()
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to send login request: {}", e);
-            EaseeError::HttpFailed
-        })?;
+    let response = LOGIN_ENDPOINT.post(&payload).await.inspect_err(|_| {
+        warn!(target: AUTH_TARGET, outcome = "http_failed", "Login failed");
+    })?;
+    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        upstream_limit::rate_limited(
+            EndpointClass::Auth,
+            response.headers().get(reqwest::header::RETRY_AFTER),
+        );
+        // Not a rejection of the credentials, so it doesn't count towards the lockout
+        warn!(target: AUTH_TARGET, outcome = "rate_limited", "Login failed");
+        return Err(EaseeError::RateLimit);
+    }
 
     if response.status().is_success() {
         let body = response.text().await.map_err(|_| EaseeError::HttpFailed)?;
+        record_response("login", None, &body).await;
         debug!("Got response: {}", body);
 
         let parsing_span = span!(Level::TRACE, "parsing_response");
@@ -238,9 +1080,11 @@ async fn login(session: Arc<Mutex<SessionState>>) -> Result<(), EaseeError> {
                     })?,
             );
             debug!("Token: {}", token);
+            check_token_lifetime(Duration::seconds(duration), get_interval());
         }
 
         info!("Login success");
+        info!(target: AUTH_TARGET, outcome = "success", "Login succeeded");
         Ok(())
     } else {
         error!(
@@ -248,16 +1092,18 @@ async fn login(session: Arc<Mutex<SessionState>>) -> Result<(), EaseeError> {
             response.status(),
             response.status().canonical_reason()
         );
+        warn!(
+            target: AUTH_TARGET,
+            outcome = "rejected",
+            status = response.status().as_u16(),
+            "Login failed"
+        );
         Err(EaseeError::LoginFailed)
     }
 }
 
 #[instrument(skip_all, level = "trace")]
 async fn refresh_token(session: Arc<Mutex<SessionState>>) -> Result<(), EaseeError> {
-    let client = reqwest::Client::new();
-
-    let mut payload = HashMap::new();
-
     if session.lock().await.refresh_token.is_none() {
         warn!("No refresh token, logging in");
         login(session).await?;
@@ -270,30 +1116,49 @@ async fn refresh_token(session: Arc<Mutex<SessionState>>) -> Result<(), EaseeErr
         return Ok(());
     }
 
-    let response;
-    // Ok to unwrap as the two checks above *should* ensure they are `Some`. If they fail the function will return before this point.
-    {
+    // Cloned so the session isn't locked during the request
+    let tokens = {
         let mutex_guard = session.lock().await;
-        let refresh_token = mutex_guard.refresh_token.as_ref().unwrap();
-        let token = mutex_guard.token.as_ref().unwrap();
-        payload.insert("refreshToken", refresh_token);
-        payload.insert("accessToken", token);
-
-        debug!("Sending token refresh request");
-        response = client
-            .post(REFRESH_ENDPOINT)
-            .json(&payload)
-            .header("Content-type", "application/json")
-            .send()
-            .await
-            .map_err(|_| EaseeError::HttpFailed)?;
+        mutex_guard
+            .refresh_token
+            .clone()
+            .zip(mutex_guard.token.clone())
+    };
+    let (used_refresh_token, used_token) = match tokens {
+        Some(tokens) => tokens,
+        None => {
+            warn!("Session cleared before refreshing, logging in");
+            return login(session).await;
+        }
+    };
+    let mut payload = HashMap::new();
+    payload.insert("refreshToken", &used_refresh_token);
+    payload.insert("accessToken", &used_token);
+
+    if !upstream_limit::acquire(EndpointClass::Auth) {
+        warn!(target: AUTH_TARGET, outcome = "rate_limited", "Token refresh failed");
+        return Err(EaseeError::RateLimit);
+    }
+    debug!("Sending token refresh request");
+    info!(target: AUTH_TARGET, source = "token", "Token refresh attempt");
+    let response = REFRESH_ENDPOINT.post(&payload).await.inspect_err(|_| {
+        warn!(target: AUTH_TARGET, outcome = "http_failed", "Token refresh failed");
+    })?;
+    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        upstream_limit::rate_limited(
+            EndpointClass::Auth,
+            response.headers().get(reqwest::header::RETRY_AFTER),
+        );
+        warn!(target: AUTH_TARGET, outcome = "rate_limited", "Token refresh failed");
+        return Err(EaseeError::RateLimit);
     }
     if response.status().is_success() {
         let body = response.text().await.map_err(|_| EaseeError::HttpFailed)?;
+        record_response("refresh_token", None, &body).await;
         debug!("Got response: {}", body);
 
         let parsing_span = span!(Level::TRACE, "parsing_response");
-        {
+        let cleared = {
             let _guard = parsing_span.enter();
 
             let json: serde_json::Value =
@@ -309,40 +1174,436 @@ async fn refresh_token(session: Arc<Mutex<SessionState>>) -> Result<(), EaseeErr
                 .as_i64()
                 .ok_or(EaseeError::InvalidResponse)?;
             let mut mutex_guard = session.lock().await;
-            mutex_guard.token = Some(token.to_string());
-            mutex_guard.refresh_token = Some(refresh_token.to_string());
-            mutex_guard.lifetime = Some(
-                Local::now()
-                    .checked_add_signed(Duration::seconds(duration))
-                    .ok_or(EaseeError::InvalidResponse)?,
-            );
-            debug!("Token: {}", token);
+            if mutex_guard.refresh_token.as_ref() == Some(&used_refresh_token) {
+                mutex_guard.token = Some(token.to_string());
+                mutex_guard.refresh_token = Some(refresh_token.to_string());
+                mutex_guard.lifetime = Some(
+                    Local::now()
+                        .checked_add_signed(Duration::seconds(duration))
+                        .ok_or(EaseeError::InvalidResponse)?,
+                );
+                debug!("Token: {}", token);
+                check_token_lifetime(Duration::seconds(duration), get_interval());
+                false
+            } else if mutex_guard.token.is_some() {
+                // Another task refreshed or logged in while we waited, keep its tokens
+                debug!("Session changed during the refresh, discarding the response");
+                return Ok(());
+            } else {
+                true
+            }
+        };
+        // Cleared while we waited, the response belongs to the forgotten session
+        if cleared {
+            warn!("Session cleared during the refresh, logging in");
+            return login(session).await;
         }
 
         info!("Token refreshed");
+        info!(target: AUTH_TARGET, outcome = "success", "Token refresh succeeded");
         Ok(())
     } else {
-        error!("Token refresh failed");
-        Err(EaseeError::LoginFailed)
+        error!("Token refresh failed, logging in");
+        warn!(
+            target: AUTH_TARGET,
+            outcome = "rejected",
+            status = response.status().as_u16(),
+            "Token refresh failed"
+        );
+        // The refresh token is of no further use, only a login can renew the session
+        login(session).await
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenLifetime {
+    Ok,
+    ShorterThanInterval,
+    Suspicious,
+}
+
+impl TokenLifetime {
+    pub fn classify(lifetime: Duration, interval: Duration) -> Self {
+        if lifetime < Duration::seconds(SUSPICIOUS_TOKEN_LIFETIME_SECS) {
+            TokenLifetime::Suspicious
+        } else if lifetime < interval {
+            TokenLifetime::ShorterThanInterval
+        } else {
+            TokenLifetime::Ok
+        }
+    }
+}
+
+/// A token outliving less than a polling interval has to be refreshed on every tick.
+fn check_token_lifetime(lifetime: Duration, interval: Duration) {
+    match TokenLifetime::classify(lifetime, interval) {
+        TokenLifetime::Ok => trace!("Token lifetime: {}s", lifetime.num_seconds()),
+        TokenLifetime::ShorterThanInterval => warn!(
+            "Token lifetime of {}s is shorter than the polling interval of {}s",
+            lifetime.num_seconds(),
+            interval.num_seconds()
+        ),
+        TokenLifetime::Suspicious => error!(
+            "Token lifetime of {}s is suspiciously short, polling interval is {}s",
+            lifetime.num_seconds(),
+            interval.num_seconds()
+        ),
+    }
+}
+
+/// Refreshes the token this long before it expires, from `SESSION_REFRESH_MARGIN_SECS`.
+fn refresh_margin() -> Duration {
+    static MARGIN: OnceLock<Duration> = OnceLock::new();
+    *MARGIN.get_or_init(|| {
+        let margin = env::var("SESSION_REFRESH_MARGIN_SECS").map_or(60, |m| {
+            m.parse().expect("Illegal session refresh margin format")
+        });
+        tracing::info!("SESSION_REFRESH_MARGIN_SECS: {}", margin);
+        Duration::seconds(margin)
+    })
+}
+
+/// Time waited after the first failed scheduled refresh, doubling up to `MAX_REFRESH_BACKOFF`.
+const REFRESH_BACKOFF: std::time::Duration = std::time::Duration::from_secs(30);
+const MAX_REFRESH_BACKOFF: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// Refreshes the token shortly before it expires, so requests rarely have to wait for a
+/// refresh themselves. Before the first login it only waits, as requests log in anyway.
+#[instrument(skip_all, level = "trace")]
+pub async fn keep_session_fresh(session: Arc<Mutex<SessionState>>, shutdown: CancellationToken) {
+    let margin = refresh_margin();
+    let mut backoff = REFRESH_BACKOFF;
+    loop {
+        let lifetime = session.lock().await.lifetime;
+        let wait = match lifetime {
+            // Tokens living less than twice the margin are refreshed half way instead
+            Some(lifetime) => {
+                let left = lifetime - Local::now();
+                (left - margin.min(left / 2)).to_std().unwrap_or_default()
+            }
+            None => REFRESH_BACKOFF,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => (),
+            _ = shutdown.cancelled() => {
+                debug!("Stopping scheduled session refresh");
+                return;
+            }
+        }
+
+        let refreshed = {
+            let _auth = auth_lock().lock().await;
+            // A request may have logged in or refreshed while we slept
+            if lifetime.is_none() || session.lock().await.lifetime != lifetime {
+                continue;
+            }
+            debug!("Refreshing token ahead of expiry");
+            match refresh_token(session.clone()).await {
+                Ok(()) => match session.lock().await.lifetime {
+                    Some(lifetime) if lifetime > Local::now() => Ok(()),
+                    _ => Err(String::from("the new token has already expired")),
+                },
+                Err(e) => Err(e.to_string()),
+            }
+        };
+        match refreshed {
+            Ok(()) => backoff = REFRESH_BACKOFF,
+            Err(e) => {
+                warn!(
+                    "Scheduled token refresh failed, retrying in {}s: {}",
+                    backoff.as_secs(),
+                    e
+                );
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => (),
+                    _ = shutdown.cancelled() => return,
+                }
+                backoff = (backoff * 2).min(MAX_REFRESH_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Held while logging in or refreshing, so concurrent requests finding the session stale
+/// wait for one login instead of each logging in.
+fn auth_lock() -> &'static Mutex<()> {
+    static AUTH_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+    AUTH_LOCK.get_or_init(|| Mutex::new(()))
+}
+
 #[instrument(skip_all, level = "trace")]
 async fn refresh_auth(session: Arc<Mutex<SessionState>>) -> Result<(), EaseeError> {
+    let _auth = auth_lock().lock().await;
     let mutex_guard = session.lock().await;
-    if mutex_guard.token.is_some() && mutex_guard.lifetime.is_some() {
-        // Safe to unwrap as above checks that lifetime is some
-        if mutex_guard.lifetime.unwrap() > Local::now() {
+    if let (Some(_), Some(lifetime)) = (&mutex_guard.token, mutex_guard.lifetime) {
+        if lifetime > Local::now() {
             debug!("Token is still valid");
         } else {
             debug!("Token expired");
+            info!(target: AUTH_TARGET, "Token expired");
             drop(mutex_guard);
             refresh_token(session).await?;
         }
     } else {
         debug!("Performing first login");
+        info!(target: AUTH_TARGET, "No session, logging in");
         drop(mutex_guard);
         login(session).await?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_token_lifetimes() {
+        let minute = Duration::minutes(1);
+        let cases = [
+            (Duration::hours(1), minute, TokenLifetime::Ok),
+            (minute, minute, TokenLifetime::Ok),
+            (
+                Duration::seconds(90),
+                Duration::minutes(2),
+                TokenLifetime::ShorterThanInterval,
+            ),
+            (Duration::seconds(59), minute, TokenLifetime::Suspicious),
+            (
+                Duration::seconds(59),
+                Duration::seconds(10),
+                TokenLifetime::Suspicious,
+            ),
+            (Duration::zero(), minute, TokenLifetime::Suspicious),
+            (Duration::seconds(-5), minute, TokenLifetime::Suspicious),
+        ];
+        for (lifetime, interval, expected) in cases {
+            assert_eq!(
+                TokenLifetime::classify(lifetime, interval),
+                expected,
+                "{} with {}",
+                lifetime,
+                interval
+            );
+        }
+    }
+
+    fn ids(body: &str) -> Vec<(String, Option<String>)> {
+        parse_charger_list(body)
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.id, c.name))
+            .collect()
+    }
+
+    fn named(id: &str, name: &str) -> (String, Option<String>) {
+        (id.to_string(), Some(name.to_string()))
+    }
+
+    #[test]
+    fn parses_the_flat_list() {
+        assert_eq!(
+            ids(include_str!("../../tests/fixtures/chargers.json")),
+            vec![named("EH000001", "Garage"), named("EH000002", "Driveway")]
+        );
+        assert!(ids("[]").is_empty());
+    }
+
+    #[test]
+    fn parses_chargers_nested_in_products_sites_and_circuits() {
+        let body = r#"[{
+            "products": [{"id": "EH000001", "name": "Garage"}],
+            "sites": [{
+                "id": 123,
+                "circuits": [
+                    {"id": 1, "chargers": [{"id": "EH000002"}]},
+                    {"id": 2, "chargers": [{"id": "EH000003", "name": "Barn"}]}
+                ]
+            }]
+        }]"#;
+        assert_eq!(
+            ids(body),
+            vec![
+                named("EH000001", "Garage"),
+                (String::from("EH000002"), None),
+                named("EH000003", "Barn"),
+            ]
+        );
+        // A single object rather than an array
+        assert_eq!(
+            ids(r#"{"chargers": [{"id": "EH000001", "name": "Garage"}]}"#),
+            vec![named("EH000001", "Garage")]
+        );
+    }
+
+    #[test]
+    fn refuses_unknown_shapes() {
+        for body in [
+            "",
+            "<html>",
+            r#"[{"name": "no id"}]"#,
+            r#"[{"id": 5}]"#,
+            r#"["EH000001"]"#,
+            "null",
+        ] {
+            assert!(
+                matches!(parse_charger_list(body), Err(EaseeError::InvalidResponse)),
+                "{:?}",
+                body
+            );
+        }
+    }
+
+    fn offsets(count: usize, window_secs: u64) -> Vec<StdDuration> {
+        (0..count)
+            .map(|i| spread_offset(i, count, StdDuration::from_secs(window_secs)))
+            .collect()
+    }
+
+    #[test]
+    fn one_charger_goes_first() {
+        assert_eq!(offsets(1, 30), vec![StdDuration::ZERO]);
+    }
+
+    #[test]
+    fn three_chargers_spread_over_the_window() {
+        assert_eq!(
+            offsets(3, 30),
+            vec![
+                StdDuration::ZERO,
+                StdDuration::from_secs(10),
+                StdDuration::from_secs(20)
+            ]
+        );
+    }
+
+    #[test]
+    fn ten_chargers_spread_evenly_within_the_window() {
+        let offsets = offsets(10, 5);
+        assert_eq!(offsets[0], StdDuration::ZERO);
+        for pair in offsets.windows(2) {
+            assert_eq!(pair[1] - pair[0], StdDuration::from_millis(500));
+        }
+        assert!(*offsets.last().unwrap() < StdDuration::from_secs(5));
+    }
+
+    #[test]
+    fn no_window_sends_back_to_back() {
+        assert!(offsets(10, 0).iter().all(|o| o.is_zero()));
+        assert_eq!(
+            spread_offset(0, 0, StdDuration::from_secs(30)),
+            StdDuration::ZERO
+        );
+    }
+
+    #[test]
+    fn resolve_overrides_are_parsed() {
+        let overrides =
+            parse_resolve_overrides(" api.easee.cloud=203.0.113.10:443, ,other=[::1]:8443")
+                .unwrap();
+        assert_eq!(
+            overrides,
+            vec![
+                (
+                    String::from("api.easee.cloud"),
+                    "203.0.113.10:443".parse().unwrap()
+                ),
+                (String::from("other"), "[::1]:8443".parse().unwrap()),
+            ]
+        );
+        assert!(parse_resolve_overrides("").unwrap().is_empty());
+        for bad in [
+            "api.easee.cloud",
+            "=203.0.113.10:443",
+            "api.easee.cloud=203.0.113.10",
+            "api.easee.cloud=api.example:443",
+        ] {
+            assert!(parse_resolve_overrides(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn client_options_are_checked() {
+        assert!(build_http_client(&HttpClientOptions::default()).is_ok());
+        let overrides = HttpClientOptions {
+            resolve_override: Some(String::from("api.easee.cloud=203.0.113.10:443")),
+            accept_invalid_certs: true,
+            ..HttpClientOptions::default()
+        };
+        assert!(build_http_client(&overrides).is_ok());
+
+        let error = build_http_client(&HttpClientOptions {
+            resolve_override: Some(String::from("api.easee.cloud")),
+            ..HttpClientOptions::default()
+        })
+        .unwrap_err();
+        assert!(error.contains("EASEE_RESOLVE_OVERRIDE"), "{}", error);
+    }
+
+    #[test]
+    fn the_extra_ca_cert_must_be_a_readable_certificate() {
+        let cert = |path: &str| HttpClientOptions {
+            extra_ca_cert: Some(path.to_string()),
+            ..HttpClientOptions::default()
+        };
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+        assert!(build_http_client(&cert(&format!("{}/ca.pem", fixtures))).is_ok());
+
+        let error = build_http_client(&cert("/nonexistent/ca.pem")).unwrap_err();
+        assert!(error.contains("/nonexistent/ca.pem"), "{}", error);
+        // Not a certificate
+        assert!(build_http_client(&cert(&format!("{}/login.json", fixtures))).is_err());
+    }
+
+    #[test]
+    fn maintenance_is_a_503_without_json() {
+        use reqwest::StatusCode;
+
+        let cases = [
+            (StatusCode::SERVICE_UNAVAILABLE, Some("text/html"), true),
+            (StatusCode::SERVICE_UNAVAILABLE, None, true),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Some("application/json; charset=utf-8"),
+                false,
+            ),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Some("application/problem+json"),
+                false,
+            ),
+            (StatusCode::OK, Some("text/html"), false),
+            (StatusCode::BAD_GATEWAY, Some("text/html"), false),
+        ];
+        for (status, content_type, expected) in cases {
+            assert_eq!(
+                is_maintenance_response(status, content_type),
+                expected,
+                "{} {:?}",
+                status,
+                content_type
+            );
+        }
+    }
+
+    #[test]
+    fn maintenance_backs_off_and_warns_once_per_interval() {
+        let start = Instant::now();
+        let minutes = |m: u64| start + StdDuration::from_secs(m * 60);
+        let mut state = MaintenanceState::default();
+        assert_eq!(state.remaining(start), None);
+
+        assert!(state.enter(start));
+        assert_eq!(state.remaining(start), Some(MAINTENANCE_BACKOFF));
+        // Still under maintenance: the backoff is extended without another warning
+        assert!(!state.enter(minutes(4)));
+        assert_eq!(
+            state.remaining(minutes(8)),
+            Some(StdDuration::from_secs(60))
+        );
+        assert_eq!(state.remaining(minutes(9)), None);
+        assert!(!state.enter(minutes(9)));
+        assert!(state.enter(minutes(10)));
+        assert!(!state.enter(minutes(11)));
+    }
+}
@@ -0,0 +1,155 @@
+use rocket::{
+    http::{ContentType, MediaType, Status},
+    request::{FromRequest, Outcome},
+    response::{self, Responder},
+    serde::json::Json,
+    Request, Response,
+};
+use serde::Serialize;
+use tracing::error;
+
+/// The format a response body is serialized in, picked from the `Accept` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    Cbor,
+    MessagePack,
+}
+
+impl Encoding {
+    /// The first of the media types we can serve, JSON when none is.
+    pub fn negotiate<'a>(accepted: impl IntoIterator<Item = &'a MediaType>) -> Self {
+        accepted
+            .into_iter()
+            .find_map(Encoding::from_media_type)
+            .unwrap_or_default()
+    }
+
+    fn from_media_type(media_type: &MediaType) -> Option<Self> {
+        if media_type.top() != "application" {
+            return None;
+        }
+        let sub = media_type.sub().as_str();
+        if sub.eq_ignore_ascii_case("json") {
+            Some(Encoding::Json)
+        } else if sub.eq_ignore_ascii_case("cbor") {
+            Some(Encoding::Cbor)
+        } else if sub.eq_ignore_ascii_case("msgpack") || sub.eq_ignore_ascii_case("x-msgpack") {
+            Some(Encoding::MessagePack)
+        } else {
+            None
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Encoding {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let encoding = match req.accept() {
+            Some(accept) => Encoding::negotiate(accept.iter().map(|q| q.media_type())),
+            None => Encoding::Json,
+        };
+        Outcome::Success(encoding)
+    }
+}
+
+/// A body in the negotiated encoding, from the same `Serialize` impl as the JSON.
+#[derive(Debug)]
+pub struct Encoded<T> {
+    encoding: Encoding,
+    value: T,
+}
+
+impl<T: Serialize> Encoded<T> {
+    pub fn new(encoding: Encoding, value: T) -> Self {
+        Encoded { encoding, value }
+    }
+}
+
+/// Serializes to CBOR, or MessagePack with structs as maps so the keys match the JSON.
+pub fn to_bytes<T: Serialize>(encoding: Encoding, value: &T) -> Result<Vec<u8>, String> {
+    match encoding {
+        Encoding::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+        Encoding::Cbor => {
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(value, &mut bytes).map_err(|e| e.to_string())?;
+            Ok(bytes)
+        }
+        Encoding::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+    }
+}
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Encoded<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let response = match self.encoding {
+            Encoding::Json => Json(self.value).respond_to(req)?,
+            binary => {
+                let bytes = to_bytes(binary, &self.value).map_err(|e| {
+                    error!("Serializing as {:?} failed: {}", binary, e);
+                    Status::InternalServerError
+                })?;
+                let content_type = match binary {
+                    Encoding::Cbor => ContentType::new("application", "cbor"),
+                    _ => ContentType::MsgPack,
+                };
+                (content_type, bytes).respond_to(req)?
+            }
+        };
+        Response::build_from(response)
+            .raw_header("Vary", "Accept")
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::structs::ChargerState;
+
+    fn negotiated(accept: &str) -> Encoding {
+        let types: Vec<MediaType> = accept
+            .split(',')
+            .map(|t| MediaType::parse_flexible(t.trim()).unwrap())
+            .collect();
+        Encoding::negotiate(types.iter())
+    }
+
+    #[test]
+    fn negotiates_the_first_supported_type() {
+        assert_eq!(negotiated("application/cbor"), Encoding::Cbor);
+        assert_eq!(negotiated("application/msgpack"), Encoding::MessagePack);
+        assert_eq!(negotiated("application/x-msgpack"), Encoding::MessagePack);
+        assert_eq!(negotiated("text/html, application/CBOR"), Encoding::Cbor);
+        assert_eq!(
+            negotiated("application/json, application/cbor"),
+            Encoding::Json
+        );
+        assert_eq!(negotiated("text/html"), Encoding::Json);
+        assert_eq!(negotiated("*/*"), Encoding::Json);
+        assert_eq!(Encoding::negotiate([]), Encoding::Json);
+    }
+
+    #[test]
+    fn binary_encodings_decode_to_the_json() {
+        let state = ChargerState::builder("EH000001")
+            .name("Garage")
+            .power(11.04)
+            .session(4.5)
+            .reason_for_no_current(0)
+            .output_current(16.0)
+            .build();
+        let json: serde_json::Value =
+            serde_json::from_slice(&to_bytes(Encoding::Json, &state).unwrap()).unwrap();
+
+        let cbor = to_bytes(Encoding::Cbor, &state).unwrap();
+        let from_cbor: serde_json::Value = ciborium::de::from_reader(cbor.as_slice()).unwrap();
+        assert_eq!(from_cbor, json);
+
+        let msgpack = to_bytes(Encoding::MessagePack, &state).unwrap();
+        let from_msgpack: serde_json::Value = rmp_serde::from_slice(&msgpack).unwrap();
+        assert_eq!(from_msgpack, json);
+    }
+}
@@ -0,0 +1,102 @@
+use std::env;
+
+use chrono::{DateTime, Duration, Utc};
+use influxdb::InfluxDbWriteable;
+use tracing::{instrument, warn};
+
+/// Measurement gap markers are written to.
+pub const GAP_MEASUREMENT: &str = "gap";
+
+/// Notices when ticks stop for longer than twice the interval, e.g. while the machine sleeps.
+#[derive(Debug)]
+pub struct GapDetector {
+    interval: Duration,
+    write_markers: bool,
+    last_tick: Option<DateTime<Utc>>,
+}
+
+/// A stretch of time without data, written so dashboards can annotate it.
+#[derive(Debug, Clone, InfluxDbWriteable)]
+pub struct Gap {
+    /// When polling resumed.
+    pub time: DateTime<Utc>,
+    pub started: String,
+    pub seconds: i64,
+}
+
+impl GapDetector {
+    pub fn new(interval: Duration, write_markers: bool) -> Self {
+        GapDetector {
+            interval,
+            write_markers,
+            last_tick: None,
+        }
+    }
+
+    #[instrument(level = "trace")]
+    pub fn from_env(interval: Duration) -> Self {
+        let write_markers = env::var("GAP_MARKERS").is_ok_and(|g| g == "true");
+        tracing::info!("GAP_MARKERS: {}", write_markers);
+        GapDetector::new(interval, write_markers)
+    }
+
+    /// Records a tick, returning the gap before it if one should be written.
+    pub fn observe(&mut self, now: DateTime<Utc>) -> Option<Gap> {
+        let last = self.last_tick.replace(now)?;
+        let pause = now - last;
+        if pause <= self.interval * 2 {
+            return None;
+        }
+        warn!(
+            "No ticks for {}s since {}, expected one every {}s",
+            pause.num_seconds(),
+            last,
+            self.interval.num_seconds()
+        );
+        self.write_markers.then(|| Gap {
+            time: now,
+            started: last.to_rfc3339(),
+            seconds: pause.num_seconds(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn a_delayed_tick_is_a_gap() {
+        let mut detector = GapDetector::new(Duration::seconds(60), true);
+        assert!(detector.observe(at(0)).is_none());
+        assert!(detector.observe(at(60)).is_none());
+        // Up to twice the interval is still on time
+        assert!(detector.observe(at(180)).is_none());
+        let gap = detector.observe(at(600)).unwrap();
+        assert_eq!(gap.time, at(600));
+        assert_eq!(gap.started, at(180).to_rfc3339());
+        assert_eq!(gap.seconds, 420);
+        assert!(detector.observe(at(660)).is_none());
+    }
+
+    #[test]
+    fn gaps_are_only_written_with_markers() {
+        let mut detector = GapDetector::new(Duration::seconds(60), false);
+        detector.observe(at(0));
+        assert!(detector.observe(at(600)).is_none());
+    }
+
+    #[test]
+    fn a_restart_starts_without_a_gap() {
+        // The last tick isn't persisted, a new detector has nothing to compare with
+        let mut detector = GapDetector::new(Duration::seconds(60), true);
+        detector.observe(at(0));
+        let mut restarted = GapDetector::new(Duration::seconds(60), true);
+        assert!(restarted.observe(at(3600)).is_none());
+        assert!(restarted.observe(at(3660)).is_none());
+    }
+}
@@ -0,0 +1,100 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use super::{record::redact_text, structs::EaseeError};
+
+/// The most recent Easee and InfluxDB errors, reported by `/health`.
+#[derive(Debug, Default)]
+pub struct LastErrors {
+    inner: Mutex<LastErrorsInner>,
+}
+
+#[derive(Debug, Default)]
+struct LastErrorsInner {
+    ticks: u64,
+    easee: Option<RecordedError>,
+    influxdb: Option<RecordedError>,
+    influxdb_ok_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+struct RecordedError {
+    kind: String,
+    message: String,
+    at: DateTime<Utc>,
+    tick: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+    pub kind: String,
+    pub message: String,
+    pub at: DateTime<Utc>,
+    pub ticks_ago: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorsReport {
+    pub easee: Option<ErrorReport>,
+    pub influxdb: Option<ErrorReport>,
+}
+
+impl LastErrors {
+    pub fn new() -> Self {
+        LastErrors::default()
+    }
+
+    pub async fn tick(&self) {
+        self.inner.lock().await.ticks += 1;
+    }
+
+    pub async fn easee_failed(&self, e: &EaseeError) {
+        let mut inner = self.inner.lock().await;
+        inner.easee = Some(RecordedError {
+            kind: format!("{:?}", e),
+            message: redact_text(&e.to_string()),
+            at: Utc::now(),
+            tick: inner.ticks,
+        });
+    }
+
+    pub async fn easee_succeeded(&self) {
+        self.inner.lock().await.easee = None;
+    }
+
+    pub async fn influxdb_failed(&self, message: &str) {
+        let mut inner = self.inner.lock().await;
+        inner.influxdb = Some(RecordedError {
+            kind: String::from("WriteFailed"),
+            message: redact_text(message),
+            at: Utc::now(),
+            tick: inner.ticks,
+        });
+    }
+
+    pub async fn influxdb_succeeded(&self) {
+        let mut inner = self.inner.lock().await;
+        inner.influxdb = None;
+        inner.influxdb_ok_at = Some(Utc::now());
+    }
+
+    /// When the poller last wrote to InfluxDB without errors.
+    pub async fn last_influxdb_write(&self) -> Option<DateTime<Utc>> {
+        self.inner.lock().await.influxdb_ok_at
+    }
+
+    pub async fn report(&self) -> ErrorsReport {
+        let inner = self.inner.lock().await;
+        let report = |e: &RecordedError| ErrorReport {
+            kind: e.kind.clone(),
+            message: e.message.clone(),
+            at: e.at,
+            ticks_ago: inner.ticks - e.tick,
+        };
+        ErrorsReport {
+            easee: inner.easee.as_ref().map(report),
+            influxdb: inner.influxdb.as_ref().map(report),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+use super::structs::ChargerState;
+
+/// Integrates each charger's power over the tick times, trapezoid by trapezoid, into the
+/// energy drawn since the process started. It starts over from zero on a restart.
+///
+/// Ticks further apart than `max_gap` add nothing, as what happened between them is unknown.
+#[derive(Debug)]
+pub struct EnergyIntegrator {
+    max_gap: Duration,
+    chargers: HashMap<String, Integral>,
+}
+
+#[derive(Debug)]
+struct Integral {
+    last_time: DateTime<Utc>,
+    last_power: f64,
+    kwh: f64,
+}
+
+impl EnergyIntegrator {
+    pub fn new(max_gap: Duration) -> Self {
+        EnergyIntegrator {
+            max_gap,
+            chargers: HashMap::new(),
+        }
+    }
+
+    /// Three intervals may pass between ticks before they count as a gap.
+    pub fn for_interval(interval: Duration) -> Self {
+        EnergyIntegrator::new(interval * 3)
+    }
+
+    /// Adds the energy drawn since the charger was last observed, returning the total in kWh.
+    pub fn observe(&mut self, charger: &ChargerState, time: DateTime<Utc>) -> f64 {
+        let max_gap = self.max_gap;
+        let integral = self.chargers.entry(charger.id.clone()).or_insert(Integral {
+            last_time: time,
+            last_power: charger.power,
+            kwh: 0.0,
+        });
+        let elapsed = time - integral.last_time;
+        if elapsed > Duration::zero() && elapsed <= max_gap {
+            let hours = elapsed.num_milliseconds() as f64 / 3_600_000.0;
+            integral.kwh += (integral.last_power + charger.power) / 2.0 * hours;
+        }
+        integral.last_time = time;
+        integral.last_power = charger.power;
+        integral.kwh
+    }
+
+    /// The energy drawn since the charger was first observed, 0 for a charger not seen yet.
+    pub fn total(&self, charger_id: &str) -> f64 {
+        self.chargers
+            .get(charger_id)
+            .map_or(0.0, |integral| integral.kwh)
+    }
+
+    pub fn forget(&mut self, charger_id: &str) {
+        self.chargers.remove(charger_id);
+    }
+
+    pub fn totals(&self) -> HashMap<String, f64> {
+        self.chargers
+            .iter()
+            .map(|(id, integral)| (id.clone(), integral.kwh))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn power(id: &str, kw: f64) -> ChargerState {
+        ChargerState::builder(id).power(kw).build()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn integrates_trapezoids_over_uneven_ticks() {
+        let mut integrator = EnergyIntegrator::new(Duration::minutes(10));
+        assert_eq!(integrator.observe(&power("EH000001", 10.0), at(0)), 0.0);
+        // 10 kW for 6 minutes
+        let kwh = integrator.observe(&power("EH000001", 10.0), at(360));
+        assert!(close(kwh, 1.0), "{}", kwh);
+        // Ramping from 10 to 2 kW over 90 seconds averages 6 kW
+        let kwh = integrator.observe(&power("EH000001", 2.0), at(450));
+        assert!(close(kwh, 1.15), "{}", kwh);
+        // Down to 0 over 3 minutes averages 1 kW
+        let kwh = integrator.observe(&power("EH000001", 0.0), at(630));
+        assert!(close(kwh, 1.2), "{}", kwh);
+    }
+
+    #[test]
+    fn a_gap_adds_nothing() {
+        let mut integrator = EnergyIntegrator::new(Duration::minutes(3));
+        integrator.observe(&power("EH000001", 11.0), at(0));
+        integrator.observe(&power("EH000001", 11.0), at(60));
+        let before = integrator.totals()["EH000001"];
+        // What happened in the hour between is unknown
+        let kwh = integrator.observe(&power("EH000001", 11.0), at(3660));
+        assert!(close(kwh, before), "{}", kwh);
+        // Counting goes on from the tick after the gap
+        let kwh = integrator.observe(&power("EH000001", 11.0), at(3720));
+        assert!(close(kwh, before * 2.0), "{}", kwh);
+    }
+
+    #[test]
+    fn repeated_or_earlier_times_add_nothing() {
+        let mut integrator = EnergyIntegrator::new(Duration::minutes(10));
+        integrator.observe(&power("EH000001", 11.0), at(60));
+        assert_eq!(integrator.observe(&power("EH000001", 11.0), at(60)), 0.0);
+        assert_eq!(integrator.observe(&power("EH000001", 11.0), at(0)), 0.0);
+    }
+
+    #[test]
+    fn chargers_are_integrated_apart() {
+        let mut integrator = EnergyIntegrator::for_interval(Duration::minutes(1));
+        integrator.observe(&power("EH000001", 6.0), at(0));
+        integrator.observe(&power("EH000002", 12.0), at(0));
+        integrator.observe(&power("EH000001", 6.0), at(60));
+        integrator.observe(&power("EH000002", 12.0), at(60));
+        let totals = integrator.totals();
+        assert!(close(totals["EH000001"], 0.1));
+        assert!(close(totals["EH000002"], 0.2));
+        assert!(close(integrator.total("EH000002"), 0.2));
+        assert_eq!(integrator.total("EH000003"), 0.0);
+        integrator.forget("EH000001");
+        assert_eq!(integrator.totals().len(), 1);
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use influxdb::{InfluxDbWriteable, WriteQuery};
+
+use super::structs::ChargerState;
+
+/// Measurement the lifetime energy of all chargers combined is written to, without tags.
+pub const TOTAL_LIFETIME_MEASUREMENT: &str = "total_lifetime_energy";
+
+#[derive(Debug, Clone, InfluxDbWriteable)]
+struct TotalPoint {
+    time: DateTime<Utc>,
+    value: f64,
+}
+
+/// Sums the lifetime energy of the chargers. A charger missing from a tick, or reporting no
+/// lifetime energy, counts with its last known value so the total doesn't dip.
+#[derive(Debug, Default)]
+pub struct LifetimeTotal {
+    last: HashMap<String, f64>,
+}
+
+impl LifetimeTotal {
+    pub fn new() -> Self {
+        LifetimeTotal::default()
+    }
+
+    /// The total in kWh after the tick, `None` until a charger reported its lifetime energy.
+    pub fn observe(&mut self, state: &[ChargerState]) -> Option<f64> {
+        for charger in state {
+            if let Some(kwh) = charger.lifetime_energy {
+                self.last.insert(charger.id.clone(), kwh);
+            }
+        }
+        self.total()
+    }
+
+    pub fn total(&self) -> Option<f64> {
+        if self.last.is_empty() {
+            None
+        } else {
+            Some(self.last.values().sum())
+        }
+    }
+
+    /// Drops a charger that is gone, its energy leaving the total.
+    pub fn forget(&mut self, charger_id: &str) {
+        self.last.remove(charger_id);
+    }
+}
+
+pub fn total_query(kwh: f64, time: DateTime<Utc>) -> WriteQuery {
+    TotalPoint { time, value: kwh }.into_query(TOTAL_LIFETIME_MEASUREMENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lifetime(id: &str, kwh: f64) -> ChargerState {
+        ChargerState::builder(id).lifetime_energy(kwh).build()
+    }
+
+    #[test]
+    fn sums_the_chargers() {
+        let mut total = LifetimeTotal::new();
+        assert_eq!(total.observe(&[]), None);
+        assert_eq!(
+            total.observe(&[lifetime("EH1", 100.0), lifetime("EH2", 50.5)]),
+            Some(150.5)
+        );
+        assert_eq!(
+            total.observe(&[lifetime("EH1", 101.0), lifetime("EH2", 51.5)]),
+            Some(152.5)
+        );
+    }
+
+    #[test]
+    fn carries_missing_chargers_forward() {
+        let mut total = LifetimeTotal::new();
+        total.observe(&[lifetime("EH1", 100.0), lifetime("EH2", 50.0)]);
+        // EH2 failed this tick, EH1 reported no lifetime energy
+        let no_lifetime = ChargerState::builder("EH1").build();
+        assert_eq!(total.observe(&[no_lifetime]), Some(150.0));
+        assert_eq!(total.observe(&[lifetime("EH2", 52.0)]), Some(152.0));
+    }
+
+    #[test]
+    fn removed_chargers_leave_the_total() {
+        let mut total = LifetimeTotal::new();
+        total.observe(&[lifetime("EH1", 100.0), lifetime("EH2", 50.0)]);
+        total.forget("EH2");
+        assert_eq!(total.total(), Some(100.0));
+        total.forget("EH1");
+        assert_eq!(total.total(), None);
+        // Back again, it counts again
+        assert_eq!(total.observe(&[lifetime("EH2", 55.0)]), Some(55.0));
+    }
+}
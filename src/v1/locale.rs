@@ -0,0 +1,95 @@
+use std::env;
+
+use tracing::instrument;
+
+/// Languages writing numbers with a decimal comma.
+const COMMA_LANGUAGES: [&str; 16] = [
+    "nb", "nn", "no", "da", "sv", "fi", "de", "fr", "es", "it", "nl", "pt", "pl", "cs", "ru", "is",
+];
+
+/// Decimal separator used by the plain text routes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberLocale {
+    Point,
+    Comma,
+}
+
+impl NumberLocale {
+    /// Parses a language tag such as `en`, `nb` or `nb-NO`.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag.split(['-', '_']).next()?.to_ascii_lowercase();
+        if language == "en" {
+            Some(NumberLocale::Point)
+        } else if COMMA_LANGUAGES.contains(&language.as_str()) {
+            Some(NumberLocale::Comma)
+        } else {
+            None
+        }
+    }
+
+    #[instrument(level = "trace")]
+    pub fn from_env() -> Result<Self, String> {
+        let locale = match env::var("NUMBER_LOCALE") {
+            Ok(tag) => NumberLocale::from_tag(&tag)
+                .ok_or_else(|| format!("Unsupported number locale: {}", tag))?,
+            Err(_) => NumberLocale::Point,
+        };
+        tracing::info!("NUMBER_LOCALE: {:?}", locale);
+        Ok(locale)
+    }
+
+    pub fn format(&self, value: f64) -> String {
+        match self {
+            NumberLocale::Point => value.to_string(),
+            NumberLocale::Comma => value.to_string().replace('.', ","),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_language_tags() {
+        let cases = [
+            ("en", Some(NumberLocale::Point)),
+            ("en-GB", Some(NumberLocale::Point)),
+            ("nb", Some(NumberLocale::Comma)),
+            ("nb-NO", Some(NumberLocale::Comma)),
+            ("NB_no", Some(NumberLocale::Comma)),
+            ("de-DE", Some(NumberLocale::Comma)),
+            ("ja", None),
+            ("", None),
+        ];
+        for (tag, expected) in cases {
+            assert_eq!(NumberLocale::from_tag(tag), expected, "{:?}", tag);
+        }
+    }
+
+    #[test]
+    fn formats_with_both_separators() {
+        let cases = [
+            (11.04, "11.04", "11,04"),
+            (0.5, "0.5", "0,5"),
+            (-1.25, "-1.25", "-1,25"),
+            (16.0, "16", "16"),
+            (0.0, "0", "0"),
+            // Never in exponent notation
+            (0.0001, "0.0001", "0,0001"),
+            (1e7, "10000000", "10000000"),
+        ];
+        for (value, point, comma) in cases {
+            assert_eq!(NumberLocale::Point.format(value), point);
+            assert_eq!(NumberLocale::Comma.format(value), comma);
+        }
+    }
+
+    #[test]
+    fn formats_the_shortest_form_that_reads_back() {
+        // What the value rounds to as f64, not more digits
+        assert_eq!(NumberLocale::Comma.format(2.34), "2,34");
+        assert_eq!(NumberLocale::Comma.format(0.1 + 0.2), "0,30000000000000004");
+        assert_eq!(NumberLocale::Point.format(1.0 / 3.0), "0.3333333333333333");
+    }
+}
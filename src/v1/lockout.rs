@@ -0,0 +1,171 @@
+use std::{
+    env,
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tracing::{error, info};
+
+use super::easee::AUTH_TARGET;
+
+/// Stops logging in after `threshold` rejected logins in a row, until `cooldown` has passed,
+/// so a wrong password can't get the account locked by Easee.
+#[derive(Debug)]
+pub struct LoginLockout {
+    threshold: u32,
+    cooldown: Duration,
+    failures: u32,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockoutReport {
+    pub consecutive_failures: u32,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl LoginLockout {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        LoginLockout {
+            threshold,
+            cooldown,
+            failures: 0,
+            locked_until: None,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let threshold = env::var("LOGIN_LOCKOUT_AFTER").map_or(3, |t| {
+            t.parse().expect("Illegal login lockout threshold format")
+        });
+        tracing::info!("LOGIN_LOCKOUT_AFTER: {}", threshold);
+        let minutes = env::var("LOGIN_LOCKOUT_MINUTES").map_or(30, |m| {
+            m.parse().expect("Illegal login lockout minutes format")
+        });
+        tracing::info!("LOGIN_LOCKOUT_MINUTES: {}", minutes);
+        LoginLockout::new(threshold, Duration::minutes(minutes))
+    }
+
+    /// When logins are allowed again, `None` if they are allowed now. A lockout that has
+    /// expired lets one attempt through, a rejection locking out again.
+    pub fn locked_until(&mut self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self.locked_until {
+            Some(until) if now < until => Some(until),
+            Some(_) => {
+                self.locked_until = None;
+                self.failures = self.threshold.saturating_sub(1);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Counts a rejected login, returning when logins are allowed again if it locked out.
+    pub fn failed(&mut self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.failures += 1;
+        if self.threshold > 0 && self.failures >= self.threshold {
+            self.locked_until = Some(now + self.cooldown);
+        }
+        self.locked_until
+    }
+
+    pub fn succeeded(&mut self) {
+        self.failures = 0;
+        self.locked_until = None;
+    }
+
+    pub fn report(&self) -> LockoutReport {
+        LockoutReport {
+            consecutive_failures: self.failures,
+            locked_until: self.locked_until,
+        }
+    }
+}
+
+fn lockout() -> &'static Mutex<LoginLockout> {
+    static LOCKOUT: OnceLock<Mutex<LoginLockout>> = OnceLock::new();
+    LOCKOUT.get_or_init(|| Mutex::new(LoginLockout::from_env()))
+}
+
+/// When logins are allowed again, `None` if they are allowed now.
+pub fn check() -> Option<DateTime<Utc>> {
+    lockout().lock().unwrap().locked_until(Utc::now())
+}
+
+pub fn failed() {
+    let mut lockout = lockout().lock().unwrap();
+    let failures = lockout.failures + 1;
+    if let Some(until) = lockout.failed(Utc::now()) {
+        error!(
+            "{} logins failed in a row, not logging in again until {}",
+            failures, until
+        );
+        info!(target: AUTH_TARGET, failures, until = %until, "Login locked out");
+    }
+}
+
+pub fn succeeded() {
+    lockout().lock().unwrap().succeeded();
+}
+
+pub fn report() -> LockoutReport {
+    lockout().lock().unwrap().report()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn locks_out_after_threshold_failures() {
+        let mut lockout = LoginLockout::new(3, Duration::minutes(30));
+        assert_eq!(lockout.failed(at(0)), None);
+        assert_eq!(lockout.failed(at(10)), None);
+        assert_eq!(lockout.locked_until(at(20)), None);
+        assert_eq!(lockout.failed(at(20)), Some(at(20 + 1800)));
+        assert_eq!(lockout.locked_until(at(21)), Some(at(1820)));
+        assert_eq!(lockout.locked_until(at(1819)), Some(at(1820)));
+        assert_eq!(lockout.report().consecutive_failures, 3);
+    }
+
+    #[test]
+    fn expired_lockout_allows_one_attempt() {
+        let mut lockout = LoginLockout::new(3, Duration::minutes(30));
+        for _ in 0..3 {
+            lockout.failed(at(0));
+        }
+        assert_eq!(lockout.locked_until(at(1800)), None);
+        assert_eq!(lockout.report().locked_until, None);
+        assert_eq!(lockout.failed(at(1800)), Some(at(3600)));
+    }
+
+    #[test]
+    fn success_resets() {
+        let mut lockout = LoginLockout::new(3, Duration::minutes(30));
+        lockout.failed(at(0));
+        lockout.failed(at(1));
+        lockout.succeeded();
+        assert_eq!(lockout.report().consecutive_failures, 0);
+        assert_eq!(lockout.failed(at(2)), None);
+        assert_eq!(lockout.failed(at(3)), None);
+        assert_eq!(lockout.failed(at(4)), Some(at(1804)));
+
+        lockout.succeeded();
+        assert_eq!(lockout.locked_until(at(5)), None);
+        assert_eq!(lockout.report().locked_until, None);
+    }
+
+    #[test]
+    fn zero_threshold_never_locks_out() {
+        let mut lockout = LoginLockout::new(0, Duration::minutes(30));
+        for i in 0..10 {
+            assert_eq!(lockout.failed(at(i)), None);
+        }
+        assert_eq!(lockout.locked_until(at(10)), None);
+    }
+}
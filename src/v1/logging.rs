@@ -0,0 +1,255 @@
+use std::env;
+
+use tracing::{metadata::LevelFilter, Level, Subscriber};
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_subscriber::{
+    filter::Targets,
+    fmt::{self, format::FmtSpan},
+    prelude::*,
+    registry::LookupSpan,
+    Layer,
+};
+
+use super::easee::AUTH_TARGET;
+
+/// Keeps the log writers flushing. Hold it until the process exits, dropping it stops the logs.
+#[must_use = "dropping the guards stops the logs from being written"]
+pub struct LoggerGuards {
+    _main: WorkerGuard,
+    _auth: WorkerGuard,
+    /// Why the logs went to stdout instead of the log directory, if they did.
+    fallback: Option<String>,
+}
+
+impl LoggerGuards {
+    /// Logs what went wrong building the logger. Call it once the subscriber is installed,
+    /// before that there is nowhere to log it to.
+    pub fn log_setup_errors(&self) {
+        if let Some(ref fallback) = self.fallback {
+            tracing::error!("{}", fallback);
+        }
+    }
+}
+
+/// Builds the subscriber writing the main log and, for the `auth` target, the audit log.
+///
+/// It isn't installed, so callers can add layers or use it for a scope only.
+pub fn get_logger() -> (impl Subscriber + Send + Sync, LoggerGuards) {
+    let log_dir = env::var("LOG_DIR").unwrap_or_else(|_| String::from("./var/log"));
+    build_logger(&log_dir, log_level())
+}
+
+/// The logger for the values of `LOG_DIR` and `LOG_LEVEL`.
+pub fn build_logger(log_dir: &str, level: Level) -> (impl Subscriber + Send + Sync, LoggerGuards) {
+    let ((main_writer, main_guard), (auth_writer, auth_guard), fallback) = log_writers(log_dir);
+
+    let subscriber = tracing_subscriber::registry()
+        .with(main_log_layer(main_writer, level))
+        .with(auth_log_layer(auth_writer));
+
+    (
+        subscriber,
+        LoggerGuards {
+            _main: main_guard,
+            _auth: auth_guard,
+            fallback,
+        },
+    )
+}
+
+/// Reads `LOG_LEVEL`, defaulting to info.
+pub fn log_level() -> Level {
+    parse_log_level(env::var("LOG_LEVEL").ok().as_deref())
+}
+
+fn parse_log_level(level: Option<&str>) -> Level {
+    match level {
+        Some("trace") => Level::TRACE,
+        Some("debug") => Level::DEBUG,
+        Some("info") => Level::INFO,
+        Some("warn") => Level::WARN,
+        Some("error") => Level::ERROR,
+        _ => Level::INFO,
+    }
+}
+
+/// The main log, every event at `level` or above.
+pub fn main_log_layer<S>(writer: NonBlocking, level: Level) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fmt::layer()
+        .with_span_events(FmtSpan::NONE)
+        .with_ansi(false)
+        .with_writer(writer)
+        .with_filter(LevelFilter::from_level(level))
+}
+
+/// The audit log, `auth` events only, recorded regardless of `LOG_LEVEL`.
+pub fn auth_log_layer<S>(writer: NonBlocking) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fmt::layer()
+        .with_ansi(false)
+        .with_writer(writer)
+        .with_filter(Targets::new().with_target(AUTH_TARGET, Level::INFO))
+}
+
+type Writer = (NonBlocking, WorkerGuard);
+
+/// Writers for the main and the audit log in `log_dir`, or both to stdout when it isn't
+/// writable, along with why.
+fn log_writers(log_dir: &str) -> (Writer, Writer, Option<String>) {
+    // The appenders swallow errors, so a directory they can't write to would leave no logs at all
+    match prepare_log_dir(log_dir) {
+        Ok(()) => (
+            tracing_appender::non_blocking(tracing_appender::rolling::daily(
+                log_dir,
+                "easee-status-server",
+            )),
+            tracing_appender::non_blocking(tracing_appender::rolling::never(log_dir, "auth.log")),
+            None,
+        ),
+        Err(e) => (
+            tracing_appender::non_blocking(std::io::stdout()),
+            tracing_appender::non_blocking(std::io::stdout()),
+            Some(format!(
+                "Log directory {} is not writable ({}), logging to stdout instead",
+                log_dir, e
+            )),
+        ),
+    }
+}
+
+/// Creates the log directory if needed and checks that files can be created in it.
+fn prepare_log_dir(log_dir: &str) -> std::io::Result<()> {
+    std::fs::create_dir_all(log_dir)?;
+    let probe = std::path::Path::new(log_dir).join(".write-test");
+    std::fs::File::create(&probe)?;
+    std::fs::remove_file(probe)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use tracing::{debug, info, warn};
+
+    use super::*;
+
+    /// An empty directory of its own for each test.
+    fn log_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("logging-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Everything written to the files in `dir` starting with `prefix`.
+    fn read_logs(dir: &Path, prefix: &str) -> String {
+        let mut logs = String::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with(prefix)
+            {
+                logs.push_str(&fs::read_to_string(path).unwrap());
+            }
+        }
+        logs
+    }
+
+    /// Logs the events of `log` with the logger for `dir` and `level`, then flushes it.
+    fn log_with(dir: &Path, level: Level, log: impl FnOnce()) {
+        let (subscriber, guards) = build_logger(dir.to_str().unwrap(), level);
+        tracing::subscriber::with_default(subscriber, log);
+        drop(guards);
+    }
+
+    #[test]
+    fn log_levels_are_parsed() {
+        let cases = [
+            (Some("trace"), Level::TRACE),
+            (Some("debug"), Level::DEBUG),
+            (Some("info"), Level::INFO),
+            (Some("warn"), Level::WARN),
+            (Some("error"), Level::ERROR),
+            (Some("WARN"), Level::INFO),
+            (Some("verbose"), Level::INFO),
+            (None, Level::INFO),
+        ];
+        for (level, expected) in cases {
+            assert_eq!(parse_log_level(level), expected, "{:?}", level);
+        }
+    }
+
+    #[test]
+    fn the_main_log_has_the_events_at_the_level() {
+        let info_dir = log_dir("info");
+        log_with(&info_dir, Level::INFO, || {
+            debug!("debug event");
+            info!("info event");
+        });
+        let logs = read_logs(&info_dir, "easee-status-server");
+        assert!(logs.contains("info event"), "{}", logs);
+        assert!(!logs.contains("debug event"), "{}", logs);
+
+        let debug_dir = log_dir("debug");
+        log_with(&debug_dir, Level::DEBUG, || debug!("debug event"));
+        let logs = read_logs(&debug_dir, "easee-status-server");
+        assert!(logs.contains("debug event"), "{}", logs);
+
+        // A directory that doesn't exist yet is created
+        let nested = log_dir("nested").join("var").join("log");
+        log_with(&nested, Level::WARN, || {
+            info!("info event");
+            warn!("warn event");
+        });
+        let logs = read_logs(&nested, "easee-status-server");
+        assert!(logs.contains("warn event"), "{}", logs);
+        assert!(!logs.contains("info event"), "{}", logs);
+    }
+
+    #[test]
+    fn auth_events_reach_the_audit_log_whatever_the_level() {
+        let dir = log_dir("auth");
+        log_with(&dir, Level::ERROR, || {
+            info!(target: AUTH_TARGET, source = "route", "Login attempt");
+            info!("Tick done");
+        });
+        let audit = read_logs(&dir, "auth.log");
+        assert!(audit.contains("Login attempt"), "{}", audit);
+        assert!(audit.contains("source=\"route\""), "{}", audit);
+        assert!(!audit.contains("Tick done"), "{}", audit);
+    }
+
+    #[test]
+    fn an_unwritable_log_dir_falls_back_to_stdout() {
+        // A directory can't be created below a file
+        let file = env::temp_dir().join(format!("logging-test-file-{}", std::process::id()));
+        fs::write(&file, "").unwrap();
+        let dir = file.join("log");
+
+        let (_, guards) = build_logger(dir.to_str().unwrap(), Level::INFO);
+        let fallback = guards.fallback.clone().unwrap();
+        assert!(fallback.contains(dir.to_str().unwrap()), "{}", fallback);
+
+        // The notice goes to the installed subscriber, here one writing to a file
+        let notices = log_dir("notices");
+        fs::create_dir_all(&notices).unwrap();
+        let (writer, guard) =
+            tracing_appender::non_blocking(fs::File::create(notices.join("main.log")).unwrap());
+        let subscriber = tracing_subscriber::registry().with(main_log_layer(writer, Level::INFO));
+        tracing::subscriber::with_default(subscriber, || guards.log_setup_errors());
+        drop(guard);
+        let logs = read_logs(&notices, "main.log");
+        assert!(logs.contains("ERROR"), "{}", logs);
+        assert!(logs.contains("not writable"), "{}", logs);
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use tracing::info;
+
+use super::{access, anonymize::log_id, structs::ProductInfo};
+
+/// When each charger was last in the charger list, so what is kept about chargers that
+/// left the account can be forgotten after `CHARGER_MEMORY_RETENTION_DAYS`, and when its
+/// state was last fetched, along with its product info, which doesn't change, or when
+/// fetching that last failed.
+#[derive(Debug)]
+pub struct ChargerMemory {
+    retention: Duration,
+    last_seen: HashMap<String, DateTime<Utc>>,
+    last_success: HashMap<String, DateTime<Utc>>,
+    products: HashMap<String, ProductInfo>,
+    /// When fetching the product info last failed, so it isn't asked for on every tick.
+    product_failures: HashMap<String, DateTime<Utc>>,
+}
+
+impl ChargerMemory {
+    pub fn new(retention: Duration) -> Self {
+        ChargerMemory {
+            retention,
+            last_seen: HashMap::new(),
+            last_success: HashMap::new(),
+            products: HashMap::new(),
+            product_failures: HashMap::new(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let days = env::var("CHARGER_MEMORY_RETENTION_DAYS").map_or(7, |d| {
+            d.parse().expect("Illegal charger memory retention format")
+        });
+        tracing::info!("CHARGER_MEMORY_RETENTION_DAYS: {}", days);
+        ChargerMemory::new(Duration::days(days))
+    }
+
+    pub fn touch<'a>(&mut self, ids: impl IntoIterator<Item = &'a str>, now: DateTime<Utc>) {
+        for id in ids {
+            self.last_seen.insert(id.to_string(), now);
+        }
+    }
+
+    /// Forgets chargers not listed for the retention period, returning their ids.
+    pub fn evict(&mut self, now: DateTime<Utc>) -> Vec<String> {
+        let retention = self.retention;
+        let evicted: Vec<String> = self
+            .last_seen
+            .iter()
+            .filter(|(_, seen)| now - **seen > retention)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &evicted {
+            self.last_seen.remove(id);
+            self.last_success.remove(id);
+            self.products.remove(id);
+            self.product_failures.remove(id);
+        }
+        evicted
+    }
+
+    pub fn succeeded(&mut self, id: &str, now: DateTime<Utc>) {
+        self.last_success.insert(id.to_string(), now);
+    }
+
+    /// When each listed charger's state was last fetched, `None` if it never was.
+    pub fn last_success(&self) -> Vec<(String, Option<DateTime<Utc>>)> {
+        let mut chargers: Vec<(String, Option<DateTime<Utc>>)> = self
+            .last_seen
+            .keys()
+            .map(|id| (id.clone(), self.last_success.get(id).copied()))
+            .collect();
+        chargers.sort();
+        chargers
+    }
+
+    pub fn last_success_of(&self, id: &str) -> Option<DateTime<Utc>> {
+        self.last_success.get(id).copied()
+    }
+
+    pub fn product(&self, id: &str) -> Option<&ProductInfo> {
+        self.products.get(id)
+    }
+
+    pub fn remember_product(&mut self, id: &str, product: ProductInfo) {
+        self.product_failures.remove(id);
+        self.products.insert(id.to_string(), product);
+    }
+
+    pub fn product_failed(&mut self, id: &str, now: DateTime<Utc>) {
+        self.product_failures.insert(id.to_string(), now);
+    }
+
+    /// Whether fetching the product info failed after `since`.
+    pub fn product_failed_since(&self, id: &str, since: DateTime<Utc>) -> bool {
+        self.product_failures
+            .get(id)
+            .is_some_and(|failed| *failed > since)
+    }
+
+    pub fn len(&self) -> usize {
+        self.last_seen.len()
+    }
+}
+
+static MEMORY: OnceLock<Mutex<ChargerMemory>> = OnceLock::new();
+
+fn memory() -> &'static Mutex<ChargerMemory> {
+    MEMORY.get_or_init(|| Mutex::new(ChargerMemory::from_env()))
+}
+
+/// Records the chargers as listed now.
+pub fn touch<'a>(ids: impl IntoIterator<Item = &'a str>) {
+    memory().lock().unwrap().touch(ids, Utc::now());
+}
+
+/// Records a successful fetch of the charger's state.
+pub fn succeeded(id: &str) {
+    memory().lock().unwrap().succeeded(id, Utc::now());
+}
+
+/// The charger's product info, `None` until it was fetched.
+pub fn product(id: &str) -> Option<ProductInfo> {
+    memory().lock().unwrap().product(id).cloned()
+}
+
+pub fn remember_product(id: &str, product: ProductInfo) {
+    memory().lock().unwrap().remember_product(id, product);
+}
+
+pub fn product_failed(id: &str) {
+    memory().lock().unwrap().product_failed(id, Utc::now());
+}
+
+/// Whether fetching the charger's product info failed within the last `within`.
+pub fn product_failed_within(id: &str, within: Duration) -> bool {
+    memory()
+        .lock()
+        .unwrap()
+        .product_failed_since(id, Utc::now() - within)
+}
+
+/// When each listed charger's state was last fetched, by the poller or a route.
+pub fn last_success() -> Vec<(String, Option<DateTime<Utc>>)> {
+    memory().lock().unwrap().last_success()
+}
+
+/// When the charger's state was last fetched, `None` if it never was.
+pub fn last_success_of(id: &str) -> Option<DateTime<Utc>> {
+    memory().lock().unwrap().last_success_of(id)
+}
+
+/// Forgets chargers gone from the list for too long, including their access state. The
+/// caller forgets them in its own per-charger state.
+pub fn evict() -> Vec<String> {
+    let evicted = memory().lock().unwrap().evict(Utc::now());
+    for id in &evicted {
+        info!("Forgetting charger {}, not listed for a while", log_id(id));
+        access::forget(id);
+    }
+    evicted
+}
+
+/// Chargers remembered, for `/health`.
+pub fn size() -> usize {
+    memory().lock().unwrap().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(d: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap() + Duration::days(d)
+    }
+
+    #[test]
+    fn evicts_chargers_not_listed_for_the_retention() {
+        let mut memory = ChargerMemory::new(Duration::days(7));
+        memory.touch(["EH1", "EH2"], day(0));
+        memory.succeeded("EH1", day(0));
+        memory.product_failed("EH1", day(0));
+        memory.touch(["EH2"], day(5));
+
+        assert!(memory.evict(day(7)).is_empty());
+        assert_eq!(memory.evict(day(8)), vec!["EH1"]);
+        assert_eq!(memory.len(), 1);
+        assert!(!memory.product_failed_since("EH1", day(-1)));
+        // Listed again, it starts over
+        memory.touch(["EH1"], day(9));
+        assert_eq!(
+            memory.last_success(),
+            vec![(String::from("EH1"), None), (String::from("EH2"), None)]
+        );
+    }
+
+    #[test]
+    fn active_chargers_are_never_evicted() {
+        let mut memory = ChargerMemory::new(Duration::days(1));
+        for d in 0..30 {
+            memory.touch(["EH1"], day(d));
+            assert!(memory.evict(day(d)).is_empty());
+        }
+        assert_eq!(memory.len(), 1);
+    }
+
+    #[test]
+    fn remembers_products_and_failures() {
+        let mut memory = ChargerMemory::new(Duration::days(7));
+        memory.touch(["EH1"], day(0));
+        memory.product_failed("EH1", day(1));
+        assert!(memory.product_failed_since("EH1", day(0)));
+        assert!(!memory.product_failed_since("EH1", day(1)));
+
+        let product =
+            ProductInfo::from_details_json(&serde_json::json!({ "product": "Easee Home" }));
+        memory.remember_product("EH1", product);
+        assert!(!memory.product_failed_since("EH1", day(0)));
+        assert_eq!(
+            memory.product("EH1").unwrap().product.as_deref(),
+            Some("Easee Home")
+        );
+        assert!(memory.product("EH2").is_none());
+    }
+}
@@ -1,3 +1,55 @@
-pub mod easee;
-pub mod run;
-pub mod structs;
+pub(crate) mod access;
+pub(crate) mod adaptive;
+pub(crate) mod admin;
+pub(crate) mod aliases;
+pub(crate) mod annotations;
+pub(crate) mod anonymize;
+pub(crate) mod api_key;
+pub(crate) mod backfill;
+pub(crate) mod backlog;
+pub(crate) mod banner;
+pub(crate) mod base_path;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub(crate) mod check_db;
+pub(crate) mod client;
+pub(crate) mod clock;
+pub(crate) mod commands;
+pub(crate) mod config;
+pub(crate) mod cors;
+pub(crate) mod credentials;
+pub(crate) mod debug;
+pub(crate) mod derive;
+pub(crate) mod drain;
+pub(crate) mod easee;
+pub(crate) mod encoding;
+pub(crate) mod gap;
+pub(crate) mod health;
+pub(crate) mod integrate;
+pub(crate) mod lifetime;
+pub(crate) mod locale;
+pub(crate) mod lockout;
+pub(crate) mod logging;
+pub(crate) mod memory;
+pub(crate) mod mode;
+pub(crate) mod names;
+pub(crate) mod overrides;
+pub(crate) mod poller;
+#[cfg(feature = "spotprice")]
+pub(crate) mod price;
+pub(crate) mod rate_limit;
+pub(crate) mod record;
+pub(crate) mod report;
+pub(crate) mod retry;
+pub(crate) mod rollup;
+pub(crate) mod roster;
+pub(crate) mod routes;
+pub(crate) mod routes_config;
+pub(crate) mod run;
+pub(crate) mod schema;
+pub(crate) mod sessions;
+pub(crate) mod sink;
+pub(crate) mod source;
+pub(crate) mod stale;
+pub(crate) mod structs;
+pub(crate) mod upstream_limit;
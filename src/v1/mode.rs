@@ -0,0 +1,129 @@
+use futures::future::{self, Either};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
+
+use super::routes::ServerError;
+
+/// Runs the server and the poller until either stops, then stops the other one too, so
+/// neither is left running alone. Fails naming the half that stopped first, unless it stopped
+/// cleanly for `shutdown`, or it was the server shutting down cleanly.
+#[instrument(skip_all, level = "trace")]
+pub async fn run_both(
+    server: JoinHandle<Result<(), ServerError>>,
+    poller: JoinHandle<()>,
+    shutdown: CancellationToken,
+) -> Result<(), String> {
+    match future::select(server, poller).await {
+        Either::Left((result, poller)) => {
+            shutdown.cancel();
+            let _ = poller.await;
+            match result {
+                Ok(Ok(())) => Ok(()),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(e) => Err(format!("Server failed: {}", e)),
+            }
+        }
+        Either::Right((result, server)) => {
+            let asked = shutdown.is_cancelled();
+            shutdown.cancel();
+            let _ = server.await;
+            match result {
+                Ok(()) if asked => Ok(()),
+                Ok(()) => Err(String::from("Poller stopped")),
+                Err(e) => Err(format!("Poller failed: {}", e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::*;
+
+    /// A half that runs until `shutdown`, setting `stopped` once it has.
+    fn until_shutdown<T: Send + 'static>(
+        shutdown: &CancellationToken,
+        stopped: &Arc<AtomicBool>,
+        result: T,
+    ) -> JoinHandle<T> {
+        let (shutdown, stopped) = (shutdown.clone(), stopped.clone());
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            stopped.store(true, Ordering::SeqCst);
+            result
+        })
+    }
+
+    async fn run(
+        server: JoinHandle<Result<(), ServerError>>,
+        poller: JoinHandle<()>,
+        shutdown: CancellationToken,
+    ) -> Result<(), String> {
+        tokio::time::timeout(Duration::from_secs(5), run_both(server, poller, shutdown))
+            .await
+            .expect("run_both did not return")
+    }
+
+    #[tokio::test]
+    async fn a_failing_server_stops_the_poller() {
+        let shutdown = CancellationToken::new();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let server = tokio::spawn(async { Err(ServerError::Config(String::from("no port"))) });
+        let poller = until_shutdown(&shutdown, &stopped, ());
+
+        let error = run(server, poller, shutdown.clone()).await.unwrap_err();
+        assert!(error.contains("no port"), "{}", error);
+        assert!(shutdown.is_cancelled());
+        assert!(stopped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn a_stopped_poller_stops_the_server() {
+        let shutdown = CancellationToken::new();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let server = until_shutdown(&shutdown, &stopped, Ok(()));
+        let poller = tokio::spawn(async {});
+
+        assert_eq!(
+            run(server, poller, shutdown.clone()).await,
+            Err(String::from("Poller stopped"))
+        );
+        assert!(stopped.load(Ordering::SeqCst));
+
+        let shutdown = CancellationToken::new();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let server = until_shutdown(&shutdown, &stopped, Ok(()));
+        let poller = tokio::spawn(async { panic!("tick failed") });
+
+        let error = run(server, poller, shutdown).await.unwrap_err();
+        assert!(error.starts_with("Poller failed"), "{}", error);
+        assert!(stopped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_both() {
+        let shutdown = CancellationToken::new();
+        let server_stopped = Arc::new(AtomicBool::new(false));
+        let poller_stopped = Arc::new(AtomicBool::new(false));
+        let server = until_shutdown(&shutdown, &server_stopped, Ok(()));
+        let poller = until_shutdown(&shutdown, &poller_stopped, ());
+
+        let both = tokio::spawn(run(server, poller, shutdown.clone()));
+        tokio::task::yield_now().await;
+        assert!(!both.is_finished());
+        shutdown.cancel();
+
+        assert_eq!(both.await.unwrap(), Ok(()));
+        assert!(server_stopped.load(Ordering::SeqCst));
+        assert!(poller_stopped.load(Ordering::SeqCst));
+    }
+}
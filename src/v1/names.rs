@@ -0,0 +1,183 @@
+use std::{collections::HashMap, env, sync::OnceLock};
+
+use super::{overrides::charger_config, structs::ChargerInfo};
+
+static NAME_MAP: OnceLock<HashMap<String, String>> = OnceLock::new();
+static CHARGER_ORDER: OnceLock<Vec<String>> = OnceLock::new();
+
+fn name_map() -> &'static HashMap<String, String> {
+    NAME_MAP.get_or_init(|| match env::var("CHARGER_NAME_MAP") {
+        Ok(map) => {
+            let map =
+                parse_name_map(&map).unwrap_or_else(|e| panic!("Illegal CHARGER_NAME_MAP: {}", e));
+            tracing::info!("CHARGER_NAME_MAP: {:?}", map);
+            map
+        }
+        Err(_) => HashMap::new(),
+    })
+}
+
+/// The name shown for a charger: the overridden one, the mapped one, else Easee's, else the id.
+pub fn display_name(charger_id: &str, easee_name: Option<&str>) -> String {
+    if let Some(name) = charger_config(charger_id).name {
+        return name;
+    }
+    name_map()
+        .get(charger_id)
+        .map(String::as_str)
+        .or(easee_name)
+        .unwrap_or(charger_id)
+        .to_string()
+}
+
+/// Parses `id=name,id=name`. A backslash escapes the next character, so names can hold
+/// commas and equals signs.
+pub fn parse_name_map(s: &str) -> Result<HashMap<String, String>, String> {
+    let mut map = HashMap::new();
+    let mut entries = vec![(String::new(), None::<String>)];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        let (id, name) = entries.last_mut().unwrap();
+        match c {
+            ',' => entries.push((String::new(), None)),
+            '=' if name.is_none() => *name = Some(String::new()),
+            c => {
+                let c = match c {
+                    '\\' => chars.next().ok_or("Trailing backslash")?,
+                    c => c,
+                };
+                name.as_mut().unwrap_or(id).push(c);
+            }
+        }
+    }
+    for (id, name) in entries {
+        let id = id.trim();
+        if id.is_empty() && name.is_none() {
+            continue;
+        }
+        let name = name.ok_or_else(|| format!("No name for {}", id))?;
+        if id.is_empty() {
+            return Err(format!("No id for {}", name));
+        }
+        map.insert(id.to_string(), name.trim().to_string());
+    }
+    Ok(map)
+}
+
+fn charger_order() -> &'static [String] {
+    CHARGER_ORDER.get_or_init(|| match env::var("CHARGER_ORDER") {
+        Ok(order) => {
+            let order: Vec<String> = order
+                .split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(str::to_string)
+                .collect();
+            tracing::info!("CHARGER_ORDER: {:?}", order);
+            order
+        }
+        Err(_) => Vec::new(),
+    })
+}
+
+/// Sorts the chargers so their indices don't depend on the order Easee lists them in.
+///
+/// Chargers in `CHARGER_ORDER` come first, in that order, followed by the rest sorted by id.
+pub fn order_chargers(chargers: &mut [ChargerInfo]) {
+    sort_chargers(chargers, charger_order());
+}
+
+pub fn sort_chargers(chargers: &mut [ChargerInfo], preferred: &[String]) {
+    chargers.sort_by(|a, b| {
+        let position = |c: &ChargerInfo| {
+            preferred
+                .iter()
+                .position(|id| *id == c.id)
+                .unwrap_or(preferred.len())
+        };
+        position(a).cmp(&position(b)).then_with(|| a.id.cmp(&b.id))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(s: &str) -> Vec<(String, String)> {
+        let mut entries: Vec<_> = parse_name_map(s).unwrap().into_iter().collect();
+        entries.sort();
+        entries
+    }
+
+    fn entry(id: &str, name: &str) -> (String, String) {
+        (id.to_string(), name.to_string())
+    }
+
+    #[test]
+    fn parses_names_with_spaces_and_escapes() {
+        assert_eq!(
+            parsed("EH123=Garage left,EH456=Garage right"),
+            vec![
+                entry("EH123", "Garage left"),
+                entry("EH456", "Garage right")
+            ]
+        );
+        assert_eq!(
+            parsed(r"EH123=Garage\, left , EH456 = a\=b\\c"),
+            vec![entry("EH123", "Garage, left"), entry("EH456", r"a=b\c")]
+        );
+        // Only the first equals sign splits
+        assert_eq!(parsed("EH123=a=b"), vec![entry("EH123", "a=b")]);
+        assert_eq!(parsed("EH123=Garage,"), vec![entry("EH123", "Garage")]);
+        assert!(parsed("").is_empty());
+    }
+
+    #[test]
+    fn refuses_malformed_maps() {
+        for map in ["EH123", "EH123=Garage,EH456", "=Garage", r"EH123=Garage\"] {
+            assert!(parse_name_map(map).is_err(), "{:?}", map);
+        }
+    }
+
+    fn chargers(ids: &[&str]) -> Vec<ChargerInfo> {
+        ids.iter()
+            .map(|id| ChargerInfo {
+                id: id.to_string(),
+                name: None,
+            })
+            .collect()
+    }
+
+    fn sorted(ids: &[&str], preferred: &[&str]) -> Vec<String> {
+        let preferred: Vec<String> = preferred.iter().map(|id| id.to_string()).collect();
+        let mut chargers = chargers(ids);
+        sort_chargers(&mut chargers, &preferred);
+        chargers.into_iter().map(|c| c.id).collect()
+    }
+
+    #[test]
+    fn sorts_by_id_without_an_order() {
+        assert_eq!(
+            sorted(&["EH3", "EH1", "EH2"], &[]),
+            vec!["EH1", "EH2", "EH3"]
+        );
+    }
+
+    #[test]
+    fn partial_order_comes_first_and_the_rest_by_id() {
+        assert_eq!(
+            sorted(&["EH1", "EH4", "EH3", "EH2"], &["EH3", "EH1"]),
+            vec!["EH3", "EH1", "EH2", "EH4"]
+        );
+        // Ids in the order but not listed by Easee are skipped
+        assert_eq!(sorted(&["EH2", "EH1"], &["EH9", "EH2"]), vec!["EH2", "EH1"]);
+    }
+
+    #[test]
+    fn order_doesnt_depend_on_the_listing() {
+        let preferred = ["EH5"];
+        let expected = sorted(&["EH1", "EH5", "EH3"], &preferred);
+        assert_eq!(sorted(&["EH3", "EH1", "EH5"], &preferred), expected);
+        assert_eq!(sorted(&["EH5", "EH3", "EH1"], &preferred), expected);
+    }
+}
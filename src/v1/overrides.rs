@@ -0,0 +1,209 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::{Mutex, OnceLock},
+};
+
+use serde::Deserialize;
+use tracing::{error, warn};
+
+/// What a charger is treated with when it has no overrides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GlobalConfig {
+    /// Leave out the `reachable` 0 points of unreachable chargers, from `SKIP_OFFLINE`.
+    pub skip_offline: bool,
+}
+
+/// The settings a `[chargers.<id>]` table of `CONFIG_FILE` can change, unset ones fall back
+/// to the global ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChargerOverride {
+    pub name: Option<String>,
+    pub skip_offline: Option<bool>,
+    pub collect: Option<bool>,
+}
+
+/// The settings a charger is treated with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedConfig {
+    pub name: Option<String>,
+    pub skip_offline: bool,
+    /// Whether its points are written to InfluxDB. The routes serve it either way.
+    pub collect: bool,
+}
+
+/// Merges a charger's overrides over the global settings.
+pub fn resolve(global: GlobalConfig, charger: Option<&ChargerOverride>) -> ResolvedConfig {
+    let charger = charger.cloned().unwrap_or_default();
+    ResolvedConfig {
+        name: charger.name,
+        skip_offline: charger.skip_offline.unwrap_or(global.skip_offline),
+        collect: charger.collect.unwrap_or(true),
+    }
+}
+
+/// The contents of `CONFIG_FILE`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    chargers: HashMap<String, ChargerOverride>,
+}
+
+/// Parses the `[chargers.<id>]` tables of a config file.
+pub fn parse_overrides(s: &str) -> Result<HashMap<String, ChargerOverride>, String> {
+    let file: ConfigFile = toml::from_str(s).map_err(|e| e.to_string())?;
+    Ok(file.chargers)
+}
+
+static GLOBAL: OnceLock<GlobalConfig> = OnceLock::new();
+static OVERRIDES: OnceLock<HashMap<String, ChargerOverride>> = OnceLock::new();
+static CHECKED: Mutex<bool> = Mutex::new(false);
+
+fn global() -> GlobalConfig {
+    *GLOBAL.get_or_init(|| {
+        let skip_offline = env::var("SKIP_OFFLINE").is_ok_and(|s| s == "true");
+        tracing::info!("SKIP_OFFLINE: {}", skip_offline);
+        GlobalConfig { skip_offline }
+    })
+}
+
+fn read_file(path: &str) -> Result<HashMap<String, ChargerOverride>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Reading CONFIG_FILE {} failed: {}", path, e))?;
+    parse_overrides(&contents).map_err(|e| format!("Illegal CONFIG_FILE {}: {}", path, e))
+}
+
+fn read_overrides() -> Result<HashMap<String, ChargerOverride>, String> {
+    let path = match env::var("CONFIG_FILE") {
+        Ok(path) => path,
+        Err(_) => return Ok(HashMap::new()),
+    };
+    tracing::info!("CONFIG_FILE: {}", path);
+    let overrides = read_file(&path)?;
+    tracing::info!("Charger overrides: {:?}", overrides);
+    Ok(overrides)
+}
+
+/// Reads `CONFIG_FILE`, so a missing or invalid file stops the process at startup rather
+/// than failing the first tick or request that needs a charger's settings.
+pub fn load() -> Result<(), String> {
+    if OVERRIDES.get().is_none() {
+        let _ = OVERRIDES.set(read_overrides()?);
+    }
+    Ok(())
+}
+
+/// The overrides read by `load`. Without it, read on first use, and left out on errors.
+fn overrides() -> &'static HashMap<String, ChargerOverride> {
+    OVERRIDES.get_or_init(|| {
+        read_overrides().unwrap_or_else(|e| {
+            error!("{}, no charger overrides applied", e);
+            HashMap::new()
+        })
+    })
+}
+
+/// The settings the charger is treated with.
+pub fn charger_config(charger_id: &str) -> ResolvedConfig {
+    resolve(global(), overrides().get(charger_id))
+}
+
+/// Warns, the first time the chargers are listed, about overrides for chargers not among them.
+pub fn check_known<'a>(ids: impl IntoIterator<Item = &'a str>) {
+    let mut checked = CHECKED.lock().unwrap();
+    if *checked {
+        return;
+    }
+    *checked = true;
+    let known: HashSet<&str> = ids.into_iter().collect();
+    for id in overrides().keys() {
+        if !known.contains(id.as_str()) {
+            warn!("CONFIG_FILE has settings for unknown charger {}", id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GLOBAL: GlobalConfig = GlobalConfig { skip_offline: true };
+
+    #[test]
+    fn overrides_are_merged_over_the_global_settings() {
+        let renamed = ChargerOverride {
+            name: Some(String::from("Outdoor")),
+            ..Default::default()
+        };
+        let online = ChargerOverride {
+            skip_offline: Some(false),
+            ..Default::default()
+        };
+        let silent = ChargerOverride {
+            collect: Some(false),
+            ..Default::default()
+        };
+        let cases = [
+            (None, None, true, true),
+            (Some(&renamed), Some("Outdoor"), true, true),
+            (Some(&online), None, false, true),
+            (Some(&silent), None, true, false),
+        ];
+        for (charger, name, skip_offline, collect) in cases {
+            let resolved = resolve(GLOBAL, charger);
+            assert_eq!(resolved.name.as_deref(), name, "{:?}", charger);
+            assert_eq!(resolved.skip_offline, skip_offline, "{:?}", charger);
+            assert_eq!(resolved.collect, collect, "{:?}", charger);
+        }
+    }
+
+    #[test]
+    fn charger_tables_are_parsed() {
+        let overrides = parse_overrides(
+            r#"
+            [chargers.EH000001]
+            name = "Outdoor"
+            skip_offline = true
+
+            [chargers.EH000002]
+            collect = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(overrides.len(), 2);
+        assert_eq!(overrides["EH000001"].name.as_deref(), Some("Outdoor"));
+        assert_eq!(overrides["EH000001"].skip_offline, Some(true));
+        assert_eq!(overrides["EH000001"].collect, None);
+        assert_eq!(overrides["EH000002"].collect, Some(false));
+        assert!(parse_overrides("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_and_mistyped_settings_are_rejected() {
+        let cases = [
+            "[chargers.EH000001]\nmax_power = 22",
+            "[chargers.EH000001]\nskip_offline = \"yes\"",
+            "[charger.EH000001]\nname = \"Outdoor\"",
+        ];
+        for case in cases {
+            assert!(parse_overrides(case).is_err(), "{}", case);
+        }
+    }
+
+    #[test]
+    fn missing_and_invalid_files_are_errors() {
+        let dir = env::temp_dir().join(format!("overrides-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let missing = dir.join("missing.toml");
+        let error = read_file(missing.to_str().unwrap()).unwrap_err();
+        assert!(error.starts_with("Reading CONFIG_FILE"), "{}", error);
+
+        let invalid = dir.join("invalid.toml");
+        std::fs::write(&invalid, "[chargers.EH000001]\nmax_power = 22").unwrap();
+        let error = read_file(invalid.to_str().unwrap()).unwrap_err();
+        assert!(error.starts_with("Illegal CONFIG_FILE"), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, instrument};
+
+use super::{
+    backlog::spawn_tick,
+    client::EaseeClient,
+    drain::is_draining,
+    health::LastErrors,
+    routes::Cache,
+    run::{
+        get_interval, get_slow_interval, get_tick_deadline, slow_tick, tick, DbTarget, PollerState,
+    },
+    schema::{schema_annotations_enabled, write_schema_annotations},
+    source::check_other_writers,
+    structs::SessionState,
+};
+
+/// Polls Easee and writes what it got to the targets, until shut down.
+#[derive(Debug)]
+pub struct Poller {
+    session: Arc<Mutex<SessionState>>,
+    targets: Arc<Vec<DbTarget>>,
+    cache: Option<Arc<Cache>>,
+    state: Arc<PollerState>,
+    deadline: Duration,
+    slow_interval: Duration,
+}
+
+/// Builds a `Poller`, everything but the targets defaulting to the environment.
+#[derive(Debug)]
+pub struct PollerBuilder {
+    targets: Arc<Vec<DbTarget>>,
+    session: Option<Arc<Mutex<SessionState>>>,
+    cache: Option<Arc<Cache>>,
+    errors: Option<Arc<LastErrors>>,
+    interval: Option<chrono::Duration>,
+    deadline: Option<Duration>,
+    slow_interval: Option<Duration>,
+}
+
+impl PollerBuilder {
+    pub fn new(targets: Arc<Vec<DbTarget>>) -> Self {
+        PollerBuilder {
+            targets,
+            session: None,
+            cache: None,
+            errors: None,
+            interval: None,
+            deadline: None,
+            slow_interval: None,
+        }
+    }
+
+    /// The session shared with the server and the session refresh, else a new one.
+    pub fn session(mut self, session: Arc<Mutex<SessionState>>) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Polls with the session of the client.
+    pub fn client(self, client: &EaseeClient) -> Self {
+        self.session(client.session())
+    }
+
+    /// A cache fed with every tick, for the server in `MODE=both`.
+    pub fn cache(mut self, cache: Arc<Cache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn errors(mut self, errors: Arc<LastErrors>) -> Self {
+        self.errors = Some(errors);
+        self
+    }
+
+    /// Time between ticks, else `INTERVAL`.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(chrono::Duration::from_std(interval).expect("Illegal interval"));
+        self
+    }
+
+    /// Time each phase of a tick may take, else `TICK_DEADLINE_SECS`.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Time between the ticks polling the charger configs, else `SLOW_INTERVAL`.
+    pub fn slow_interval(mut self, interval: Duration) -> Self {
+        self.slow_interval = Some(interval);
+        self
+    }
+
+    pub fn build(self) -> Poller {
+        let errors = self.errors.unwrap_or_else(|| Arc::new(LastErrors::new()));
+        let interval = self.interval.unwrap_or_else(get_interval);
+        Poller {
+            session: self
+                .session
+                .unwrap_or_else(|| Arc::new(Mutex::new(SessionState::new()))),
+            targets: self.targets,
+            cache: self.cache,
+            state: Arc::new(PollerState::with_interval(errors, interval)),
+            deadline: self.deadline.unwrap_or_else(get_tick_deadline),
+            slow_interval: self
+                .slow_interval
+                .unwrap_or_else(|| get_slow_interval().to_std().unwrap()),
+        }
+    }
+}
+
+impl Poller {
+    pub fn builder(targets: Arc<Vec<DbTarget>>) -> PollerBuilder {
+        PollerBuilder::new(targets)
+    }
+
+    pub fn session(&self) -> Arc<Mutex<SessionState>> {
+        self.session.clone()
+    }
+
+    /// Ticks until `shutdown` is cancelled. Ticks already running are left to finish.
+    #[instrument(skip_all, level = "trace")]
+    pub async fn run(self, shutdown: CancellationToken) {
+        if schema_annotations_enabled() {
+            write_schema_annotations(&self.targets).await;
+        }
+        check_other_writers(&self.targets).await;
+
+        tokio::spawn(poll_slow(
+            self.session.clone(),
+            self.targets.clone(),
+            self.slow_interval,
+            self.deadline,
+            shutdown.clone(),
+        ));
+
+        let mut period = self.state.interval.lock().await.current();
+        let mut interval_timer = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                _ = interval_timer.tick() => (),
+                _ = shutdown.cancelled() => return,
+            }
+
+            // Follows what the previous tick saw, as this one runs in the background
+            let next = self.state.interval.lock().await.current();
+            if next != period {
+                period = next;
+                interval_timer =
+                    tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            }
+            if is_draining() {
+                debug!("Draining, skipping the tick");
+                continue;
+            }
+
+            spawn_tick(
+                "tick",
+                tick(
+                    self.session.clone(),
+                    self.targets.clone(),
+                    self.cache.clone(),
+                    self.state.clone(),
+                    self.deadline,
+                ),
+            );
+        }
+    }
+}
+
+/// Polls the rarely changing settings on their own timer, sharing the session with the ticks.
+async fn poll_slow(
+    session: Arc<Mutex<SessionState>>,
+    targets: Arc<Vec<DbTarget>>,
+    interval: Duration,
+    deadline: Duration,
+    shutdown: CancellationToken,
+) {
+    let mut interval_timer = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = interval_timer.tick() => (),
+            _ = shutdown.cancelled() => return,
+        }
+        if is_draining() {
+            continue;
+        }
+
+        spawn_tick(
+            "slow tick",
+            slow_tick(session.clone(), targets.clone(), deadline),
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::env;
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, Utc};
+use tokio::sync::Mutex;
+use tracing::{debug, instrument, trace, warn};
+
+const DEFAULT_PRICE_URL: &str =
+    "https://www.hvakosterstrommen.no/api/v1/prices/{year}/{month}-{day}_{area}.json";
+
+/// Hourly spot prices, fetched once per day from a hvakosterstrommen.no style API.
+#[derive(Debug)]
+pub struct PriceProvider {
+    url_template: String,
+    area: String,
+    fallback: Option<f64>,
+    day: Mutex<Option<(NaiveDate, Vec<HourPrice>)>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HourPrice {
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    pub price: f64,
+}
+
+impl PriceProvider {
+    pub fn new(url_template: String, area: String, fallback: Option<f64>) -> Self {
+        PriceProvider {
+            url_template,
+            area,
+            fallback,
+            day: Mutex::new(None),
+        }
+    }
+
+    #[instrument(level = "trace")]
+    pub fn from_env() -> Option<Self> {
+        let area = match env::var("SPOT_PRICE_AREA") {
+            Ok(area) => area,
+            Err(_) => {
+                tracing::info!("SPOT_PRICE_AREA not set, not recording cost");
+                return None;
+            }
+        };
+        tracing::info!("SPOT_PRICE_AREA: {}", area);
+
+        let url_template =
+            env::var("SPOT_PRICE_URL").unwrap_or_else(|_| String::from(DEFAULT_PRICE_URL));
+        tracing::info!("SPOT_PRICE_URL: {}", url_template);
+
+        let fallback = env::var("ENERGY_PRICE_PER_KWH")
+            .ok()
+            .map(|p| p.parse().expect("Illegal energy price format"));
+        tracing::info!("ENERGY_PRICE_PER_KWH: {:?}", fallback);
+
+        Some(PriceProvider::new(url_template, area, fallback))
+    }
+
+    /// The price per kWh at the given time, falling back to the flat price if the spot
+    /// prices can't be fetched.
+    #[instrument(skip(self), level = "trace")]
+    pub async fn price_at(&self, time: DateTime<Utc>) -> Option<f64> {
+        let date = time.with_timezone(&Local).date_naive();
+        let mut day = self.day.lock().await;
+
+        if day.as_ref().map(|(d, _)| *d) != Some(date) {
+            match self.fetch(date).await {
+                Some(prices) => *day = Some((date, prices)),
+                None => return self.fallback,
+            }
+        }
+
+        let price = day
+            .as_ref()
+            .and_then(|(_, prices)| price_for(prices, time))
+            .or(self.fallback);
+        trace!("Price at {}: {:?}", time, price);
+        price
+    }
+
+    async fn fetch(&self, date: NaiveDate) -> Option<Vec<HourPrice>> {
+        let url = render_url(&self.url_template, date, &self.area);
+        debug!("Fetching spot prices from {}", url);
+        let body = match reqwest::get(&url).await {
+            Ok(res) if res.status().is_success() => res.text().await.ok()?,
+            Ok(res) => {
+                warn!("Fetching spot prices failed: {}", res.status());
+                return None;
+            }
+            Err(e) => {
+                warn!("Fetching spot prices failed: {}", e);
+                return None;
+            }
+        };
+        let prices = parse_prices(&body);
+        if prices.is_none() {
+            warn!("Invalid spot price response");
+        }
+        prices
+    }
+}
+
+pub fn render_url(template: &str, date: NaiveDate, area: &str) -> String {
+    template
+        .replace("{year}", &date.format("%Y").to_string())
+        .replace("{month}", &date.format("%m").to_string())
+        .replace("{day}", &date.format("%d").to_string())
+        .replace("{area}", area)
+}
+
+/// Parses a list of `{ "NOK_per_kWh", "time_start", "time_end" }` objects.
+pub fn parse_prices(body: &str) -> Option<Vec<HourPrice>> {
+    let json: serde_json::Value = serde_json::from_str(body).ok()?;
+    json.as_array()?
+        .iter()
+        .map(|hour| {
+            Some(HourPrice {
+                start: DateTime::parse_from_rfc3339(hour["time_start"].as_str()?).ok()?,
+                end: DateTime::parse_from_rfc3339(hour["time_end"].as_str()?).ok()?,
+                price: hour["NOK_per_kWh"].as_f64()?,
+            })
+        })
+        .collect()
+}
+
+/// Finds the price of the hour containing `time`. The prices carry their own offsets,
+/// so days with 23 or 25 hours need no special handling.
+pub fn price_for(prices: &[HourPrice], time: DateTime<Utc>) -> Option<f64> {
+    prices
+        .iter()
+        .find(|hour| hour.start.with_timezone(&Utc) <= time && time < hour.end.with_timezone(&Utc))
+        .map(|hour| hour.price)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+    use chrono_tz::Europe::Oslo;
+
+    use super::*;
+
+    /// A day of prices as the API lists them, each hour priced at its index.
+    fn day(date: NaiveDate) -> String {
+        let mut start = Oslo
+            .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+            .single()
+            .unwrap();
+        let mut hours = Vec::new();
+        while start.date_naive() == date {
+            let end = (start.with_timezone(&Utc) + Duration::hours(1)).with_timezone(&Oslo);
+            let index = hours.len() as f64;
+            hours.push(serde_json::json!({
+                "NOK_per_kWh": index,
+                "EUR_per_kWh": 0.0,
+                "time_start": start.to_rfc3339(),
+                "time_end": end.to_rfc3339(),
+            }));
+            start = end;
+        }
+        serde_json::Value::from(hours).to_string()
+    }
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().into()
+    }
+
+    #[test]
+    fn parses_the_api_format() {
+        let body = r#"[
+            {"NOK_per_kWh": 1.25, "EUR_per_kWh": 0.11, "EXR": 11.3,
+             "time_start": "2024-01-15T00:00:00+01:00", "time_end": "2024-01-15T01:00:00+01:00"},
+            {"NOK_per_kWh": 0.5, "EUR_per_kWh": 0.04, "EXR": 11.3,
+             "time_start": "2024-01-15T01:00:00+01:00", "time_end": "2024-01-15T02:00:00+01:00"}
+        ]"#;
+        let prices = parse_prices(body).unwrap();
+        assert_eq!(prices.len(), 2);
+        assert_eq!(prices[0].price, 1.25);
+        assert_eq!(prices[1].start, prices[0].end);
+        assert_eq!(price_for(&prices, at("2024-01-14T23:59:59Z")), Some(1.25));
+        assert_eq!(price_for(&prices, at("2024-01-15T00:00:00Z")), Some(0.5));
+        assert_eq!(price_for(&prices, at("2024-01-15T01:00:00Z")), None);
+    }
+
+    #[test]
+    fn invalid_responses_are_none() {
+        assert_eq!(parse_prices("not json"), None);
+        assert_eq!(parse_prices(r#"{"NOK_per_kWh": 1.0}"#), None);
+        assert_eq!(
+            parse_prices(r#"[{"NOK_per_kWh": 1.0, "time_start": "noon", "time_end": "one"}]"#),
+            None
+        );
+    }
+
+    #[test]
+    fn a_23_hour_day_skips_two_oclock() {
+        let prices = parse_prices(&day(NaiveDate::from_ymd_opt(2024, 3, 31).unwrap())).unwrap();
+        assert_eq!(prices.len(), 23);
+        // 01:00 and 03:00 local time, an hour apart
+        assert_eq!(price_for(&prices, at("2024-03-31T00:30:00Z")), Some(1.0));
+        assert_eq!(price_for(&prices, at("2024-03-31T01:30:00Z")), Some(2.0));
+        assert_eq!(price_for(&prices, at("2024-03-31T21:30:00Z")), Some(22.0));
+        assert_eq!(price_for(&prices, at("2024-03-31T22:00:00Z")), None);
+    }
+
+    #[test]
+    fn a_25_hour_day_has_two_oclock_twice() {
+        let prices = parse_prices(&day(NaiveDate::from_ymd_opt(2024, 10, 27).unwrap())).unwrap();
+        assert_eq!(prices.len(), 25);
+        // 02:00 local time in summer time, then again in winter time
+        assert_eq!(price_for(&prices, at("2024-10-27T00:30:00Z")), Some(2.0));
+        assert_eq!(price_for(&prices, at("2024-10-27T01:30:00Z")), Some(3.0));
+        assert_eq!(price_for(&prices, at("2024-10-27T22:30:00Z")), Some(24.0));
+        assert_eq!(price_for(&prices, at("2024-10-27T23:00:00Z")), None);
+    }
+
+    #[test]
+    fn renders_the_date_and_area() {
+        let url = render_url(
+            DEFAULT_PRICE_URL,
+            NaiveDate::from_ymd_opt(2024, 3, 5).unwrap(),
+            "NO1",
+        );
+        assert_eq!(
+            url,
+            "https://www.hvakosterstrommen.no/api/v1/prices/2024/03-05_NO1.json"
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    env,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rocket::{
+    catch,
+    http::{Header, Status},
+    request::{FromRequest, Outcome},
+    Request, Responder,
+};
+use tracing::{debug, instrument, trace};
+
+/// Buckets that have been refilled and untouched for this long are dropped.
+const IDLE_BUCKET_LIFETIME: Duration = Duration::from_secs(600);
+
+/// Per client IP token buckets limiting how often the routes can be called.
+#[derive(Debug)]
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_seen: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        RateLimiter {
+            capacity: per_minute as f64,
+            refill_per_sec: per_minute as f64 / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[instrument(level = "trace")]
+    pub fn from_env() -> Result<Self, String> {
+        let per_minute = match env::var("RATE_LIMIT_PER_MINUTE") {
+            Ok(r) => parse_per_minute(&r)?,
+            Err(_) => 120,
+        };
+        tracing::info!("RATE_LIMIT_PER_MINUTE: {}", per_minute);
+        Ok(RateLimiter::new(per_minute))
+    }
+
+    /// Takes a token for the client, or returns how long until one is available.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.capacity,
+            last_seen: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_seen).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.last_seen = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.refill_per_sec,
+            ))
+        }
+    }
+
+    pub fn per_minute(&self) -> u32 {
+        self.capacity as u32
+    }
+
+    /// Clients with a bucket, those seen recently.
+    pub fn tracked_clients(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+
+    pub fn cleanup(&self) {
+        let mut buckets = self.buckets.lock().unwrap();
+        let before = buckets.len();
+        buckets.retain(|_, b| b.last_seen.elapsed() < IDLE_BUCKET_LIFETIME);
+        trace!("Dropped {} idle rate limit buckets", before - buckets.len());
+    }
+
+    pub async fn run_cleanup(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(IDLE_BUCKET_LIFETIME);
+        loop {
+            interval.tick().await;
+            self.cleanup();
+        }
+    }
+}
+
+/// Reads a limit, which must let at least one request through each minute.
+fn parse_per_minute(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(0) => Err(String::from("Illegal rate limit: 0, must be at least 1")),
+        Ok(per_minute) => Ok(per_minute),
+        Err(_) => Err(format!("Illegal rate limit format: {}", value)),
+    }
+}
+
+/// Request guard rejecting clients that exceeded the rate limit with a 429.
+pub struct RateLimited;
+
+struct RetryAfter(u64);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimited {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let (limiter, ip) = match (req.rocket().state::<Arc<RateLimiter>>(), req.client_ip()) {
+            (Some(limiter), Some(ip)) => (limiter, ip),
+            _ => return Outcome::Success(RateLimited),
+        };
+        match limiter.check(ip) {
+            Ok(()) => Outcome::Success(RateLimited),
+            Err(retry_after) => {
+                debug!("Rate limiting {}", ip);
+                req.local_cache(|| RetryAfter(retry_after.as_secs().max(1)));
+                Outcome::Error((Status::TooManyRequests, ()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Responder)]
+#[response(status = 429, content_type = "json")]
+pub struct TooManyRequests {
+    body: String,
+    retry_after: Header<'static>,
+}
+
+impl TooManyRequests {
+    pub fn new(retry_after_secs: u64) -> Self {
+        TooManyRequests {
+            body: serde_json::json!({ "error": "Rate limit exceeded" }).to_string(),
+            retry_after: Header::new("Retry-After", retry_after_secs.to_string()),
+        }
+    }
+}
+
+#[catch(429)]
+pub fn too_many_requests(req: &Request) -> TooManyRequests {
+    TooManyRequests::new(req.local_cache(|| RetryAfter(60)).0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use rocket::{get, local::asynchronous::Client, routes};
+
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn a_burst_is_limited_to_the_bucket() {
+        let limiter = RateLimiter::new(60);
+        let now = Instant::now();
+        for _ in 0..60 {
+            assert!(limiter.check_at(CLIENT, now).is_ok());
+        }
+        let wait = limiter.check_at(CLIENT, now).unwrap_err();
+        assert_eq!(wait, Duration::from_secs(1));
+        // Each client has its own bucket
+        assert!(limiter.check_at(OTHER, now).is_ok());
+        assert_eq!(limiter.tracked_clients(), 2);
+    }
+
+    #[test]
+    fn tokens_come_back_with_time() {
+        let limiter = RateLimiter::new(60);
+        let start = Instant::now();
+        for _ in 0..60 {
+            limiter.check_at(CLIENT, start).unwrap();
+        }
+        assert!(limiter.check_at(CLIENT, start).is_err());
+        let later = start + Duration::from_secs(2);
+        assert!(limiter.check_at(CLIENT, later).is_ok());
+        assert!(limiter.check_at(CLIENT, later).is_ok());
+        assert!(limiter.check_at(CLIENT, later).is_err());
+        // Never more than the bucket holds, however long the client was away
+        let much_later = start + Duration::from_secs(3600);
+        for _ in 0..60 {
+            limiter.check_at(CLIENT, much_later).unwrap();
+        }
+        assert!(limiter.check_at(CLIENT, much_later).is_err());
+    }
+
+    #[test]
+    fn a_limit_of_zero_is_rejected() {
+        let error = parse_per_minute("0").unwrap_err();
+        assert!(error.starts_with("Illegal rate limit"), "{}", error);
+        assert!(parse_per_minute("soon").is_err());
+        assert_eq!(parse_per_minute("1"), Ok(1));
+    }
+
+    #[get("/limited")]
+    fn limited(_limit: RateLimited) -> &'static str {
+        "ok"
+    }
+
+    #[rocket::async_test]
+    async fn routes_answer_429_with_retry_after() {
+        let rocket = rocket::build()
+            .mount("/", routes![limited])
+            .register("/", rocket::catchers![too_many_requests])
+            .manage(Arc::new(RateLimiter::new(3)));
+        let client = Client::untracked(rocket).await.unwrap();
+        let remote = SocketAddr::new(CLIENT, 8000);
+        for _ in 0..3 {
+            let response = client.get("/limited").remote(remote).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+        }
+        let response = client.get("/limited").remote(remote).dispatch().await;
+        assert_eq!(response.status(), Status::TooManyRequests);
+        // A token every 20 seconds, less the time the requests took
+        let retry_after: u64 = response
+            .headers()
+            .get_one("Retry-After")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((19..=20).contains(&retry_after), "{}", retry_after);
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::BTreeMap,
+    env, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use serde_json::Value;
+use tracing::{instrument, trace, warn};
+
+const REDACTED: &str = "REDACTED";
+const SECRET_KEYS: [&str; 8] = [
+    "accessToken",
+    "refreshToken",
+    "password",
+    "userName",
+    "username",
+    "userId",
+    "accountId",
+    "email",
+];
+
+/// Replaces tokens and account identifiers in a JSON body.
+///
+/// Bodies that are not JSON are replaced entirely, as they can't be scrubbed reliably.
+pub fn redact(body: &str) -> String {
+    match serde_json::from_str::<Value>(body) {
+        Ok(mut json) => {
+            redact_value(&mut json);
+            json.to_string()
+        }
+        Err(_) => String::from(REDACTED),
+    }
+}
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if SECRET_KEYS.contains(&key.as_str()) {
+                    *value = Value::String(String::from(REDACTED));
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_value),
+        Value::String(s) if looks_like_jwt(s) => *s = String::from(REDACTED),
+        _ => (),
+    }
+}
+
+/// Replaces token-like words in free text, such as error messages.
+pub fn redact_text(text: &str) -> String {
+    text.split(' ')
+        .map(|word| if looks_like_jwt(word) { REDACTED } else { word })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn looks_like_jwt(s: &str) -> bool {
+    s.starts_with("eyJ") && s.split('.').count() == 3
+}
+
+/// Writes a successful Easee response to `RECORD_RESPONSES_DIR`, if set, for use as a fixture.
+#[instrument(skip(body), level = "trace")]
+pub async fn record_response(endpoint: &str, charger_id: Option<&str>, body: &str) {
+    let dir = match env::var("RECORD_RESPONSES_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => return,
+    };
+
+    let mut name = format!("{}_{}", Utc::now().format("%Y%m%dT%H%M%S%.3fZ"), endpoint);
+    if let Some(id) = charger_id {
+        name.push('_');
+        name.push_str(id);
+    }
+    let path = dir.join(name + ".json");
+
+    match tokio::fs::write(&path, redact(body)).await {
+        Ok(_) => trace!("Recorded response to {}", path.display()),
+        Err(e) => warn!("Failed to record response to {}: {}", path.display(), e),
+    }
+}
+
+/// The endpoints [`record_response`] is called for, the longest first, as `refresh_token`
+/// has the separator in its name.
+const ENDPOINTS: [&str; 7] = [
+    "refresh_token",
+    "chargers",
+    "sessions",
+    "details",
+    "config",
+    "login",
+    "state",
+];
+
+/// A response recorded by [`record_response`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recorded {
+    pub endpoint: String,
+    pub charger_id: Option<String>,
+    pub body: String,
+}
+
+impl Recorded {
+    /// Reads a name such as `20240101T120000.000Z_state_EH000001.json`.
+    fn from_name(name: &str, body: String) -> Option<Self> {
+        let (_, rest) = name.strip_suffix(".json")?.split_once('_')?;
+        let endpoint = ENDPOINTS.iter().find(|e| rest.starts_with(**e))?;
+        let charger_id = match &rest[endpoint.len()..] {
+            "" => None,
+            id => Some(id.strip_prefix('_')?.to_string()),
+        };
+        Some(Recorded {
+            endpoint: endpoint.to_string(),
+            charger_id,
+            body,
+        })
+    }
+
+    /// The method and the path below the API base the response answered, as a regex, the
+    /// sessions path having dates in it.
+    pub fn route(&self) -> (&'static str, String) {
+        let id = self.charger_id.as_deref().unwrap_or_default();
+        match self.endpoint.as_str() {
+            "login" => ("POST", String::from("/accounts/login")),
+            "refresh_token" => ("POST", String::from("/accounts/refresh_token")),
+            "chargers" => ("GET", String::from("/chargers")),
+            "sessions" => ("GET", format!("/sessions/charger/{}/sessions/.+", id)),
+            endpoint => ("GET", format!("/chargers/{}/{}", id, endpoint)),
+        }
+    }
+}
+
+/// The responses recorded in `dir`, the latest of each endpoint and charger, to serve them
+/// again from a mock Easee. Files not named by [`record_response`] are skipped.
+pub fn recorded_responses(dir: &Path) -> io::Result<Vec<Recorded>> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    // The timestamps come first, so the latest sorts last and replaces the others
+    names.sort();
+    let mut latest = BTreeMap::new();
+    for name in names {
+        let body = std::fs::read_to_string(dir.join(&name))?;
+        match Recorded::from_name(&name, body) {
+            Some(recorded) => {
+                let key = (recorded.endpoint.clone(), recorded.charger_id.clone());
+                latest.insert(key, recorded);
+            }
+            None => trace!("Skipping {}, not a recorded response", name),
+        }
+    }
+    Ok(latest.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_names_are_read() {
+        let read = |name: &str| Recorded::from_name(name, String::new());
+        let state = read("20240101T120000.000Z_state_EH000001.json").unwrap();
+        assert_eq!(state.endpoint, "state");
+        assert_eq!(state.charger_id.as_deref(), Some("EH000001"));
+        assert_eq!(
+            state.route(),
+            ("GET", String::from("/chargers/EH000001/state"))
+        );
+
+        let refresh = read("20240101T120000.000Z_refresh_token.json").unwrap();
+        assert_eq!(refresh.endpoint, "refresh_token");
+        assert_eq!(refresh.charger_id, None);
+        assert_eq!(
+            refresh.route(),
+            ("POST", String::from("/accounts/refresh_token"))
+        );
+
+        for other in [
+            "notes.txt",
+            "20240101T120000.000Z_unknown.json",
+            "20240101T120000.000Z_stateEH000001.json",
+        ] {
+            assert_eq!(read(other), None, "{}", other);
+        }
+    }
+
+    #[test]
+    fn the_latest_recording_is_replayed() {
+        let dir = env::temp_dir().join(format!("record-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, body) in [
+            ("20240101T120000.000Z_state_EH000001.json", "old"),
+            ("20240101T120500.000Z_state_EH000001.json", "new"),
+            ("20240101T120000.000Z_state_EH000002.json", "other"),
+            ("README.md", "skipped"),
+        ] {
+            std::fs::write(dir.join(name), body).unwrap();
+        }
+
+        let bodies: Vec<_> = recorded_responses(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.body)
+            .collect();
+        assert_eq!(bodies, vec!["new", "other"]);
+    }
+}
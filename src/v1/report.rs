@@ -0,0 +1,218 @@
+use std::env;
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use influxdb::{Client, ReadQuery};
+use rocket::FromFormField;
+use serde::Serialize;
+use tracing::{instrument, trace, warn};
+
+/// Periods an energy report can cover. Only these reach the query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum Period {
+    Today,
+    Week,
+    Month,
+}
+
+impl Period {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Period::Today => "today",
+            Period::Week => "week",
+            Period::Month => "month",
+        }
+    }
+
+    /// The local date the period started on, weeks starting on Monday.
+    pub fn start_date(&self, today: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Today => today,
+            Period::Week => {
+                today - chrono::Duration::days(today.weekday().num_days_from_monday() as i64)
+            }
+            Period::Month => today.with_day(1).unwrap(),
+        }
+    }
+}
+
+/// Timezone the report periods are aligned to, from `REPORT_TIMEZONE`.
+#[derive(Debug, Clone, Copy)]
+pub enum ReportTimezone {
+    Local,
+    Named(Tz),
+}
+
+impl ReportTimezone {
+    #[instrument(level = "trace")]
+    pub fn from_env() -> Result<Self, String> {
+        let tz = match env::var("REPORT_TIMEZONE") {
+            Ok(tz) => ReportTimezone::Named(
+                tz.parse()
+                    .map_err(|_| format!("Illegal report timezone: {}", tz))?,
+            ),
+            Err(_) => ReportTimezone::Local,
+        };
+        tracing::info!("REPORT_TIMEZONE: {:?}", tz);
+        Ok(tz)
+    }
+
+    /// The local date `now` falls on.
+    pub fn date(&self, now: DateTime<Utc>) -> NaiveDate {
+        match self {
+            ReportTimezone::Local => now.with_timezone(&Local).date_naive(),
+            ReportTimezone::Named(tz) => now.with_timezone(tz).date_naive(),
+        }
+    }
+
+    /// Start of the period containing `now`.
+    pub fn period_start(&self, period: Period, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            ReportTimezone::Local => start_in(&Local, period, now),
+            ReportTimezone::Named(tz) => start_in(tz, period, now),
+        }
+    }
+}
+
+fn start_in<T: TimeZone>(tz: &T, period: Period, now: DateTime<Utc>) -> DateTime<Utc> {
+    let today = now.with_timezone(tz).date_naive();
+    let midnight = period.start_date(today).and_hms_opt(0, 0, 0).unwrap();
+    // Midnight may not exist on DST changes, the earliest hour after it does
+    (0..3)
+        .find_map(|h| {
+            tz.from_local_datetime(&(midnight + chrono::Duration::hours(h)))
+                .earliest()
+        })
+        .map_or(now, |t| t.with_timezone(&Utc))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EnergyReport {
+    pub charger_id: String,
+    pub period: &'static str,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub kwh: f64,
+}
+
+/// Energy charged between `from` and `to`, integrating the recorded power.
+#[instrument(skip(client), level = "trace")]
+pub async fn energy(
+    client: &Client,
+    charger_id: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<f64, String> {
+    let query = format!(
+        "SELECT INTEGRAL(\"value\", 1h) FROM \"{}\" WHERE \"variable\" = 'power' AND time >= '{}' AND time < '{}'",
+        escape_identifier(charger_id),
+        from.to_rfc3339(),
+        to.to_rfc3339()
+    );
+    trace!("Query: {}", query);
+    let body = client.query(ReadQuery::new(query)).await.map_err(|e| {
+        warn!("Energy query failed: {}", e);
+        format!("Energy query failed: {}", e)
+    })?;
+    parse_single_value(&body).ok_or_else(|| String::from("Invalid InfluxDB response"))
+}
+
+fn escape_identifier(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Reads the single value of an aggregation. No series means no data, which is no energy.
+pub fn parse_single_value(body: &str) -> Option<f64> {
+    let json: serde_json::Value = serde_json::from_str(body).ok()?;
+    let result = json["results"].get(0)?;
+    if let Some(e) = result["error"].as_str() {
+        warn!("InfluxDB error: {}", e);
+        return None;
+    }
+    match result["series"].get(0) {
+        Some(series) => series["values"].get(0)?.get(1)?.as_f64(),
+        None => Some(0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn period_start_dates() {
+        // A Wednesday
+        let today = date(2024, 1, 31);
+        assert_eq!(Period::Today.start_date(today), today);
+        assert_eq!(Period::Week.start_date(today), date(2024, 1, 29));
+        assert_eq!(Period::Month.start_date(today), date(2024, 1, 1));
+        // Weeks crossing a month and a year
+        assert_eq!(Period::Week.start_date(date(2024, 3, 1)), date(2024, 2, 26));
+        assert_eq!(
+            Period::Week.start_date(date(2025, 1, 1)),
+            date(2024, 12, 30)
+        );
+        // Mondays start their own week
+        assert_eq!(Period::Week.start_date(date(2024, 4, 1)), date(2024, 4, 1));
+        assert_eq!(
+            Period::Month.start_date(date(2024, 2, 29)),
+            date(2024, 2, 1)
+        );
+    }
+
+    #[test]
+    fn periods_start_at_local_midnight() {
+        let oslo = ReportTimezone::Named(chrono_tz::Europe::Oslo);
+        // 00:30 on the 1st in Oslo is still the 31st in UTC
+        let now = utc(2024, 1, 31, 23) + chrono::Duration::minutes(30);
+        assert_eq!(oslo.date(now), date(2024, 2, 1));
+        assert_eq!(oslo.period_start(Period::Month, now), utc(2024, 1, 31, 23));
+        assert_eq!(oslo.period_start(Period::Today, now), utc(2024, 1, 31, 23));
+        // Summer time
+        assert_eq!(
+            oslo.period_start(Period::Month, utc(2024, 7, 15, 12)),
+            utc(2024, 6, 30, 22)
+        );
+        // The month started before the change to summer time
+        assert_eq!(
+            oslo.period_start(Period::Month, utc(2024, 3, 31, 12)),
+            utc(2024, 2, 29, 23)
+        );
+    }
+
+    #[test]
+    fn missing_midnight_starts_at_the_first_hour() {
+        // Santiago skipped from 00:00 to 01:00 on 2024-09-08
+        let santiago = ReportTimezone::Named(chrono_tz::America::Santiago);
+        assert_eq!(
+            santiago.period_start(Period::Today, utc(2024, 9, 8, 15)),
+            utc(2024, 9, 8, 4)
+        );
+    }
+
+    #[test]
+    fn reads_single_values() {
+        let value = r#"{"results":[{"statement_id":0,"series":[{"name":"EH1","columns":["time","integral"],"values":[["1970-01-01T00:00:00Z",12.5]]}]}]}"#;
+        assert_eq!(parse_single_value(value), Some(12.5));
+        let empty = r#"{"results":[{"statement_id":0}]}"#;
+        assert_eq!(parse_single_value(empty), Some(0.0));
+        let error = r#"{"results":[{"statement_id":0,"error":"database not found"}]}"#;
+        assert_eq!(parse_single_value(error), None);
+        assert_eq!(parse_single_value("<html>"), None);
+        assert_eq!(parse_single_value(r#"{"results":[]}"#), None);
+    }
+
+    #[test]
+    fn escapes_identifiers() {
+        assert_eq!(escape_identifier("EH000001"), "EH000001");
+        assert_eq!(escape_identifier(r#"a"b\c"#), r#"a\"b\\c"#);
+    }
+}
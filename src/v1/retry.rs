@@ -0,0 +1,356 @@
+use std::{collections::VecDeque, env, fs, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+use tracing::{debug, info, instrument, warn};
+
+use super::run::{influx_batch_size, DbTarget};
+
+/// Points that failed to be written, kept to be retried on the next tick.
+///
+/// With `RETRY_BUFFER_PATH` set the points are also kept in that file as JSON lines,
+/// so they survive a restart.
+#[derive(Debug)]
+pub struct RetryBuffer {
+    path: Option<PathBuf>,
+    max_bytes: usize,
+    points: Mutex<VecDeque<BufferedPoint>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BufferedPoint {
+    /// Address of the target the point failed to be written to.
+    target: String,
+    /// The point as line protocol.
+    line: String,
+}
+
+impl BufferedPoint {
+    fn to_json_line(&self) -> String {
+        serde_json::to_string(self).unwrap() + "\n"
+    }
+}
+
+impl RetryBuffer {
+    pub fn new(path: Option<PathBuf>, max_bytes: usize) -> Self {
+        let points = path.as_ref().map(load).unwrap_or_default();
+        RetryBuffer {
+            path,
+            max_bytes,
+            points: Mutex::new(points),
+        }
+    }
+
+    #[instrument(level = "trace")]
+    pub fn from_env() -> Self {
+        let path = env::var("RETRY_BUFFER_PATH").ok().map(PathBuf::from);
+        tracing::info!("RETRY_BUFFER_PATH: {:?}", path);
+
+        let max_bytes = env::var("RETRY_BUFFER_MAX_BYTES").map_or(10_000_000, |b| {
+            b.parse().expect("Illegal retry buffer size format")
+        });
+        tracing::info!("RETRY_BUFFER_MAX_BYTES: {}", max_bytes);
+
+        RetryBuffer::new(path, max_bytes)
+    }
+
+    pub async fn len(&self) -> usize {
+        self.points.lock().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.points.lock().await.is_empty()
+    }
+
+    /// Keeps failed points, dropping the oldest ones once the buffer is full.
+    pub async fn push(&self, target: &str, lines: Vec<String>) {
+        if lines.is_empty() {
+            return;
+        }
+        let mut points = self.points.lock().await;
+        let new: Vec<BufferedPoint> = lines
+            .into_iter()
+            .map(|line| BufferedPoint {
+                target: target.to_string(),
+                line,
+            })
+            .collect();
+        points.extend(new.iter().cloned());
+
+        let mut size: usize = points.iter().map(|p| p.to_json_line().len()).sum();
+        let mut evicted = 0;
+        while size > self.max_bytes {
+            match points.pop_front() {
+                Some(p) => size -= p.to_json_line().len(),
+                None => break,
+            }
+            evicted += 1;
+        }
+        if evicted > 0 {
+            warn!("Retry buffer full, dropped the {} oldest points", evicted);
+            self.rewrite(&points).await;
+        } else {
+            self.append(&new).await;
+        }
+    }
+
+    /// Writes the points buffered for the target in batches, forgetting those written.
+    ///
+    /// Stops at the first failed batch, which stays buffered along with the rest.
+    #[instrument(skip_all, fields(target = %target.addr), level = "trace")]
+    pub async fn flush(&self, target: &DbTarget) -> Result<usize, String> {
+        let mut points = self.points.lock().await;
+        let lines: Vec<String> = points
+            .iter()
+            .filter(|p| p.target == target.addr)
+            .map(|p| p.line.clone())
+            .collect();
+        if lines.is_empty() {
+            return Ok(0);
+        }
+        let mut flushed = 0;
+        let mut result = Ok(());
+        for batch in lines.chunks(influx_batch_size()) {
+            result = target.write_lines(&batch.join("\n")).await;
+            if result.is_err() {
+                break;
+            }
+            flushed += batch.len();
+        }
+        if flushed > 0 {
+            // Buffered points keep their order, so the written ones are the target's first
+            let mut skip = flushed;
+            points.retain(|p| {
+                if skip > 0 && p.target == target.addr {
+                    skip -= 1;
+                    false
+                } else {
+                    true
+                }
+            });
+            self.rewrite(&points).await;
+            info!("Wrote {} buffered points", flushed);
+        }
+        result.map(|()| flushed)
+    }
+
+    async fn append(&self, new: &[BufferedPoint]) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+        let contents: String = new.iter().map(BufferedPoint::to_json_line).collect();
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await;
+        // Flushed, as a tokio file finishes writing in the background otherwise
+        let result = match file {
+            Ok(mut file) => match file.write_all(contents.as_bytes()).await {
+                Ok(()) => file.flush().await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!("Failed to append to {}: {}", path.display(), e);
+        }
+    }
+
+    async fn rewrite(&self, points: &VecDeque<BufferedPoint>) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+        let contents: String = points.iter().map(BufferedPoint::to_json_line).collect();
+        if let Err(e) = tokio::fs::write(path, contents).await {
+            warn!("Failed to rewrite {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Reads the points left by a previous run, skipping lines that don't parse.
+fn load(path: &PathBuf) -> VecDeque<BufferedPoint> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            debug!("No retry buffer read from {}: {}", path.display(), e);
+            return VecDeque::new();
+        }
+    };
+    let points: VecDeque<BufferedPoint> = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(point) => Some(point),
+            Err(e) => {
+                warn!("Skipping corrupt retry buffer line: {}", e);
+                None
+            }
+        })
+        .collect();
+    info!(
+        "Recovered {} buffered points from {}",
+        points.len(),
+        path.display()
+    );
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::v1::sink::{MemorySink, Sink};
+
+    /// Keeps what it is sent while up.
+    #[derive(Debug, Default)]
+    struct TestSink {
+        down: AtomicBool,
+        memory: MemorySink,
+    }
+
+    #[rocket::async_trait]
+    impl Sink for TestSink {
+        async fn write_lines(&self, lines: &str) -> Result<(), String> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(String::from("down"));
+            }
+            self.memory.write_lines(lines).await
+        }
+    }
+
+    fn target(addr: &str) -> (DbTarget, Arc<TestSink>) {
+        let sink = Arc::new(TestSink::default());
+        let target = DbTarget::with_sink(addr.to_string(), String::from("easee"), sink.clone());
+        (target, sink)
+    }
+
+    fn lines(prefix: &str, n: usize) -> Vec<String> {
+        (0..n)
+            .map(|i| format!("{} value={} {}", prefix, i, i))
+            .collect()
+    }
+
+    /// A file of its own per test, gone when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!(
+                "easee_status_retry_{}_{}.jsonl",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_file(&path);
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn flushes_the_points_of_the_target() {
+        let buffer = RetryBuffer::new(None, 1_000_000);
+        let (first, first_sink) = target("http://first");
+        let (second, _) = target("http://second");
+        buffer.push("http://first", lines("a", 3)).await;
+        buffer.push("http://second", lines("b", 2)).await;
+        buffer.push("http://first", Vec::new()).await;
+        assert_eq!(buffer.len().await, 5);
+
+        assert_eq!(buffer.flush(&first).await, Ok(3));
+        assert_eq!(first_sink.memory.lines(), lines("a", 3));
+        assert_eq!(buffer.len().await, 2);
+        assert_eq!(buffer.flush(&first).await, Ok(0));
+        assert_eq!(buffer.flush(&second).await, Ok(2));
+        assert!(buffer.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn a_failed_flush_keeps_the_points() {
+        let buffer = RetryBuffer::new(None, 1_000_000);
+        let (target, sink) = target("http://influx");
+        buffer.push("http://influx", lines("a", 3)).await;
+        sink.down.store(true, Ordering::SeqCst);
+        assert!(buffer.flush(&target).await.is_err());
+        assert_eq!(buffer.len().await, 3);
+        sink.down.store(false, Ordering::SeqCst);
+        assert_eq!(buffer.flush(&target).await, Ok(3));
+    }
+
+    #[tokio::test]
+    async fn the_oldest_points_are_dropped_when_full() {
+        let point_size = BufferedPoint {
+            target: String::from("http://influx"),
+            line: lines("a", 1).remove(0),
+        }
+        .to_json_line()
+        .len();
+        let buffer = RetryBuffer::new(None, point_size * 3);
+        buffer.push("http://influx", lines("a", 5)).await;
+        assert_eq!(buffer.len().await, 3);
+        let (target, sink) = target("http://influx");
+        buffer.flush(&target).await.unwrap();
+        assert_eq!(sink.memory.lines(), lines("a", 5)[2..].to_vec());
+    }
+
+    #[tokio::test]
+    async fn points_survive_a_restart() {
+        let file = TempFile::new("restart");
+        let (target, sink) = target("http://influx");
+        {
+            let buffer = RetryBuffer::new(Some(file.0.clone()), 1_000_000);
+            buffer.push("http://influx", lines("a", 2)).await;
+            buffer.push("http://influx", lines("b", 1)).await;
+        }
+        let restarted = RetryBuffer::new(Some(file.0.clone()), 1_000_000);
+        assert_eq!(restarted.len().await, 3);
+        assert_eq!(restarted.flush(&target).await, Ok(3));
+        let mut expected = lines("a", 2);
+        expected.extend(lines("b", 1));
+        assert_eq!(sink.memory.lines(), expected);
+        // Flushed points are gone from the file too
+        assert!(
+            RetryBuffer::new(Some(file.0.clone()), 1_000_000)
+                .is_empty()
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn the_size_cap_holds_across_a_restart() {
+        let file = TempFile::new("cap");
+        let point_size = BufferedPoint {
+            target: String::from("http://influx"),
+            line: lines("a", 1).remove(0),
+        }
+        .to_json_line()
+        .len();
+        RetryBuffer::new(Some(file.0.clone()), point_size * 2)
+            .push("http://influx", lines("a", 4))
+            .await;
+        let restarted = RetryBuffer::new(Some(file.0.clone()), point_size * 2);
+        assert_eq!(restarted.len().await, 2);
+    }
+
+    #[tokio::test]
+    async fn corrupt_lines_are_skipped() {
+        let file = TempFile::new("corrupt");
+        let point = BufferedPoint {
+            target: String::from("http://influx"),
+            line: String::from("a value=1 1"),
+        };
+        fs::write(&file.0, format!("{}not json\n\n", point.to_json_line())).unwrap();
+        assert_eq!(RetryBuffer::new(Some(file.0.clone()), 1_000).len().await, 1);
+    }
+}
@@ -0,0 +1,223 @@
+use std::{collections::HashMap, env};
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use influxdb::{InfluxDbWriteable, WriteQuery};
+use tracing::instrument;
+
+use super::structs::ChargerState;
+
+/// Measurement the hourly power rollups are written to.
+pub const ROLLUP_MEASUREMENT: &str = "hourly_rollup";
+
+#[derive(Debug, Clone, InfluxDbWriteable)]
+struct RollupPoint {
+    time: DateTime<Utc>,
+    power_min: f64,
+    power_avg: f64,
+    power_max: f64,
+    samples: i64,
+}
+
+/// The samples of one charger during one hour.
+#[derive(Debug, Clone)]
+struct Hour {
+    start: DateTime<Utc>,
+    name: String,
+    min: f64,
+    max: f64,
+    sum: f64,
+    samples: i64,
+}
+
+impl Hour {
+    fn new(start: DateTime<Utc>, name: &str, power: f64) -> Self {
+        Hour {
+            start,
+            name: name.to_string(),
+            min: power,
+            max: power,
+            sum: power,
+            samples: 1,
+        }
+    }
+
+    fn add(&mut self, power: f64) {
+        self.min = self.min.min(power);
+        self.max = self.max.max(power);
+        self.sum += power;
+        self.samples += 1;
+    }
+}
+
+/// Aggregates the power of each charger per hour, enabled by `ROLLUPS`. An hour is written
+/// by the first tick after it ends, timestamped at its start.
+#[derive(Debug)]
+pub struct HourlyRollup {
+    enabled: bool,
+    /// Hours starting before this missed samples, they are tagged `partial`.
+    started: DateTime<Utc>,
+    hours: HashMap<String, Hour>,
+}
+
+/// The start of the hour `time` falls in.
+pub fn hour_start(time: DateTime<Utc>) -> DateTime<Utc> {
+    time.duration_trunc(Duration::hours(1)).unwrap()
+}
+
+impl HourlyRollup {
+    pub fn new(enabled: bool, started: DateTime<Utc>) -> Self {
+        HourlyRollup {
+            enabled,
+            started,
+            hours: HashMap::new(),
+        }
+    }
+
+    #[instrument(level = "trace")]
+    pub fn from_env() -> Self {
+        let enabled = env::var("ROLLUPS").is_ok_and(|r| r == "true");
+        tracing::info!("ROLLUPS: {}", enabled);
+        HourlyRollup::new(enabled, Utc::now())
+    }
+
+    /// Adds the tick, returning the points of the hours it ended.
+    pub fn observe(&mut self, state: &[ChargerState], time: DateTime<Utc>) -> Vec<WriteQuery> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let start = hour_start(time);
+        let mut points = Vec::new();
+        for charger in state {
+            if let Some(hour) = self.hours.get_mut(&charger.id) {
+                if hour.start == start {
+                    hour.add(charger.power);
+                    continue;
+                }
+            }
+            let ended = self.hours.insert(
+                charger.id.clone(),
+                Hour::new(start, &charger.name, charger.power),
+            );
+            if let Some(ended) = ended {
+                points.push(self.point(&charger.id, &ended));
+            }
+        }
+        points
+    }
+
+    pub fn forget(&mut self, charger_id: &str) {
+        self.hours.remove(charger_id);
+    }
+
+    fn point(&self, charger_id: &str, hour: &Hour) -> WriteQuery {
+        RollupPoint {
+            time: hour.start,
+            power_min: hour.min,
+            power_avg: hour.sum / hour.samples as f64,
+            power_max: hour.max,
+            samples: hour.samples,
+        }
+        .into_query(ROLLUP_MEASUREMENT)
+        .add_tag("charger_id", charger_id)
+        .add_tag("name", hour.name.as_str())
+        .add_tag("partial", hour.start < self.started)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use influxdb::Query;
+
+    use super::*;
+
+    /// 2023-11-14 23:00 UTC, on an hour boundary.
+    const HOUR: i64 = 1_700_002_800;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(HOUR + secs, 0).unwrap()
+    }
+
+    fn power(id: &str, kw: f64) -> ChargerState {
+        ChargerState::builder(id).name("Garage").power(kw).build()
+    }
+
+    fn lines(points: Vec<WriteQuery>) -> Vec<String> {
+        points
+            .into_iter()
+            .map(|point| point.build().unwrap().get())
+            .collect()
+    }
+
+    #[test]
+    fn truncates_to_the_hour() {
+        assert_eq!(hour_start(at(0)), at(0));
+        assert_eq!(hour_start(at(3599)), at(0));
+        assert_eq!(hour_start(at(3600)), at(3600));
+        assert_eq!(hour_start(at(-1)), at(-3600));
+    }
+
+    #[test]
+    fn writes_the_hour_once_it_ends() {
+        let mut rollup = HourlyRollup::new(true, at(-10));
+        assert!(rollup.observe(&[power("EH000001", 2.0)], at(0)).is_empty());
+        assert!(rollup
+            .observe(&[power("EH000001", 7.0)], at(1200))
+            .is_empty());
+        assert!(rollup
+            .observe(&[power("EH000001", 3.0)], at(3599))
+            .is_empty());
+
+        let lines = lines(rollup.observe(&[power("EH000001", 5.0)], at(3600)));
+        assert_eq!(lines.len(), 1);
+        let line = &lines[0];
+        assert!(line.starts_with("hourly_rollup,"), "{}", line);
+        assert!(line.contains("charger_id=EH000001"), "{}", line);
+        assert!(line.contains("name=Garage"), "{}", line);
+        assert!(line.contains("partial=false"), "{}", line);
+        assert!(line.contains("power_min=2"), "{}", line);
+        assert!(line.contains("power_avg=4"), "{}", line);
+        assert!(line.contains("power_max=7"), "{}", line);
+        assert!(line.contains("samples=3i"), "{}", line);
+        assert!(line.ends_with(&format!(" {}000000000", HOUR)), "{}", line);
+    }
+
+    #[test]
+    fn first_hour_after_start_is_partial() {
+        let mut rollup = HourlyRollup::new(true, at(1800));
+        rollup.observe(&[power("EH000001", 1.0)], at(1800));
+        let lines = lines(rollup.observe(&[power("EH000001", 1.0)], at(3600)));
+        assert!(lines[0].contains("partial=true"), "{}", lines[0]);
+    }
+
+    #[test]
+    fn chargers_roll_up_separately() {
+        let mut rollup = HourlyRollup::new(true, at(0));
+        rollup.observe(&[power("EH000001", 1.0), power("EH000002", 10.0)], at(0));
+        rollup.observe(&[power("EH000001", 3.0)], at(60));
+        let lines =
+            lines(rollup.observe(&[power("EH000001", 0.0), power("EH000002", 0.0)], at(3600)));
+        assert_eq!(lines.len(), 2);
+        let first = lines.iter().find(|l| l.contains("EH000001")).unwrap();
+        let second = lines.iter().find(|l| l.contains("EH000002")).unwrap();
+        assert!(first.contains("power_avg=2"), "{}", first);
+        assert!(first.contains("samples=2i"), "{}", first);
+        assert!(second.contains("power_avg=10"), "{}", second);
+        assert!(second.contains("samples=1i"), "{}", second);
+    }
+
+    #[test]
+    fn forgotten_and_disabled_chargers_write_nothing() {
+        let mut rollup = HourlyRollup::new(true, at(0));
+        rollup.observe(&[power("EH000001", 1.0)], at(0));
+        rollup.forget("EH000001");
+        assert!(rollup
+            .observe(&[power("EH000001", 1.0)], at(3600))
+            .is_empty());
+
+        let mut disabled = HourlyRollup::new(false, at(0));
+        disabled.observe(&[power("EH000001", 1.0)], at(0));
+        assert!(disabled
+            .observe(&[power("EH000001", 1.0)], at(3600))
+            .is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+use tracing::info;
+
+use super::{anonymize::log_id, structs::ChargerInfo};
+
+/// Charger names by id.
+type Roster = HashMap<String, Option<String>>;
+
+/// The charger list Easee sent last, by id, so a swapped charger shows up in the logs.
+static KNOWN: OnceLock<Mutex<Option<Roster>>> = OnceLock::new();
+
+/// How the charger list changed since it was last fetched.
+#[derive(Debug, Default)]
+pub struct RosterChanges {
+    pub added: Vec<ChargerInfo>,
+    pub removed: Vec<String>,
+    /// Id, old name and new name.
+    pub renamed: Vec<(String, Option<String>, Option<String>)>,
+}
+
+/// Compares the chargers against the previous list by id.
+pub fn diff(previous: &HashMap<String, Option<String>>, chargers: &[ChargerInfo]) -> RosterChanges {
+    let mut changes = RosterChanges::default();
+    for charger in chargers {
+        match previous.get(&charger.id) {
+            None => changes.added.push(charger.clone()),
+            Some(name) if *name != charger.name => {
+                changes
+                    .renamed
+                    .push((charger.id.clone(), name.clone(), charger.name.clone()))
+            }
+            Some(_) => (),
+        }
+    }
+    let mut removed: Vec<String> = previous
+        .keys()
+        .filter(|id| !chargers.iter().any(|c| &c.id == *id))
+        .cloned()
+        .collect();
+    removed.sort();
+    changes.removed = removed;
+    changes
+}
+
+/// Logs chargers added, removed or renamed since the last list. The first list is only
+/// remembered.
+pub fn observe(chargers: &[ChargerInfo]) {
+    let current: HashMap<String, Option<String>> = chargers
+        .iter()
+        .map(|c| (c.id.clone(), c.name.clone()))
+        .collect();
+    let mut known = KNOWN.get_or_init(|| Mutex::new(None)).lock().unwrap();
+    if let Some(previous) = known.as_ref() {
+        let changes = diff(previous, chargers);
+        for charger in &changes.added {
+            info!(
+                "charger added: {} ({})",
+                log_id(&charger.id),
+                charger.name.as_deref().unwrap_or("unnamed")
+            );
+        }
+        for id in &changes.removed {
+            info!("charger removed: {}", log_id(id));
+        }
+        for (id, old, new) in &changes.renamed {
+            info!(
+                "charger renamed: {} ({} -> {})",
+                log_id(id),
+                old.as_deref().unwrap_or("unnamed"),
+                new.as_deref().unwrap_or("unnamed")
+            );
+        }
+    }
+    *known = Some(current);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn charger(id: &str, name: Option<&str>) -> ChargerInfo {
+        ChargerInfo {
+            id: id.to_string(),
+            name: name.map(str::to_string),
+        }
+    }
+
+    fn roster(chargers: &[ChargerInfo]) -> Roster {
+        chargers
+            .iter()
+            .map(|c| (c.id.clone(), c.name.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_list_has_no_changes() {
+        let chargers = [charger("EH1", Some("Garage")), charger("EH2", None)];
+        let changes = diff(&roster(&chargers), &chargers);
+        assert!(changes.added.is_empty());
+        assert!(changes.removed.is_empty());
+        assert!(changes.renamed.is_empty());
+    }
+
+    #[test]
+    fn finds_added_removed_and_renamed_by_id() {
+        let previous = roster(&[
+            charger("EH1", Some("Garage")),
+            charger("EH2", Some("Driveway")),
+            charger("EH3", None),
+            charger("EH4", Some("Barn")),
+        ]);
+        let current = [
+            charger("EH2", Some("Front")),
+            charger("EH1", Some("Garage")),
+            charger("EH3", Some("Carport")),
+            charger("EH5", Some("Barn")),
+        ];
+        let changes = diff(&previous, &current);
+        assert_eq!(
+            changes
+                .added
+                .iter()
+                .map(|c| c.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["EH5"]
+        );
+        assert_eq!(changes.removed, vec!["EH4"]);
+        assert_eq!(
+            changes.renamed,
+            vec![
+                (
+                    String::from("EH2"),
+                    Some(String::from("Driveway")),
+                    Some(String::from("Front"))
+                ),
+                (String::from("EH3"), None, Some(String::from("Carport"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn everything_gone_is_removed_in_id_order() {
+        let previous = roster(&[charger("EH3", None), charger("EH1", None)]);
+        let changes = diff(&previous, &[]);
+        assert_eq!(changes.removed, vec!["EH1", "EH3"]);
+    }
+}
@@ -0,0 +1,288 @@
+use std::{collections::HashMap, env, str::FromStr};
+
+use chrono::Duration;
+use rocket::figment::{providers::Serialized, Figment};
+use serde::{Deserialize, Serialize};
+use tracing::{instrument, warn};
+
+use super::{
+    aliases::{FieldAliases, LegacyAliases},
+    api_key::ApiKeyConfig,
+    base_path::PublicBasePath,
+    cors::Cors,
+    routes::{parse_field_ttls, Readiness},
+    run::get_interval,
+};
+
+/// The settings of the routes, read once at launch from Rocket's figment: `Rocket.toml`, then
+/// `ROCKET_` variables, falling back to the plain variables used before.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutesConfig {
+    /// Seconds a cache refreshing itself serves a snapshot, the interval when unset.
+    pub cache_ttl_secs: Option<i64>,
+    /// `field=seconds,...`, overriding the cache TTL for the field routes, see `FIELD_TTLS`.
+    pub field_ttls: Option<String>,
+    /// `alias=field,...`, other names for the fields of `/<field>/<index>`.
+    pub field_aliases: Option<String>,
+    /// `alias=/field/index,...`, see `LEGACY_ALIASES`.
+    pub legacy_aliases: Option<String>,
+    pub api_key: Option<String>,
+    /// Keys the hashes telling API keys apart in the audit log, random per start when unset.
+    pub api_key_salt: Option<String>,
+    /// Comma separated origins browsers may read the routes from, `*` for any.
+    pub cors_origins: Option<String>,
+    pub public_base_path: Option<String>,
+    /// How old data may be for `/readyz`, three intervals when unset.
+    pub readiness_max_data_age_secs: Option<i64>,
+    /// Decimals of the values in the JSON routes, 3 when unset.
+    pub json_precision: Option<i32>,
+}
+
+/// The states the routes are managed with, built from the config.
+#[derive(Debug, Clone)]
+pub struct RouteStates {
+    pub legacy_aliases: LegacyAliases,
+    pub field_aliases: FieldAliases,
+    pub api_key: ApiKeyConfig,
+    pub base_path: PublicBasePath,
+    pub cors: Cors,
+    pub readiness: Readiness,
+    /// The TTL of a cache refreshing itself, if set, and those of the fields.
+    pub cache_ttl: Option<Duration>,
+    pub field_ttls: HashMap<String, Duration>,
+    pub json_precision: i32,
+}
+
+fn parse_var<T: FromStr>(name: &str) -> Result<Option<T>, String> {
+    env::var(name)
+        .ok()
+        .map(|v| v.parse().map_err(|_| format!("Illegal {}: {}", name, v)))
+        .transpose()
+}
+
+impl RoutesConfig {
+    /// The values of the plain variables, such as `FIELD_TTLS` and `API_KEY`, the lowest
+    /// precedence. Fails on numbers that don't parse.
+    pub fn from_env() -> Result<Self, String> {
+        Ok(RoutesConfig {
+            cache_ttl_secs: parse_var("CACHE_TTL_SECS")?,
+            field_ttls: env::var("FIELD_TTLS").ok(),
+            field_aliases: env::var("FIELD_ALIASES").ok(),
+            legacy_aliases: env::var("LEGACY_ALIASES").ok(),
+            api_key: env::var("API_KEY").ok(),
+            api_key_salt: env::var("API_KEY_SALT").ok(),
+            cors_origins: env::var("CORS_ORIGINS").ok(),
+            public_base_path: env::var("PUBLIC_BASE_PATH").ok(),
+            readiness_max_data_age_secs: parse_var("READINESS_MAX_DATA_AGE_SECS")?,
+            json_precision: parse_var("JSON_PRECISION")?,
+        })
+    }
+
+    /// Extracts the config, the values set in `figment` taking precedence over `defaults`.
+    pub fn extract(figment: &Figment, defaults: RoutesConfig) -> Result<Self, String> {
+        figment
+            .clone()
+            .join(Serialized::defaults(defaults))
+            .extract()
+            .map_err(|e| e.to_string())
+    }
+
+    /// The states the routes are managed with. Fails on values that don't parse.
+    #[instrument(skip_all, level = "trace")]
+    pub fn states(&self) -> Result<RouteStates, String> {
+        let legacy_aliases = match self.legacy_aliases {
+            Some(ref aliases) => LegacyAliases::parse(aliases),
+            None => Ok(LegacyAliases::default()),
+        }
+        .map_err(|e| format!("Illegal legacy_aliases: {}", e))?;
+        tracing::info!("legacy_aliases: {:?}", legacy_aliases);
+
+        let field_aliases = FieldAliases::parse(self.field_aliases.as_deref().unwrap_or(""))
+            .map_err(|e| format!("Illegal field_aliases: {}", e))?;
+        tracing::info!("field_aliases: {:?}", field_aliases);
+
+        let key = self.api_key.clone().filter(|k| !k.is_empty());
+        tracing::info!("api_key: {}", if key.is_some() { "set" } else { "not set" });
+        let salt = match self.api_key_salt.clone().filter(|s| !s.is_empty()) {
+            Some(salt) => {
+                tracing::info!("api_key_salt: set");
+                salt.into_bytes()
+            }
+            None => {
+                if key.is_some() {
+                    warn!("api_key_salt not set, key ids in the audit log change on every start");
+                }
+                rand::random::<[u8; 32]>().to_vec()
+            }
+        };
+        let api_key = ApiKeyConfig::new(key, salt);
+
+        let base_path = PublicBasePath::new(self.public_base_path.clone());
+        tracing::info!("public_base_path: {:?}", base_path);
+
+        let cors = Cors::parse(self.cors_origins.as_deref().unwrap_or(""))
+            .map_err(|e| format!("Illegal cors_origins: {}", e))?;
+        tracing::info!("cors_origins: {:?}", cors);
+
+        let cache_ttl = match self.cache_ttl_secs {
+            Some(secs) if secs <= 0 => {
+                return Err(format!("Illegal cache_ttl_secs: {}", secs));
+            }
+            secs => secs.map(Duration::seconds),
+        };
+        tracing::info!("cache_ttl_secs: {:?}", self.cache_ttl_secs);
+        let field_ttls = parse_field_ttls(self.field_ttls.as_deref().unwrap_or(""))
+            .map_err(|e| format!("Illegal field_ttls: {}", e))?;
+        tracing::info!("field_ttls: {:?}", field_ttls);
+
+        let max_data_age = self
+            .readiness_max_data_age_secs
+            .map_or_else(|| get_interval() * 3, Duration::seconds);
+        tracing::info!(
+            "readiness_max_data_age_secs: {}",
+            max_data_age.num_seconds()
+        );
+
+        let json_precision = self.json_precision.unwrap_or(3);
+        if !(0..=15).contains(&json_precision) {
+            return Err(format!("Illegal json_precision: {}", json_precision));
+        }
+        tracing::info!("json_precision: {}", json_precision);
+
+        Ok(RouteStates {
+            legacy_aliases,
+            field_aliases,
+            api_key,
+            base_path,
+            cors,
+            readiness: Readiness { max_data_age },
+            cache_ttl,
+            field_ttls,
+            json_precision,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::providers::{Env, Format, Toml};
+
+    use super::*;
+
+    /// What `Rocket.toml` would hold.
+    const FILE: &str = r#"
+        [default]
+        cache_ttl_secs = 20
+        field_ttls = "power=5"
+        cors_origins = "https://file.example"
+    "#;
+
+    /// A figment layered as Rocket's: the file, then variables with a prefix. The prefix is
+    /// only used here, so setting the variables can't disturb the other tests.
+    fn figment(prefix: &str) -> Figment {
+        Figment::new()
+            .merge(Toml::string(FILE).nested())
+            .merge(Env::prefixed(prefix))
+    }
+
+    fn defaults() -> RoutesConfig {
+        RoutesConfig {
+            cache_ttl_secs: Some(10),
+            field_ttls: Some(String::from("power=1")),
+            cors_origins: Some(String::from("https://default.example")),
+            json_precision: Some(2),
+            ..RoutesConfig::default()
+        }
+    }
+
+    #[test]
+    fn the_file_overrides_the_defaults() {
+        let config = RoutesConfig::extract(&figment("ROUTES_CONFIG_NONE_"), defaults()).unwrap();
+        assert_eq!(config.cache_ttl_secs, Some(20));
+        assert_eq!(config.field_ttls.as_deref(), Some("power=5"));
+        assert_eq!(config.cors_origins.as_deref(), Some("https://file.example"));
+        // Not in the file
+        assert_eq!(config.json_precision, Some(2));
+    }
+
+    #[test]
+    fn variables_override_the_file() {
+        env::set_var("ROUTES_CONFIG_ENV_CACHE_TTL_SECS", "30");
+        env::set_var("ROUTES_CONFIG_ENV_CORS_ORIGINS", "*");
+        let config = RoutesConfig::extract(&figment("ROUTES_CONFIG_ENV_"), defaults()).unwrap();
+        assert_eq!(config.cache_ttl_secs, Some(30));
+        assert_eq!(config.cors_origins.as_deref(), Some("*"));
+        assert_eq!(config.field_ttls.as_deref(), Some("power=5"));
+        assert_eq!(config.json_precision, Some(2));
+    }
+
+    #[test]
+    fn wrong_types_fail_the_extraction() {
+        let figment = Figment::new().merge(Toml::string("cache_ttl_secs = \"soon\""));
+        let error = RoutesConfig::extract(&figment, RoutesConfig::default()).unwrap_err();
+        assert!(error.contains("cache_ttl_secs"), "{}", error);
+    }
+
+    #[test]
+    fn states_carry_the_values() {
+        let config = RoutesConfig {
+            cache_ttl_secs: Some(20),
+            field_ttls: Some(String::from("power=5,session=300")),
+            field_aliases: Some(String::from("watts=power")),
+            cors_origins: Some(String::from("https://grafana.example")),
+            readiness_max_data_age_secs: Some(90),
+            json_precision: Some(1),
+            ..RoutesConfig::default()
+        };
+        let states = config.states().unwrap();
+        assert_eq!(states.cache_ttl, Some(Duration::seconds(20)));
+        assert_eq!(states.field_ttls["session"], Duration::seconds(300));
+        assert_eq!(states.field_aliases.get("watts").unwrap().as_str(), "power");
+        assert!(states.cors.allows("https://grafana.example"));
+        assert_eq!(states.readiness.max_data_age, Duration::seconds(90));
+        assert_eq!(states.json_precision, 1);
+
+        let defaults = RoutesConfig::default().states().unwrap();
+        assert_eq!(defaults.cache_ttl, None);
+        assert!(defaults.field_ttls.is_empty());
+        assert_eq!(defaults.json_precision, 3);
+    }
+
+    #[test]
+    fn bad_values_fail_with_their_name() {
+        let cases = [
+            RoutesConfig {
+                field_ttls: Some(String::from("power")),
+                ..RoutesConfig::default()
+            },
+            RoutesConfig {
+                field_aliases: Some(String::from("watts=voltage")),
+                ..RoutesConfig::default()
+            },
+            RoutesConfig {
+                cors_origins: Some(String::from("grafana.example")),
+                ..RoutesConfig::default()
+            },
+            RoutesConfig {
+                cache_ttl_secs: Some(0),
+                ..RoutesConfig::default()
+            },
+            RoutesConfig {
+                json_precision: Some(-1),
+                ..RoutesConfig::default()
+            },
+        ];
+        let names = [
+            "field_ttls",
+            "field_aliases",
+            "cors_origins",
+            "cache_ttl_secs",
+            "json_precision",
+        ];
+        for (config, name) in cases.into_iter().zip(names) {
+            let error = config.states().unwrap_err();
+            assert!(error.contains(name), "{}", error);
+        }
+    }
+}
@@ -1,110 +1,1275 @@
-use std::{env, sync::Arc};
-
-use chrono::Utc;
-use influxdb::{Client, InfluxDbWriteable};
-use tokio::sync::Mutex;
-use tracing::{instrument, metadata::LevelFilter, Level};
-use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
-use tracing_subscriber::{
-    fmt::format::{DefaultFields, FmtSpan, Format},
-    FmtSubscriber,
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, OnceLock},
+    time::Duration,
 };
 
-use crate::v1::{easee::get_charger_state, structs::Variable};
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use influxdb::{Client, InfluxDbWriteable, Query, WriteQuery};
+use tokio::{sync::Mutex, time::timeout};
+use tracing::instrument;
 
-use super::structs::SessionState;
+use crate::v1::{
+    easee::{get_charger_configs, get_charger_states, spread_window},
+    structs::{ChargerConfig, ChargerState, IntegerVariable, Variable},
+};
+
+#[cfg(feature = "spotprice")]
+use super::price::PriceProvider;
+use super::{
+    adaptive::AdaptiveInterval,
+    annotations::AnnotationTracker,
+    anonymize::log_id,
+    derive::EnergyDeriver,
+    gap::{GapDetector, GAP_MEASUREMENT},
+    health::LastErrors,
+    integrate::EnergyIntegrator,
+    lifetime::{total_query, LifetimeTotal},
+    memory,
+    names::display_name,
+    overrides::charger_config,
+    report::ReportTimezone,
+    retry::RetryBuffer,
+    rollup::HourlyRollup,
+    routes::Cache,
+    sessions::{sessions_since, SessionCounter},
+    sink::{InfluxSink, Sink},
+    source::tag_lines,
+    stale::StaleDetector,
+    structs::{EaseeError, Mode, SessionState},
+};
+
+#[instrument]
+pub fn get_mode() -> Mode {
+    let mode = match env::var("MODE") {
+        Ok(m) => match m.as_str() {
+            "poller" => Mode::Poller,
+            "server" => Mode::Server,
+            "both" => Mode::Both,
+            _ => panic!("Illegal mode: {}", m),
+        },
+        Err(_) => Mode::Poller,
+    };
+    tracing::info!("MODE: {:?}", mode);
+    mode
+}
+
+pub fn get_interval() -> chrono::Duration {
+    chrono::Duration::minutes(
+        env::var("INTERVAL").map_or(1, |i| i.parse().expect("Illegal interval format")),
+    )
+}
+
+/// Interval of the slow tier, polling settings that rarely change. Defaults to an hour.
+#[instrument]
+pub fn get_slow_interval() -> chrono::Duration {
+    let interval = chrono::Duration::minutes(
+        env::var("SLOW_INTERVAL").map_or(60, |i| i.parse().expect("Illegal slow interval format")),
+    );
+    tracing::info!("SLOW_INTERVAL: {}", interval.num_minutes());
+    interval
+}
+
+/// Time each phase of a tick may take before it is abandoned. Defaults to the interval.
+#[instrument]
+pub fn get_tick_deadline() -> Duration {
+    let deadline = match env::var("TICK_DEADLINE_SECS") {
+        Ok(d) => Duration::from_secs(d.parse().expect("Illegal tick deadline format")),
+        Err(_) => get_interval().to_std().unwrap(),
+    };
+    tracing::info!("TICK_DEADLINE_SECS: {}", deadline.as_secs());
+    deadline
+}
+
+/// A database every point is written to, an InfluxDB unless built with another sink.
+///
+/// The client is built once, at startup, and shared by every tick. It is only used for
+/// reads, every write goes to the sink.
+#[derive(Clone)]
+pub struct DbTarget {
+    pub addr: String,
+    pub name: String,
+    auth: Option<(String, String)>,
+    client: Client,
+    sink: Arc<dyn Sink>,
+}
+
+impl std::fmt::Debug for DbTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DbTarget")
+            .field("addr", &self.addr)
+            .field("name", &self.name)
+            .field("auth", &self.auth.is_some())
+            .finish()
+    }
+}
+
+impl DbTarget {
+    pub fn new(addr: String, name: String, auth: Option<(String, String)>) -> Self {
+        let client = Client::new(addr.as_str(), name.as_str());
+        let client = match auth {
+            Some((ref username, ref password)) => client.with_auth(username, password),
+            None => client,
+        };
+        let sink = Arc::new(InfluxSink::new(addr.clone(), name.clone(), auth.clone()));
+        DbTarget {
+            addr,
+            name,
+            auth,
+            client,
+            sink,
+        }
+    }
+
+    /// A target writing to `sink` rather than the InfluxDB at `addr`, which only reads go to.
+    pub fn with_sink(addr: String, name: String, sink: Arc<dyn Sink>) -> Self {
+        DbTarget {
+            sink,
+            ..DbTarget::new(addr, name, None)
+        }
+    }
+
+    pub fn auth_mode(&self) -> &'static str {
+        match self.auth {
+            Some(_) => "username and password",
+            None => "none",
+        }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Writes points already rendered as line protocol in one request.
+    pub async fn write_lines(&self, lines: &str) -> Result<(), String> {
+        self.sink.write_lines(&tag_lines(lines)).await
+    }
+
+    /// Renders the points and writes them in one request.
+    pub async fn write<Q: Query>(&self, query: Q) -> Result<(), String> {
+        let lines = query.build().map_err(|e| e.to_string())?;
+        self.write_lines(&lines.get()).await
+    }
+}
 
+/// Reads the targets from `INFLUXDB_ADDR`, a comma separated list, and `INFLUXDB_DB_NAME`.
+///
+/// Target `n`, counting from 0, authenticates with `INFLUXDB_USERNAME_n` and
+/// `INFLUXDB_PASSWORD_n` when both are set.
+///
+/// `required_by` names what needs the database, such as the mode, for the panic message.
 #[instrument]
-pub fn get_db_info() -> (Arc<String>, Arc<String>) {
-    let db_addr = env::var("INFLUXDB_ADDR").expect("INFLUXDB_ADDR not set");
+pub fn get_db_info(required_by: &str) -> Arc<Vec<DbTarget>> {
+    let db_addr = env::var("INFLUXDB_ADDR")
+        .unwrap_or_else(|_| panic!("INFLUXDB_ADDR not set, {} requires it", required_by));
     tracing::info!("INFLUXDB_ADDR: {}", db_addr);
 
-    let db_name = env::var("INFLUXDB_DB_NAME").expect("INFLUXDB_DB_NAME not set");
+    let db_name = env::var("INFLUXDB_DB_NAME")
+        .unwrap_or_else(|_| panic!("INFLUXDB_DB_NAME not set, {} requires it", required_by));
     tracing::info!("INFLUXDB_DB_NAME: {}", db_name);
 
-    (Arc::new(db_addr), Arc::new(db_name))
+    let targets = parse_db_targets(&db_addr, &db_name)
+        .unwrap_or_else(|e| panic!("Illegal INFLUXDB_ADDR: {}", e));
+    if targets.is_empty() {
+        panic!("INFLUXDB_ADDR holds no address");
+    }
+    for (i, target) in targets.iter().enumerate() {
+        tracing::info!("InfluxDB target {}: {:?}", i, target);
+    }
+    Arc::new(targets)
 }
 
-pub fn get_logger() -> (
-    FmtSubscriber<DefaultFields, Format, LevelFilter, NonBlocking>,
-    WorkerGuard,
-) {
-    let appender = tracing_appender::rolling::daily("./var/log", "easee-status-server");
-    let (non_blocking_appender, guard) = tracing_appender::non_blocking(appender);
-
-    let level = match env::var("LOG_LEVEL") {
-        Ok(l) => match l.as_str() {
-            "trace" => Level::TRACE,
-            "debug" => Level::DEBUG,
-            "info" => Level::INFO,
-            "warn" => Level::WARN,
-            "error" => Level::ERROR,
-            _ => Level::INFO,
-        },
-        Err(_) => Level::INFO,
+/// Like `get_db_info`, but `None` rather than a panic when the variables are missing.
+pub fn try_get_db_info() -> Option<Vec<DbTarget>> {
+    let db_addr = env::var("INFLUXDB_ADDR").ok()?;
+    let db_name = env::var("INFLUXDB_DB_NAME").ok()?;
+    match parse_db_targets(&db_addr, &db_name) {
+        Ok(targets) => Some(targets).filter(|t| !t.is_empty()),
+        Err(e) => {
+            tracing::warn!("Illegal INFLUXDB_ADDR: {}", e);
+            None
+        }
+    }
+}
+
+fn parse_db_targets(addrs: &str, name: &str) -> Result<Vec<DbTarget>, String> {
+    addrs
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .enumerate()
+        .map(|(i, addr)| {
+            let normalized = normalize_addr(addr)?;
+            if !addr.contains("://") {
+                tracing::info!("INFLUXDB_ADDR {} has no scheme, using {}", addr, normalized);
+            }
+            let auth = match (
+                env::var(format!("INFLUXDB_USERNAME_{}", i)),
+                env::var(format!("INFLUXDB_PASSWORD_{}", i)),
+            ) {
+                (Ok(username), Ok(password)) => Some((username, password)),
+                _ => None,
+            };
+            Ok(DbTarget::new(normalized, name.to_string(), auth))
+        })
+        .collect()
+}
+
+/// Turns an InfluxDB address into `scheme://host[:port][/path]`. A bare `host:port` gets
+/// `http://`, trailing slashes are dropped, and anything but an http or https URL with a host
+/// is refused, saying how it was read.
+pub fn normalize_addr(addr: &str) -> Result<String, String> {
+    let addr = addr.trim();
+    let with_scheme = if addr.contains("://") {
+        addr.to_string()
+    } else {
+        format!("http://{}", addr)
     };
+    let url = reqwest::Url::parse(&with_scheme)
+        .map_err(|e| format!("{} read as {}: {}", addr, with_scheme, e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!(
+            "{} has scheme {}, only http and https are supported",
+            addr,
+            url.scheme()
+        ));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(format!("{} read as {} has no host", addr, url));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(format!(
+            "{} read as {} has a query or fragment, give the database in INFLUXDB_DB_NAME",
+            addr, url
+        ));
+    }
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
 
-    let subscriber = FmtSubscriber::builder()
-        // all spans/events with a level higher than TRACE (e.g, debug, info, warn, etc.)
-        // will be written to stdout.
-        .with_span_events(FmtSpan::NONE)
-        .with_ansi(false)
-        .with_max_level(level)
-        .with_writer(non_blocking_appender)
-        // completes the builder.
-        .finish();
+/// State the poller keeps between ticks.
+#[derive(Debug)]
+pub struct PollerState {
+    pub stale: Mutex<StaleDetector>,
+    pub gaps: Mutex<GapDetector>,
+    pub interval: Mutex<AdaptiveInterval>,
+    pub sessions: Mutex<SessionCounter>,
+    pub derived: Mutex<EnergyDeriver>,
+    pub integrated: Mutex<EnergyIntegrator>,
+    pub annotations: Mutex<AnnotationTracker>,
+    pub rollups: Mutex<HourlyRollup>,
+    pub lifetime: Mutex<LifetimeTotal>,
+    pub retry: RetryBuffer,
+    pub errors: Arc<LastErrors>,
+    #[cfg(feature = "spotprice")]
+    pub prices: Option<PriceProvider>,
+}
+
+impl PollerState {
+    pub fn from_env(errors: Arc<LastErrors>) -> Self {
+        PollerState::with_interval(errors, get_interval())
+    }
+
+    /// Like `from_env`, polling at `interval` rather than `INTERVAL`.
+    pub fn with_interval(errors: Arc<LastErrors>, interval: chrono::Duration) -> Self {
+        PollerState {
+            stale: Mutex::new(StaleDetector::from_env()),
+            gaps: Mutex::new(GapDetector::from_env(interval)),
+            retry: RetryBuffer::from_env(),
+            sessions: Mutex::new(SessionCounter::new(
+                ReportTimezone::from_env().unwrap_or_else(|e| panic!("{}", e)),
+            )),
+            derived: Mutex::new(EnergyDeriver::from_env()),
+            integrated: Mutex::new(EnergyIntegrator::for_interval(interval)),
+            annotations: Mutex::new(AnnotationTracker::from_env()),
+            rollups: Mutex::new(HourlyRollup::from_env()),
+            lifetime: Mutex::new(LifetimeTotal::new()),
+            interval: Mutex::new(AdaptiveInterval::from_env(interval.to_std().unwrap())),
+            errors,
+            #[cfg(feature = "spotprice")]
+            prices: PriceProvider::from_env(),
+        }
+    }
+}
 
-    (subscriber, guard)
+/// What a tick did, logged as one event at its end for log based alerting.
+#[derive(Debug, Default)]
+pub struct TickSummary {
+    pub chargers_total: usize,
+    pub chargers_ok: usize,
+    pub chargers_failed: usize,
+    /// Chargers whose energy per hour had to be derived locally.
+    pub chargers_derived: usize,
+    pub points_written: usize,
+    /// Points kept for a later retry, including those of earlier ticks.
+    pub points_buffered: usize,
+    pub outcome: &'static str,
+}
+
+impl TickSummary {
+    fn failed(outcome: &'static str) -> Self {
+        TickSummary {
+            outcome,
+            ..Default::default()
+        }
+    }
+
+    fn emit(&self, duration: Duration) {
+        tracing::info!(
+            chargers_total = self.chargers_total,
+            chargers_ok = self.chargers_ok,
+            chargers_failed = self.chargers_failed,
+            chargers_derived = self.chargers_derived,
+            points_written = self.points_written,
+            points_buffered = self.points_buffered,
+            duration_ms = duration.as_millis() as u64,
+            outcome = self.outcome,
+            "Tick finished"
+        );
+    }
+
+    /// The point telling the poller is alive, whatever the tick achieved.
+    fn heartbeat(&self, time: DateTime<Utc>) -> WriteQuery {
+        Heartbeat {
+            time,
+            value: 1,
+            outcome: self.outcome.to_string(),
+            chargers: self.chargers_total as i64,
+        }
+        .into_query(HEARTBEAT_MEASUREMENT)
+    }
+}
+
+/// Measurement the heartbeat is written to at the end of every tick.
+pub const HEARTBEAT_MEASUREMENT: &str = "poller_heartbeat";
+
+#[derive(Debug, Clone, InfluxDbWriteable)]
+struct Heartbeat {
+    time: DateTime<Utc>,
+    value: i64,
+    outcome: String,
+    chargers: i64,
+}
+
+/// What is written for a charger besides its state.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ChargerExtras {
+    pub(crate) suspect: bool,
+    /// The cost of the energy drawn since the last tick, with that energy in kWh.
+    pub(crate) cost: Option<(f64, f64)>,
+    pub(crate) sessions_today: Option<u32>,
+    pub(crate) energy_per_hour_derived: Option<f64>,
+    pub(crate) energy_since_start: Option<f64>,
+}
+
+/// How the write of one charger to one target ended.
+enum WriteOutcome {
+    Done(ChargerWrite),
+    /// The task panicked, with the lines it was to write.
+    Failed {
+        addr: String,
+        lines: Vec<String>,
+        error: String,
+    },
+    /// The write deadline passed first, with the lines it was to write.
+    TimedOut {
+        addr: String,
+        lines: Vec<String>,
+    },
+}
+
+/// Points written for one charger to one target.
+struct ChargerWrite {
+    addr: String,
+    charger_id: String,
+    written: usize,
+    failures: Vec<String>,
+    /// The points that failed, as line protocol.
+    failed_lines: Vec<String>,
 }
 
 #[instrument(skip_all, level = "trace")]
 pub async fn tick(
     login_state: Arc<Mutex<SessionState>>,
-    db_addr: Arc<String>,
-    db_name: Arc<String>,
+    targets: Arc<Vec<DbTarget>>,
+    cache: Option<Arc<Cache>>,
+    poller: Arc<PollerState>,
+    deadline: Duration,
 ) {
+    let started = std::time::Instant::now();
+    let summary = run_tick(login_state, targets.clone(), cache, poller, deadline).await;
+    summary.emit(started.elapsed());
+    // Without heartbeats the poller or the database is down, rather than Easee
+    let time = Utc::now();
+    let heartbeats = join_all(
+        targets
+            .iter()
+            .map(|target| write_to_db(target, "heartbeat", summary.heartbeat(time))),
+    );
+    if timeout(deadline, heartbeats).await.is_err() {
+        tracing::error!("Writing the heartbeat timed out after {:?}", deadline);
+    }
+}
+
+async fn run_tick(
+    login_state: Arc<Mutex<SessionState>>,
+    targets: Arc<Vec<DbTarget>>,
+    cache: Option<Arc<Cache>>,
+    poller: Arc<PollerState>,
+    deadline: Duration,
+) -> TickSummary {
     tracing::debug!("tick");
-    let charger_state = get_charger_state(login_state).await;
+    let errors = &poller.errors;
+    errors.tick().await;
+    let gap = poller.gaps.lock().await.observe(Utc::now());
+    if let Some(gap) = gap {
+        let query = gap.into_query(GAP_MEASUREMENT);
+        write_buffered(&targets, &poller, "gap", vec![query]).await;
+    }
+    // Every point of the tick shares its time, including those of chargers that failed
+    let time = Utc::now();
+    let charger_state = match timeout(deadline, get_charger_states(login_state.clone())).await {
+        Ok(charger_state) => charger_state,
+        Err(_) => {
+            tracing::error!("Fetching charger state timed out after {:?}", deadline);
+            flush_retries(&targets, &poller, deadline).await;
+            return TickSummary::failed("fetch_timeout");
+        }
+    };
+    for id in memory::evict() {
+        poller.stale.lock().await.forget(&id);
+        poller.sessions.lock().await.forget(&id);
+        poller.derived.lock().await.forget(&id);
+        poller.integrated.lock().await.forget(&id);
+        poller.annotations.lock().await.forget(&id);
+        poller.rollups.lock().await.forget(&id);
+        poller.lifetime.lock().await.forget(&id);
+    }
+    flush_retries(&targets, &poller, deadline).await;
     match charger_state {
-        Ok(state) => {
+        Ok(fetched) => {
+            let mut state = Vec::new();
+            let mut unreachable = Vec::new();
+            let mut fetch_error = None;
+            let mut reachable = Vec::new();
+            let mut order = Vec::new();
+            for (charger, result) in fetched {
+                order.push(charger.id.clone());
+                reachable.push((charger.id.clone(), result.is_ok()));
+                match result {
+                    Ok(charger_state) => state.push(charger_state),
+                    // Not fetched, which isn't a failure of this tick
+                    Err(EaseeError::NoAccess) => (),
+                    Err(e) => {
+                        unreachable.push(charger);
+                        fetch_error.get_or_insert(e);
+                    }
+                }
+            }
+            let sessions_today =
+                count_sessions(&poller, &login_state, &state, time, deadline).await;
+            if let Some(ref cache) = cache {
+                cache.publish_reachable(reachable).await;
+                cache
+                    .publish_sessions_today(poller.sessions.lock().await.counts())
+                    .await;
+            }
+            for charger in &unreachable {
+                let config = charger_config(&charger.id);
+                if config.skip_offline || !config.collect {
+                    continue;
+                }
+                let name = display_name(&charger.id, charger.name.as_deref());
+                for target in targets.iter() {
+                    let query = reachable_query(&charger.id, &name, time, false);
+                    let line = query.build().map(|q| q.get());
+                    if write_to_db(target, "reachable", query).await.is_err() {
+                        poller
+                            .retry
+                            .push(&target.addr, line.into_iter().collect())
+                            .await;
+                    }
+                }
+            }
+            let annotations = poller
+                .annotations
+                .lock()
+                .await
+                .observe(&state, &unreachable, time);
+            write_buffered(&targets, &poller, "annotations", annotations).await;
+            let collected: Vec<ChargerState> = state
+                .iter()
+                .filter(|charger| charger_config(&charger.id).collect)
+                .cloned()
+                .collect();
+            let rollups = poller.rollups.lock().await.observe(&collected, time);
+            write_buffered(&targets, &poller, "rollups", rollups).await;
+            let lifetime_total = poller.lifetime.lock().await.observe(&state);
+            if let Some(kwh) = lifetime_total {
+                let query = total_query(round_value(kwh), time);
+                write_buffered(&targets, &poller, "total_lifetime_energy", vec![query]).await;
+                if let Some(ref cache) = cache {
+                    cache.publish_total_lifetime_energy(kwh).await;
+                }
+            }
+            let chargers_total = state.len() + unreachable.len();
+            match fetch_error {
+                Some(e) => {
+                    tracing::error!("{} chargers failed: {}", unreachable.len(), e);
+                    errors.easee_failed(&e).await;
+                }
+                None => errors.easee_succeeded().await,
+            }
+            // The chargers that failed are left out, the others keep their index
+            if let Some(ref cache) = cache {
+                cache.publish(order, state.clone()).await;
+            }
             tracing::info!("Writing {} states", state.len());
-            let client = Client::new(db_addr.as_str(), db_name.as_str());
-            for charger in state {
-                tracing::trace!("Writing power");
-                write_to_db(&client, "power", charger.power, &charger.id).await;
-                tracing::trace!("Writing enrgy_per_hour");
-                write_to_db(
-                    &client,
-                    "energy_per_hour",
-                    charger.energy_per_hour,
-                    &charger.id,
+            poller.interval.lock().await.observe(&state);
+            let suspects: Vec<bool> = {
+                let mut stale = poller.stale.lock().await;
+                state.iter().map(|charger| stale.observe(charger)).collect()
+            };
+            let derived: Vec<Option<f64>> = {
+                let mut deriver = poller.derived.lock().await;
+                state
+                    .iter()
+                    .map(|charger| deriver.observe(charger, time))
+                    .collect()
+            };
+            let chargers_derived = derived.iter().filter(|d| d.is_some()).count();
+            // The energy drawn since the last tick as well, which the cost is priced from
+            let (integrated, drawn): (Vec<f64>, Vec<f64>) = {
+                let mut integrator = poller.integrated.lock().await;
+                let integrated = state
+                    .iter()
+                    .map(|charger| {
+                        let before = integrator.total(&charger.id);
+                        let total = integrator.observe(charger, time);
+                        (total, total - before)
+                    })
+                    .unzip();
+                if let Some(ref cache) = cache {
+                    cache.publish_energy_since_start(integrator.totals()).await;
+                }
+                integrated
+            };
+            let costs = charger_costs(&poller, &drawn).await;
+            let extras: Vec<ChargerExtras> = suspects
+                .into_iter()
+                .zip(costs)
+                .zip(sessions_today)
+                .zip(derived)
+                .zip(integrated)
+                .map(
+                    |(
+                        (((suspect, cost), sessions_today), energy_per_hour_derived),
+                        since_start,
+                    )| {
+                        ChargerExtras {
+                            suspect,
+                            cost,
+                            sessions_today,
+                            energy_per_hour_derived,
+                            energy_since_start: Some(since_start),
+                        }
+                    },
                 )
-                .await;
-                tracing::trace!("Writing session");
-                write_to_db(&client, "session", charger.session, &charger.id).await;
+                .collect();
+            // Each charger is written to each target from its own task, so a panic or a
+            // target being down can't hold back the other writes
+            // Spread requests are up to the window apart, so each charger keeps its fetch time
+            let fetched_at: HashMap<String, DateTime<Utc>> = if spread_window().is_zero() {
+                HashMap::new()
+            } else {
+                memory::last_success()
+                    .into_iter()
+                    .filter_map(|(id, at)| at.map(|at| (id, at)))
+                    .collect()
+            };
+            let until = tokio::time::Instant::now() + deadline;
+            let writes: Vec<_> = state
+                .into_iter()
+                .zip(extras)
+                .filter(|(charger, _)| charger_config(&charger.id).collect)
+                .flat_map(|(charger, extras)| {
+                    let time = fetched_at.get(&charger.id).copied().unwrap_or(time);
+                    let queries = charger_write_queries(&charger, time, extras);
+                    targets.iter().map(move |target| {
+                        let lines = render_lines(&queries);
+                        let addr = target.addr.clone();
+                        let task = tokio::spawn(write_charger(
+                            target.clone(),
+                            charger.id.clone(),
+                            queries.clone(),
+                        ));
+                        let abort = task.abort_handle();
+                        async move {
+                            match tokio::time::timeout_at(until, task).await {
+                                Ok(Ok(write)) => WriteOutcome::Done(write),
+                                Ok(Err(e)) => WriteOutcome::Failed {
+                                    addr,
+                                    lines,
+                                    error: e.to_string(),
+                                },
+                                Err(_) => {
+                                    abort.abort();
+                                    WriteOutcome::TimedOut { addr, lines }
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect();
+            let mut failures = Vec::new();
+            let mut failed_chargers = std::collections::HashSet::new();
+            let mut points_written = 0;
+            let mut timed_out = false;
+            for outcome in join_all(writes).await {
+                match outcome {
+                    WriteOutcome::Done(write) => {
+                        points_written += write.written;
+                        if !write.failures.is_empty() {
+                            failed_chargers.insert(write.charger_id);
+                        }
+                        failures.extend(write.failures);
+                        poller.retry.push(&write.addr, write.failed_lines).await;
+                    }
+                    // Whether any of it was written is unknown, so all of it is retried
+                    WriteOutcome::Failed { addr, lines, error } => {
+                        tracing::error!("Write task failed: {}", error);
+                        failures.push(format!("Write task failed: {}", error));
+                        poller.retry.push(&addr, lines).await;
+                    }
+                    WriteOutcome::TimedOut { addr, lines } => {
+                        timed_out = true;
+                        poller.retry.push(&addr, lines).await;
+                    }
+                }
+            }
+            if timed_out {
+                tracing::error!("Writing charger states timed out after {:?}", deadline);
+                return TickSummary {
+                    chargers_total,
+                    chargers_failed: chargers_total,
+                    points_buffered: poller.retry.len().await,
+                    ..TickSummary::failed("write_timeout")
+                };
+            }
+            match failures.last() {
+                Some(failure) => {
+                    tracing::warn!("{} writes failed", failures.len());
+                    errors.influxdb_failed(failure).await;
+                }
+                None => errors.influxdb_succeeded().await,
+            }
+            // A panicked write task can't tell which charger it was for
+            let chargers_failed = if !failures.is_empty() && failed_chargers.is_empty() {
+                chargers_total
+            } else {
+                failed_chargers.len() + unreachable.len()
+            };
+            TickSummary {
+                chargers_total,
+                chargers_ok: chargers_total - chargers_failed,
+                chargers_failed,
+                chargers_derived,
+                points_written,
+                points_buffered: poller.retry.len().await,
+                outcome: if failures.is_empty() && unreachable.is_empty() {
+                    "ok"
+                } else {
+                    "partial"
+                },
             }
         }
         Err(e) => {
-            tracing::error!("error getting charger state: {}", e);
+            match e {
+                // Already warned about, throttled, by the client
+                EaseeError::Maintenance => tracing::debug!("error getting charger state: {}", e),
+                _ => tracing::error!("error getting charger state: {}", e),
+            }
+            errors.easee_failed(&e).await;
+            TickSummary::failed("easee_failed")
         }
     }
 }
 
-#[instrument(skip(client), level = "trace")]
-async fn write_to_db(client: &Client, name: &str, value: f64, measurement: &str) {
-    let variable = Variable {
-        time: Utc::now(),
-        value,
-        variable: String::from(name),
+/// Writes the points left over from earlier ticks. Called before the points of the tick
+/// are written, so they keep their order.
+async fn flush_retries(targets: &[DbTarget], poller: &PollerState, deadline: Duration) {
+    let flushes = join_all(targets.iter().map(|target| poller.retry.flush(target)));
+    if let Ok(results) = timeout(deadline, flushes).await {
+        for e in results.into_iter().filter_map(Result::err) {
+            tracing::debug!("Buffered points not written: {}", e);
+        }
+    }
+}
+
+/// Writes the points to every target, buffering them for a retry where that fails.
+async fn write_buffered(
+    targets: &[DbTarget],
+    poller: &PollerState,
+    name: &str,
+    queries: Vec<WriteQuery>,
+) {
+    if queries.is_empty() {
+        return;
+    }
+    let lines: Vec<String> = queries
+        .iter()
+        .filter_map(|query| query.build().ok().map(|q| q.get()))
+        .collect();
+    for target in targets {
+        if write_to_db(target, name, queries.clone()).await.is_err() {
+            poller.retry.push(&target.addr, lines.clone()).await;
+        }
+    }
+}
+
+/// Polls the charger configs and writes them to `<charger id>_config`.
+///
+/// Failures are only logged, so they don't show up as errors of the state polling.
+#[instrument(skip_all, level = "trace")]
+pub async fn slow_tick(
+    login_state: Arc<Mutex<SessionState>>,
+    targets: Arc<Vec<DbTarget>>,
+    deadline: Duration,
+) {
+    tracing::debug!("slow tick");
+    let configs = match timeout(deadline, get_charger_configs(login_state)).await {
+        Ok(Ok(configs)) => configs,
+        Ok(Err(EaseeError::Maintenance)) => return,
+        Ok(Err(e)) => {
+            tracing::error!("error getting charger configs: {}", e);
+            return;
+        }
+        Err(_) => {
+            tracing::error!("Fetching charger configs timed out after {:?}", deadline);
+            return;
+        }
+    };
+
+    let mut failed = 0;
+    let configs: Vec<ChargerConfig> = configs
+        .into_iter()
+        .filter_map(|(id, config)| match config {
+            Ok(config) => Some(config),
+            Err(EaseeError::Maintenance) => None,
+            Err(e) => {
+                tracing::warn!("error getting the config of {}: {}", log_id(&id), e);
+                failed += 1;
+                None
+            }
+        })
+        .collect();
+    if failed > 0 {
+        tracing::error!("{} charger configs failed", failed);
+    }
+
+    tracing::info!("Writing {} configs", configs.len());
+    let time = Utc::now();
+    let writes = join_all(targets.iter().map(|target| {
+        let queries = configs
+            .iter()
+            .flat_map(|config| config_queries(config, time));
+        async move {
+            for (name, query) in queries {
+                let _ = write_to_db(target, name, query).await;
+            }
+        }
+    }));
+    if timeout(deadline, writes).await.is_err() {
+        tracing::error!("Writing charger configs timed out after {:?}", deadline);
+    }
+}
+
+fn config_queries(config: &ChargerConfig, time: DateTime<Utc>) -> Vec<(&'static str, WriteQuery)> {
+    let measurement = format!("{}_config", config.id);
+    let integer = |name: &str, value: i64| {
+        IntegerVariable {
+            time,
+            value,
+            variable: String::from(name),
+        }
+        .into_query(&measurement)
+    };
+
+    let mut queries = Vec::new();
+    if let Some(enabled) = config.is_enabled {
+        queries.push(("is_enabled", integer("is_enabled", enabled as i64)));
+    }
+    if let Some(current) = config.max_charger_current {
+        queries.push((
+            "max_charger_current",
+            Variable {
+                time,
+                value: current,
+                variable: String::from("max_charger_current"),
+            }
+            .into_query(&measurement),
+        ));
+    }
+    if let Some(mode) = config.phase_mode {
+        queries.push(("phase_mode", integer("phase_mode", mode)));
+    }
+    if let Some(smart) = config.smart_charging {
+        queries.push(("smart_charging", integer("smart_charging", smart as i64)));
+    }
+    queries
+}
+
+/// Counts the sessions started today, returning the new count of the chargers that started one.
+///
+/// Chargers seen for the first time start from their session history, or from zero if
+/// it can't be read.
+async fn count_sessions(
+    poller: &PollerState,
+    login_state: &Arc<Mutex<SessionState>>,
+    state: &[ChargerState],
+    time: DateTime<Utc>,
+    deadline: Duration,
+) -> Vec<Option<u32>> {
+    // The history is fetched without holding the counter, the next tick may need it meanwhile
+    let (unknown, day_start) = {
+        let sessions = poller.sessions.lock().await;
+        let unknown: Vec<&ChargerState> = state
+            .iter()
+            .filter(|charger| !sessions.knows(&charger.id))
+            .collect();
+        (unknown, sessions.day_start(time))
+    };
+    let mut counts = Vec::new();
+    let fetch = async {
+        for charger in &unknown {
+            counts.push(sessions_since(&charger.id, day_start, time, login_state.clone()).await);
+        }
+    };
+    if timeout(deadline, fetch).await.is_err() {
+        // Those not fetched count today's sessions from zero, as when there is no history
+        tracing::warn!(
+            "Fetching the session history timed out after {:?}",
+            deadline
+        );
+    }
+
+    let mut sessions = poller.sessions.lock().await;
+    for (charger, count) in unknown.into_iter().zip(counts) {
+        if sessions.knows(&charger.id) {
+            continue;
+        }
+        let count = match count {
+            Some(count) => count,
+            None => {
+                tracing::info!(
+                    "No session history for {}, counting today's sessions from zero",
+                    log_id(&charger.id)
+                );
+                0
+            }
+        };
+        sessions.seed(charger, count, time);
+    }
+    state
+        .iter()
+        .map(|charger| sessions.observe(charger, time))
+        .collect()
+}
+
+/// What the energy each charger drew since the last tick cost. The energy is integrated
+/// over the tick times, so it holds however far apart the ticks were.
+#[cfg(feature = "spotprice")]
+async fn charger_costs(poller: &PollerState, drawn: &[f64]) -> Vec<Option<(f64, f64)>> {
+    let price = match poller.prices {
+        Some(ref prices) => prices.price_at(Utc::now()).await,
+        None => None,
     };
+    drawn
+        .iter()
+        .map(|&kwh| price.map(|p| (kwh * p, kwh)))
+        .collect()
+}
+
+#[cfg(not(feature = "spotprice"))]
+async fn charger_costs(_poller: &PollerState, drawn: &[f64]) -> Vec<Option<(f64, f64)>> {
+    vec![None; drawn.len()]
+}
+
+/// Decimal places values are rounded to before they are written, from `VALUE_PRECISION`.
+fn value_precision() -> Option<i32> {
+    static PRECISION: OnceLock<Option<i32>> = OnceLock::new();
+    *PRECISION.get_or_init(|| {
+        let precision = env::var("VALUE_PRECISION")
+            .ok()
+            .map(|p| p.parse().expect("Illegal value precision format"));
+        tracing::info!("VALUE_PRECISION: {:?}", precision);
+        precision
+    })
+}
+
+/// Rounds half away from zero to `VALUE_PRECISION` decimals, so 2.3400000000000003 is
+/// written as 2.34. Unchanged when no precision is set.
+pub fn round_value(value: f64) -> f64 {
+    round_to(value, value_precision())
+}
 
-    let write_result = client.query(variable.into_query(measurement)).await;
+fn round_to(value: f64, precision: Option<i32>) -> f64 {
+    match precision {
+        Some(precision) => {
+            let factor = 10f64.powi(precision);
+            (value * factor).round() / factor
+        }
+        None => value,
+    }
+}
+
+/// The points written for a charger, shared by the InfluxDB writes and the line protocol route.
+pub fn charger_queries(
+    charger: &ChargerState,
+    time: DateTime<Utc>,
+) -> Vec<(&'static str, WriteQuery)> {
+    let variable = |name: &str, value: f64| {
+        Variable {
+            time,
+            value: round_value(value),
+            variable: String::from(name),
+        }
+        .into_query(&charger.id)
+        .add_tag("name", charger.name.as_str())
+    };
+
+    let mut queries = vec![
+        ("power", variable("power", charger.power)),
+        (
+            "energy_per_hour",
+            variable("energy_per_hour", charger.energy_per_hour),
+        ),
+        ("session", variable("session", charger.session)),
+        (
+            "limiting_factor",
+            IntegerVariable {
+                time,
+                value: charger.limiting_factor() as i64,
+                variable: String::from("limiting_factor"),
+            }
+            .into_query(&charger.id)
+            .add_tag("name", charger.name.as_str()),
+        ),
+    ];
+    // Easee reports these as null while idle, which is nothing to write rather than an error
+    if let Some(current) = charger.output_current {
+        queries.push(("output_current", variable("output_current", current)));
+    }
+    if let Some(current) = charger.dynamic_charger_current {
+        queries.push(("dynamic_current", variable("dynamic_current", current)));
+    }
+    if let Some(kwh) = charger.lifetime_energy {
+        queries.push(("lifetime_energy", variable("lifetime_energy", kwh)));
+    }
+    // Known once the charger's details were fetched, the first points go without it
+    match memory::product(&charger.id).and_then(|p| p.product) {
+        Some(model) => queries
+            .into_iter()
+            .map(|(name, query)| (name, query.add_tag("model", model.as_str())))
+            .collect(),
+        None => queries,
+    }
+}
+
+/// Whether the charger's state could be fetched, written every tick for every charger.
+fn reachable_query(id: &str, name: &str, time: DateTime<Utc>, reachable: bool) -> WriteQuery {
+    IntegerVariable {
+        time,
+        value: reachable as i64,
+        variable: String::from("reachable"),
+    }
+    .into_query(id)
+    .add_tag("name", name)
+}
+
+/// Measurement the cost of the energy drawn between ticks is written to.
+pub const COST_MEASUREMENT: &str = "cost";
+
+#[derive(Debug, Clone, InfluxDbWriteable)]
+struct CostPoint {
+    time: DateTime<Utc>,
+    value: f64,
+    energy: f64,
+}
+
+/// The cost of the `kwh` drawn by the charger since the last tick.
+fn cost_query(charger: &ChargerState, cost: f64, kwh: f64, time: DateTime<Utc>) -> WriteQuery {
+    CostPoint {
+        time,
+        value: round_value(cost),
+        energy: round_value(kwh),
+    }
+    .into_query(COST_MEASUREMENT)
+    .add_tag("charger_id", charger.id.as_str())
+    .add_tag("name", charger.name.as_str())
+}
+
+/// Renders charger states as InfluxDB line protocol, one point per line.
+pub fn line_protocol(chargers: &[ChargerState], time: DateTime<Utc>) -> String {
+    chargers
+        .iter()
+        .flat_map(|charger| charger_queries(charger, time))
+        .filter_map(|(name, query)| match query.build() {
+            Ok(line) => Some(line.get()),
+            Err(e) => {
+                tracing::warn!("Rendering {} failed: {}", name, e);
+                None
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Every variable written for a charger, its state and the extras.
+pub(crate) fn charger_write_queries(
+    charger: &ChargerState,
+    time: DateTime<Utc>,
+    extras: ChargerExtras,
+) -> Vec<(&'static str, WriteQuery)> {
+    let mut queries = charger_queries(charger, time);
+    queries.push((
+        "reachable",
+        reachable_query(&charger.id, &charger.name, time, true),
+    ));
+    if let Some((cost, kwh)) = extras.cost {
+        queries.push(("cost", cost_query(charger, cost, kwh, time)));
+    }
+    if let Some(derived) = extras.energy_per_hour_derived {
+        queries.push((
+            "energy_per_hour_derived",
+            Variable {
+                time,
+                value: round_value(derived),
+                variable: String::from("energy_per_hour_derived"),
+            }
+            .into_query(&charger.id)
+            .add_tag("name", charger.name.as_str()),
+        ));
+    }
+    if let Some(kwh) = extras.energy_since_start {
+        queries.push((
+            "energy_since_start",
+            Variable {
+                time,
+                value: round_value(kwh),
+                variable: String::from("energy_since_start"),
+            }
+            .into_query(&charger.id)
+            .add_tag("name", charger.name.as_str()),
+        ));
+    }
+    if let Some(count) = extras.sessions_today {
+        queries.push((
+            "sessions_today",
+            IntegerVariable {
+                time,
+                value: count as i64,
+                variable: String::from("sessions_today"),
+            }
+            .into_query(&charger.id)
+            .add_tag("name", charger.name.as_str()),
+        ));
+    }
+    queries.push((
+        "data_suspect",
+        IntegerVariable {
+            time,
+            value: extras.suspect as i64,
+            variable: String::from("data_suspect"),
+        }
+        .into_query(&charger.id)
+        .add_tag("name", charger.name.as_str()),
+    ));
+    queries
+}
+
+/// The queries as line protocol, leaving out those that can't be rendered.
+fn render_lines(queries: &[(&'static str, WriteQuery)]) -> Vec<String> {
+    queries
+        .iter()
+        .filter_map(|(_, query)| query.build().ok().map(|q| q.get()))
+        .collect()
+}
+
+/// Writes every variable of a charger to a target, returning how many points were written
+/// and why any of the writes failed.
+#[instrument(skip_all, fields(charger_id = %log_id(&charger_id), target = %target.addr), level = "info")]
+async fn write_charger(
+    target: DbTarget,
+    charger_id: String,
+    queries: Vec<(&'static str, WriteQuery)>,
+) -> ChargerWrite {
+    let mut written = 0;
+    let mut failures = Vec::new();
+    let mut failed_lines = Vec::new();
+    let mut queries = queries.into_iter().peekable();
+    while queries.peek().is_some() {
+        let (names, batch): (Vec<&str>, Vec<WriteQuery>) =
+            queries.by_ref().take(influx_batch_size()).unzip();
+        let lines: Vec<String> = batch
+            .iter()
+            .filter_map(|query| query.build().ok().map(|q| q.get()))
+            .collect();
+        let count = batch.len();
+        // A batch fails as a whole, so all of it is retried
+        match write_to_db(&target, &names.join(","), batch).await {
+            Ok(()) => written += count,
+            Err(e) => {
+                failures.push(format!("{}: {}", target.addr, e));
+                failed_lines.extend(lines);
+            }
+        }
+    }
+    ChargerWrite {
+        addr: target.addr,
+        charger_id,
+        written,
+        failures,
+        failed_lines,
+    }
+}
+
+/// Points sent per request to InfluxDB, from `INFLUX_BATCH_SIZE`. Defaults to 500.
+pub fn influx_batch_size() -> usize {
+    static BATCH_SIZE: OnceLock<usize> = OnceLock::new();
+    *BATCH_SIZE.get_or_init(|| {
+        let batch_size = env::var("INFLUX_BATCH_SIZE")
+            .map_or(500, |b| b.parse().expect("Illegal batch size format"))
+            .max(1);
+        tracing::info!("INFLUX_BATCH_SIZE: {}", batch_size);
+        batch_size
+    })
+}
+
+/// How long a write to InfluxDB may take, from `INFLUXDB_TIMEOUT_SECS`. Defaults to 10.
+pub fn influx_timeout() -> std::time::Duration {
+    static TIMEOUT: OnceLock<std::time::Duration> = OnceLock::new();
+    *TIMEOUT.get_or_init(|| {
+        let secs = env::var("INFLUXDB_TIMEOUT_SECS")
+            .map_or(10, |t| t.parse().expect("Illegal InfluxDB timeout format"));
+        tracing::info!("INFLUXDB_TIMEOUT_SECS: {}", secs);
+        std::time::Duration::from_secs(secs)
+    })
+}
+
+/// Writes slower than this are logged, from `INFLUXDB_SLOW_WRITE_MS`. Defaults to 2000.
+fn influx_slow_write() -> std::time::Duration {
+    static SLOW: OnceLock<std::time::Duration> = OnceLock::new();
+    *SLOW.get_or_init(|| {
+        let millis = env::var("INFLUXDB_SLOW_WRITE_MS").map_or(2000, |t| {
+            t.parse().expect("Illegal InfluxDB slow write format")
+        });
+        tracing::info!("INFLUXDB_SLOW_WRITE_MS: {}", millis);
+        std::time::Duration::from_millis(millis)
+    })
+}
+
+/// Writes the query, failing it after `influx_timeout()` so a stalled InfluxDB can't hold
+/// up the tick. A failed write is left to the caller to buffer.
+#[instrument(skip(target, query), level = "trace")]
+async fn write_to_db<Q: Query>(target: &DbTarget, name: &str, query: Q) -> Result<(), String> {
+    let started = std::time::Instant::now();
+    let write_result = timeout(influx_timeout(), target.write(query)).await;
+    let elapsed = started.elapsed();
+    if elapsed >= influx_slow_write() {
+        tracing::warn!("Writing {} took {}ms", name, elapsed.as_millis());
+    }
     match write_result {
-        Ok(_) => {
+        Ok(Ok(_)) => {
             tracing::trace!("Writing {} success", name);
+            Ok(())
         }
-        Err(e) => {
+        Ok(Err(e)) => {
             tracing::warn!("Writing {} failed: {}", name, e);
+            Err(format!("Writing {} failed: {}", name, e))
+        }
+        Err(_) => {
+            tracing::warn!(
+                "Writing {} timed out after {}s",
+                name,
+                influx_timeout().as_secs()
+            );
+            Err(format!("Writing {} timed out", name))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_influx_addresses() {
+        let cases = [
+            ("http://influx:8086", "http://influx:8086"),
+            ("http://influx:8086/", "http://influx:8086"),
+            ("https://influx.example.com", "https://influx.example.com"),
+            ("influx:8086", "http://influx:8086"),
+            ("influx", "http://influx"),
+            ("  influx:8086  ", "http://influx:8086"),
+            ("localhost:8086/", "http://localhost:8086"),
+            ("10.0.0.5:8086", "http://10.0.0.5:8086"),
+            ("[::1]:8086", "http://[::1]:8086"),
+            ("http://influx:8086/proxy/", "http://influx:8086/proxy"),
+            ("HTTP://Influx:8086", "http://influx:8086"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                normalize_addr(input).as_deref(),
+                Ok(expected),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_unusable_addresses() {
+        for input in [
+            "ftp://influx:8086",
+            "udp://influx:8089",
+            "http://",
+            "http://influx:notaport",
+            "influx:99999",
+            "http://influx:8086/?db=x",
+            "http://influx:8086/#frag",
+        ] {
+            assert!(normalize_addr(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn rounds_half_away_from_zero() {
+        let cases = [
+            (2.3400000000000003, Some(2), 2.34),
+            (2.3400000000000003, None, 2.3400000000000003),
+            (0.125, Some(2), 0.13),
+            (-0.125, Some(2), -0.13),
+            (2.5, Some(0), 3.0),
+            (-2.5, Some(0), -3.0),
+            (11.04, Some(1), 11.0),
+            (1234.0, Some(-1), 1230.0),
+        ];
+        for (value, precision, expected) in cases {
+            assert_eq!(
+                round_to(value, precision),
+                expected,
+                "{} to {:?}",
+                value,
+                precision
+            );
+        }
+    }
+
+    #[test]
+    fn errors_say_how_the_address_was_read() {
+        let err = normalize_addr("influx:notaport").unwrap_err();
+        assert!(err.contains("http://influx:notaport"), "{}", err);
+    }
+}
@@ -0,0 +1,245 @@
+use std::env;
+
+use chrono::{DateTime, TimeZone, Utc};
+use influxdb::{InfluxDbWriteable, WriteQuery};
+use tracing::{info, instrument, warn};
+
+use super::run::DbTarget;
+
+/// Measurement describing the variables written to the charger measurements.
+pub const SCHEMA_MEASUREMENT: &str = "schema_info";
+
+/// A variable written per charger, with where it comes from.
+#[derive(Debug, Clone, Copy)]
+pub struct VariableInfo {
+    pub variable: &'static str,
+    pub unit: &'static str,
+    /// Field of the Easee state response, empty when the value is derived.
+    pub source: &'static str,
+    pub description: &'static str,
+}
+
+pub const VARIABLES: &[VariableInfo] = &[
+    VariableInfo {
+        variable: "power",
+        unit: "kW",
+        source: "totalPower",
+        description: "Power drawn by the charger",
+    },
+    VariableInfo {
+        variable: "energy_per_hour",
+        unit: "kWh",
+        source: "energyPerHour",
+        description: "Energy charged the last hour",
+    },
+    VariableInfo {
+        variable: "energy_per_hour_derived",
+        unit: "kWh",
+        source: "sessionEnergy",
+        description:
+            "Session energy gained per hour, when Easee reports no energy per hour while charging",
+    },
+    VariableInfo {
+        variable: "session",
+        unit: "kWh",
+        source: "sessionEnergy",
+        description: "Energy charged this session",
+    },
+    VariableInfo {
+        variable: "limiting_factor",
+        unit: "",
+        source: "reasonForNoCurrent",
+        description: "What limits the current, see LimitingFactor",
+    },
+    VariableInfo {
+        variable: "output_current",
+        unit: "A",
+        source: "outputCurrent",
+        description: "Current delivered, absent while idle",
+    },
+    VariableInfo {
+        variable: "dynamic_current",
+        unit: "A",
+        source: "dynamicChargerCurrent",
+        description: "Current the charger is allowed, absent while idle",
+    },
+    VariableInfo {
+        variable: "lifetime_energy",
+        unit: "kWh",
+        source: "lifetimeEnergy",
+        description: "Energy delivered over the charger's life",
+    },
+    VariableInfo {
+        variable: "energy_since_start",
+        unit: "kWh",
+        source: "",
+        description:
+            "Power integrated over the updates since the service started, reset on restart",
+    },
+    VariableInfo {
+        variable: "session_kwh",
+        unit: "kWh",
+        source: "kiloWattHours",
+        description: "Energy of a past session at the time the car connected, from `backfill`",
+    },
+    VariableInfo {
+        variable: "sessions_today",
+        unit: "",
+        source: "",
+        description: "Charging sessions started today, written when one starts",
+    },
+    VariableInfo {
+        variable: "data_suspect",
+        unit: "",
+        source: "",
+        description: "1 when power is reported but the session energy doesn't grow",
+    },
+    VariableInfo {
+        variable: "reachable",
+        unit: "",
+        source: "",
+        description: "1 when the charger's state could be fetched this update",
+    },
+];
+
+#[derive(Debug, Clone, InfluxDbWriteable)]
+struct SchemaPoint {
+    time: DateTime<Utc>,
+    unit: String,
+    source: String,
+    description: String,
+}
+
+/// Whether the schema is written at startup, from `WRITE_SCHEMA_ANNOTATIONS`.
+#[instrument(level = "trace")]
+pub fn schema_annotations_enabled() -> bool {
+    let enabled = env::var("WRITE_SCHEMA_ANNOTATIONS").is_ok_and(|w| w == "true");
+    tracing::info!("WRITE_SCHEMA_ANNOTATIONS: {}", enabled);
+    enabled
+}
+
+/// One point per variable, tagged with the crate version. The fixed time makes a rerun of the
+/// same version overwrite its points rather than add to them.
+pub fn schema_queries() -> Vec<WriteQuery> {
+    VARIABLES
+        .iter()
+        .map(|info| {
+            SchemaPoint {
+                time: Utc.timestamp_opt(0, 0).unwrap(),
+                unit: info.unit.to_string(),
+                source: info.source.to_string(),
+                description: info.description.to_string(),
+            }
+            .into_query(SCHEMA_MEASUREMENT)
+            .add_tag("variable", info.variable)
+            .add_tag("version", env!("CARGO_PKG_VERSION"))
+        })
+        .collect()
+}
+
+#[instrument(skip_all, level = "trace")]
+pub async fn write_schema_annotations(targets: &[DbTarget]) {
+    for target in targets {
+        for query in schema_queries() {
+            if let Err(e) = target.write(query).await {
+                warn!(
+                    "Writing schema annotations to {} failed: {}",
+                    target.addr, e
+                );
+                break;
+            }
+        }
+    }
+    info!("Wrote schema annotations for {} variables", VARIABLES.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::v1::{
+        run::{charger_write_queries, ChargerExtras},
+        sink::MemorySink,
+        structs::ChargerState,
+    };
+
+    fn documented() -> HashSet<&'static str> {
+        VARIABLES.iter().map(|info| info.variable).collect()
+    }
+
+    #[test]
+    fn every_variable_written_is_documented() {
+        // Every optional value present, so every variable is written
+        let charger = ChargerState::builder("EH000001")
+            .name("Garage")
+            .power(7.2)
+            .session(3.0)
+            .energy_per_hour(2.1)
+            .output_current(16.0)
+            .dynamic_charger_current(32.0)
+            .lifetime_energy(1234.5)
+            .build();
+        let extras = ChargerExtras {
+            suspect: true,
+            cost: Some((1.5, 0.6)),
+            sessions_today: Some(2),
+            energy_per_hour_derived: Some(2.1),
+            energy_since_start: Some(4.2),
+        };
+        let written: HashSet<&str> = charger_write_queries(&charger, Utc::now(), extras)
+            .into_iter()
+            .map(|(name, _)| name)
+            // Written to its own measurement, not the charger's
+            .filter(|&name| name != "cost")
+            .collect();
+
+        let documented = documented();
+        let undocumented: Vec<_> = written.difference(&documented).collect();
+        assert!(undocumented.is_empty(), "Undocumented: {:?}", undocumented);
+        assert!(written.len() >= 12, "{:?}", written);
+    }
+
+    #[test]
+    fn one_point_per_variable_tagged_with_the_version() {
+        assert_eq!(documented().len(), VARIABLES.len());
+        let lines: Vec<String> = schema_queries()
+            .into_iter()
+            .map(|query| query.build().unwrap().get())
+            .collect();
+        assert_eq!(lines.len(), VARIABLES.len());
+        for (line, info) in lines.iter().zip(VARIABLES) {
+            assert!(line.starts_with(SCHEMA_MEASUREMENT), "{}", line);
+            assert!(
+                line.contains(&format!("variable={}", info.variable)),
+                "{}",
+                line
+            );
+            assert!(
+                line.contains(&format!("version={}", env!("CARGO_PKG_VERSION"))),
+                "{}",
+                line
+            );
+            // The same time for every run, so rerunning overwrites
+            assert!(line.ends_with(" 0"), "{}", line);
+        }
+    }
+
+    #[tokio::test]
+    async fn rewriting_writes_the_same_points() {
+        let sink = Arc::new(MemorySink::new());
+        let target = DbTarget::with_sink(
+            String::from("http://influx.invalid"),
+            String::from("easee"),
+            sink.clone(),
+        );
+        write_schema_annotations(&[target.clone()]).await;
+        let first = sink.measurement(SCHEMA_MEASUREMENT);
+        assert_eq!(first.len(), VARIABLES.len());
+        write_schema_annotations(&[target]).await;
+        let all = sink.measurement(SCHEMA_MEASUREMENT);
+        assert_eq!(&all[first.len()..], &first[..]);
+    }
+}
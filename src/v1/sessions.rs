@@ -0,0 +1,205 @@
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::StreamExt;
+use tokio::sync::Mutex;
+use tracing::{debug, info, instrument};
+
+use super::{
+    anonymize::log_id,
+    easee::get_charging_sessions,
+    report::{Period, ReportTimezone},
+    structs::{ChargerState, SessionState},
+};
+
+/// Counts the charging sessions each charger started today, days ending at local midnight
+/// in `REPORT_TIMEZONE`.
+///
+/// Easee resets the session energy when a new session starts, so a drop in it is a new session.
+#[derive(Debug)]
+pub struct SessionCounter {
+    tz: ReportTimezone,
+    counters: HashMap<String, DayCount>,
+}
+
+#[derive(Debug)]
+struct DayCount {
+    day: NaiveDate,
+    count: u32,
+    last_session: f64,
+}
+
+impl SessionCounter {
+    pub fn new(tz: ReportTimezone) -> Self {
+        SessionCounter {
+            tz,
+            counters: HashMap::new(),
+        }
+    }
+
+    pub fn knows(&self, charger_id: &str) -> bool {
+        self.counters.contains_key(charger_id)
+    }
+
+    pub fn forget(&mut self, charger_id: &str) {
+        self.counters.remove(charger_id);
+    }
+
+    /// Start of the local day `now` falls on.
+    pub fn day_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        self.tz.period_start(Period::Today, now)
+    }
+
+    /// Starts counting a charger from `count`, the sessions already started today.
+    pub fn seed(&mut self, charger: &ChargerState, count: u32, now: DateTime<Utc>) {
+        self.counters.insert(
+            charger.id.clone(),
+            DayCount {
+                day: self.tz.date(now),
+                count,
+                last_session: charger.session,
+            },
+        );
+    }
+
+    /// Returns today's count when the charger started a new session since the last call.
+    pub fn observe(&mut self, charger: &ChargerState, now: DateTime<Utc>) -> Option<u32> {
+        let today = self.tz.date(now);
+        let counter = self.counters.entry(charger.id.clone()).or_insert(DayCount {
+            day: today,
+            count: 0,
+            last_session: charger.session,
+        });
+        if counter.day != today {
+            debug!(
+                "New day, resetting the session count of {}",
+                log_id(&charger.id)
+            );
+            counter.day = today;
+            counter.count = 0;
+        }
+        let started = charger.session < counter.last_session;
+        counter.last_session = charger.session;
+        if started {
+            counter.count += 1;
+            info!(
+                "Charger {} started session {} of the day",
+                log_id(&charger.id),
+                counter.count
+            );
+            Some(counter.count)
+        } else {
+            None
+        }
+    }
+
+    /// Today's count of every charger seen.
+    pub fn counts(&self) -> HashMap<String, u32> {
+        self.counters
+            .iter()
+            .map(|(id, counter)| (id.clone(), counter.count))
+            .collect()
+    }
+}
+
+/// Sessions a charger started since `from`, from its history, so a restart doesn't reset
+/// the count. `None` if the history couldn't be read.
+#[instrument(skip(session), level = "trace")]
+pub async fn sessions_since(
+    charger_id: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    session: Arc<Mutex<SessionState>>,
+) -> Option<u32> {
+    let sessions: Vec<_> = get_charging_sessions(charger_id.to_string(), from, to, session)
+        .collect()
+        .await;
+    let mut count = 0;
+    for result in sessions {
+        match result {
+            Ok(s) if s.car_connected >= from => count += 1,
+            Ok(_) => (),
+            Err(e) => {
+                debug!("Reading the session history failed: {}", e);
+                return None;
+            }
+        }
+    }
+    Some(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn oslo() -> ReportTimezone {
+        ReportTimezone::Named(chrono_tz::Europe::Oslo)
+    }
+
+    /// Time in Oslo on 2024-01-15, UTC+1.
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        chrono_tz::Europe::Oslo
+            .with_ymd_and_hms(2024, 1, 15, hour, minute, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn session(kwh: f64) -> ChargerState {
+        ChargerState::builder("EH000001").session(kwh).build()
+    }
+
+    #[test]
+    fn a_drop_in_session_energy_is_a_new_session() {
+        let mut counter = SessionCounter::new(oslo());
+        assert_eq!(counter.observe(&session(5.0), at(8, 0)), None);
+        assert_eq!(counter.observe(&session(7.0), at(9, 0)), None);
+        assert_eq!(counter.observe(&session(0.1), at(10, 0)), Some(1));
+        assert_eq!(counter.observe(&session(0.1), at(11, 0)), None);
+        assert_eq!(counter.observe(&session(0.0), at(12, 0)), Some(2));
+        assert_eq!(counter.counts()["EH000001"], 2);
+    }
+
+    #[test]
+    fn resets_at_local_midnight() {
+        let mut counter = SessionCounter::new(oslo());
+        counter.observe(&session(5.0), at(20, 0));
+        assert_eq!(counter.observe(&session(1.0), at(23, 30)), Some(1));
+        // 23:59 in Oslo is 22:59 UTC, still the same day
+        assert_eq!(counter.observe(&session(0.5), at(23, 59)), Some(2));
+        let after_midnight = at(23, 59) + chrono::Duration::minutes(2);
+        assert_eq!(counter.observe(&session(0.6), after_midnight), None);
+        assert_eq!(counter.counts()["EH000001"], 0);
+        assert_eq!(
+            counter.observe(&session(0.1), after_midnight + chrono::Duration::hours(1)),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn seeding_continues_from_the_history() {
+        let mut counter = SessionCounter::new(oslo());
+        assert!(!counter.knows("EH000001"));
+        counter.seed(&session(3.0), 4, at(12, 0));
+        assert!(counter.knows("EH000001"));
+        assert_eq!(counter.observe(&session(4.0), at(12, 5)), None);
+        assert_eq!(counter.observe(&session(0.2), at(13, 0)), Some(5));
+
+        counter.forget("EH000001");
+        assert!(!counter.knows("EH000001"));
+        // Without history it starts at zero
+        assert_eq!(counter.observe(&session(0.1), at(14, 0)), None);
+        assert_eq!(counter.counts()["EH000001"], 0);
+    }
+
+    #[test]
+    fn day_starts_at_local_midnight() {
+        let counter = SessionCounter::new(oslo());
+        assert_eq!(counter.day_start(at(15, 0)), at(0, 0));
+        assert_eq!(
+            counter.day_start(at(0, 30)),
+            Utc.with_ymd_and_hms(2024, 1, 14, 23, 0, 0).unwrap()
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::Mutex;
+
+use super::{easee::http_client, run::influx_timeout};
+
+/// Where points end up, written as InfluxDB line protocol with one point per line.
+#[rocket::async_trait]
+pub trait Sink: std::fmt::Debug + Send + Sync {
+    async fn write_lines(&self, lines: &str) -> Result<(), String>;
+}
+
+/// The write endpoint of an InfluxDB 1.x.
+///
+/// The HTTP client is built once, with the same TLS options as the one for Easee.
+#[derive(Clone)]
+pub struct InfluxSink {
+    addr: String,
+    name: String,
+    auth: Option<(String, String)>,
+    client: reqwest::Client,
+}
+
+impl std::fmt::Debug for InfluxSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("InfluxSink")
+            .field("addr", &self.addr)
+            .field("name", &self.name)
+            .field("auth", &self.auth.is_some())
+            .finish()
+    }
+}
+
+impl InfluxSink {
+    pub fn new(addr: String, name: String, auth: Option<(String, String)>) -> Self {
+        InfluxSink {
+            addr,
+            name,
+            auth,
+            client: http_client(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Sink for InfluxSink {
+    async fn write_lines(&self, lines: &str) -> Result<(), String> {
+        let url = format!("{}/write", self.addr.trim_end_matches('/'));
+        let mut request = self
+            .client
+            .post(url)
+            .timeout(influx_timeout())
+            .query(&[("db", self.name.as_str())])
+            .body(lines.to_string());
+        if let Some((ref username, ref password)) = self.auth {
+            request = request.basic_auth(username, Some(password));
+        }
+        let res = request
+            .send()
+            .await
+            .map_err(|e| format!("Writing to {} failed: {}", self.addr, e))?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Writing to {} failed: {}", self.addr, res.status()))
+        }
+    }
+}
+
+/// Keeps every line written to it, for tests and for embedding without an InfluxDB.
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    lines: Mutex<Vec<String>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        MemorySink::default()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().clone()
+    }
+
+    /// The lines of one measurement, e.g. a charger id.
+    pub fn measurement(&self, measurement: &str) -> Vec<String> {
+        self.lines()
+            .into_iter()
+            .filter(|line| line.split([',', ' ']).next() == Some(measurement))
+            .collect()
+    }
+}
+
+#[rocket::async_trait]
+impl Sink for MemorySink {
+    async fn write_lines(&self, lines: &str) -> Result<(), String> {
+        self.lines
+            .lock()
+            .unwrap()
+            .extend(lines.lines().map(str::to_string));
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use std::{env, sync::OnceLock};
+
+use influxdb::ReadQuery;
+use tracing::{debug, instrument, warn};
+
+use super::run::DbTarget;
+
+/// Tag naming the writer of a point.
+pub const SOURCE_TAG: &str = "source";
+
+/// The value every written point is tagged `source` with, from `WRITE_SOURCE_TAG`. Unset
+/// leaves the points untagged.
+pub fn source_tag() -> Option<&'static str> {
+    static SOURCE: OnceLock<Option<String>> = OnceLock::new();
+    SOURCE
+        .get_or_init(|| {
+            let source = env::var("WRITE_SOURCE_TAG").ok().filter(|s| !s.is_empty());
+            tracing::info!("WRITE_SOURCE_TAG: {:?}", source);
+            source
+        })
+        .as_deref()
+}
+
+/// Adds the `source` tag to lines of line protocol, which every point is written as.
+pub fn tag_lines(lines: &str) -> String {
+    match source_tag() {
+        Some(source) => lines
+            .lines()
+            .map(|line| tag_line(line, source))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => lines.to_string(),
+    }
+}
+
+/// Puts `,source=<source>` after the measurement and tags, which end at the first unescaped
+/// space.
+pub fn tag_line(line: &str, source: &str) -> String {
+    let mut escaped = false;
+    let end = line.char_indices().find_map(|(i, c)| match c {
+        _ if escaped => {
+            escaped = false;
+            None
+        }
+        '\\' => {
+            escaped = true;
+            None
+        }
+        ' ' => Some(i),
+        _ => None,
+    });
+    let source = source
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ");
+    match end {
+        Some(end) => format!("{},{}={}{}", &line[..end], SOURCE_TAG, source, &line[end..]),
+        None => line.to_string(),
+    }
+}
+
+/// Warns about points of the last hour without our `source` tag, which another writer into
+/// the same database, or this service before the tag was set, left there.
+#[instrument(skip_all, level = "trace")]
+pub async fn check_other_writers(targets: &[DbTarget]) {
+    if source_tag().is_none() {
+        return;
+    }
+    for (addr, untagged) in other_writers(targets).await {
+        warn!(
+            "{} has recent points without a {} tag in {}, another writer may be writing to the same measurements",
+            addr,
+            SOURCE_TAG,
+            untagged.join(", ")
+        );
+    }
+}
+
+/// The targets with recent untagged points, and the measurements they are in.
+async fn other_writers(targets: &[DbTarget]) -> Vec<(String, Vec<String>)> {
+    let query = format!(
+        "SELECT COUNT(*) FROM /.*/ WHERE \"{}\" = '' AND time > now() - 1h",
+        SOURCE_TAG
+    );
+    let mut found = Vec::new();
+    for target in targets {
+        match target.client().query(ReadQuery::new(query.clone())).await {
+            Ok(body) => {
+                let untagged = untagged_measurements(&body);
+                if !untagged.is_empty() {
+                    found.push((target.addr.clone(), untagged));
+                }
+            }
+            Err(e) => debug!("Checking {} for other writers failed: {}", target.addr, e),
+        }
+    }
+    found
+}
+
+/// The measurements a `COUNT` query found points in.
+pub fn untagged_measurements(body: &str) -> Vec<String> {
+    let json: serde_json::Value = match serde_json::from_str(body) {
+        Ok(json) => json,
+        Err(_) => return Vec::new(),
+    };
+    json["results"][0]["series"]
+        .as_array()
+        .map(|series| {
+            series
+                .iter()
+                .filter_map(|s| s["name"].as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    #[test]
+    fn the_tag_goes_after_the_tags() {
+        let cases = [
+            ("power value=1 1000", "power,source=poller value=1 1000"),
+            (
+                "power,charger_id=EH1 value=1 1000",
+                "power,charger_id=EH1,source=poller value=1 1000",
+            ),
+            (
+                "power,name=My\\ charger value=1 1000",
+                "power,name=My\\ charger,source=poller value=1 1000",
+            ),
+            ("power", "power"),
+        ];
+        for (line, tagged) in cases {
+            assert_eq!(tag_line(line, "poller"), tagged, "{}", line);
+        }
+    }
+
+    #[test]
+    fn the_source_is_escaped() {
+        assert_eq!(
+            tag_line("power value=1", "a b,c=d"),
+            "power,source=a\\ b\\,c\\=d value=1"
+        );
+    }
+
+    const COUNTS: &str = r#"{"results":[{"statement_id":0,"series":[
+        {"name":"power","columns":["time","count_value"],"values":[[0,3]]},
+        {"name":"session","columns":["time","count_value"],"values":[[0,1]]}
+    ]}]}"#;
+
+    #[test]
+    fn untagged_measurements_are_read_from_the_counts() {
+        assert_eq!(untagged_measurements(COUNTS), vec!["power", "session"]);
+        assert!(untagged_measurements(r#"{"results":[{"statement_id":0}]}"#).is_empty());
+        assert!(untagged_measurements("not json").is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_targets_with_untagged_points_are_reported() {
+        let influx = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/query"))
+            .and(query_param("db", "shared"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(COUNTS))
+            .mount(&influx)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/query"))
+            .and(query_param("db", "own"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"{"results":[{"statement_id":0}]}"#),
+            )
+            .mount(&influx)
+            .await;
+        let target = |db: &str| DbTarget::new(influx.uri(), db.to_string(), None);
+        let unreachable = DbTarget::new(
+            String::from("http://127.0.0.1:1"),
+            String::from("shared"),
+            None,
+        );
+
+        let found = other_writers(&[target("shared"), target("own"), unreachable]).await;
+        assert_eq!(
+            found,
+            vec![(
+                influx.uri(),
+                vec![String::from("power"), String::from("session")]
+            )]
+        );
+        let query = &influx.received_requests().await.unwrap()[0];
+        let q = query.url.query_pairs().find(|(k, _)| k == "q").unwrap().1;
+        assert!(q.contains("\"source\" = ''"), "{}", q);
+    }
+}
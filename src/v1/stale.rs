@@ -0,0 +1,142 @@
+use std::{collections::HashMap, env};
+
+use tracing::{info, instrument, warn};
+
+use super::{anonymize::log_id, structs::ChargerState};
+
+/// Flags chargers that draw power while their session energy stays put, which
+/// happens when the charger firmware hangs but keeps reporting its last values.
+#[derive(Debug)]
+pub struct StaleDetector {
+    power_threshold: f64,
+    max_unchanged_ticks: u32,
+    counters: HashMap<String, StaleCounter>,
+}
+
+#[derive(Debug)]
+struct StaleCounter {
+    last_session: f64,
+    unchanged_ticks: u32,
+}
+
+impl StaleDetector {
+    pub fn new(power_threshold: f64, max_unchanged_ticks: u32) -> Self {
+        StaleDetector {
+            power_threshold,
+            max_unchanged_ticks,
+            counters: HashMap::new(),
+        }
+    }
+
+    #[instrument(level = "trace")]
+    pub fn from_env() -> Self {
+        let power_threshold = env::var("STALE_POWER_THRESHOLD").map_or(0.1, |p| {
+            p.parse().expect("Illegal stale power threshold format")
+        });
+        tracing::info!("STALE_POWER_THRESHOLD: {}", power_threshold);
+
+        let max_unchanged_ticks =
+            env::var("STALE_TICKS").map_or(10, |t| t.parse().expect("Illegal stale ticks format"));
+        tracing::info!("STALE_TICKS: {}", max_unchanged_ticks);
+
+        StaleDetector::new(power_threshold, max_unchanged_ticks)
+    }
+
+    pub fn forget(&mut self, charger_id: &str) {
+        self.counters.remove(charger_id);
+    }
+
+    /// Records a new state for the charger, returning whether its data is suspect.
+    pub fn observe(&mut self, charger: &ChargerState) -> bool {
+        let counter = self
+            .counters
+            .entry(charger.id.clone())
+            .or_insert(StaleCounter {
+                last_session: charger.session,
+                unchanged_ticks: 0,
+            });
+        let was_suspect = counter.unchanged_ticks >= self.max_unchanged_ticks;
+
+        if charger.power > self.power_threshold && charger.session <= counter.last_session {
+            counter.unchanged_ticks += 1;
+        } else {
+            counter.unchanged_ticks = 0;
+        }
+        counter.last_session = charger.session;
+
+        let suspect = counter.unchanged_ticks >= self.max_unchanged_ticks;
+        if suspect && !was_suspect {
+            warn!(
+                "Charger {} reports {} kW but its session energy hasn't increased for {} ticks",
+                log_id(&charger.id),
+                charger.power,
+                counter.unchanged_ticks
+            );
+        } else if was_suspect && !suspect {
+            info!(
+                "Charger {} session energy is increasing again",
+                log_id(&charger.id)
+            );
+        }
+        suspect
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(power: f64, session: f64) -> ChargerState {
+        ChargerState::builder("EH000001")
+            .power(power)
+            .session(session)
+            .build()
+    }
+
+    #[test]
+    fn stuck_session_is_flagged_after_the_ticks() {
+        let mut detector = StaleDetector::new(0.1, 3);
+        // The first state counts too, it can't show growth
+        assert!(!detector.observe(&state(11.0, 5.0)));
+        assert!(!detector.observe(&state(11.0, 5.0)));
+        assert!(detector.observe(&state(11.0, 5.0)));
+        assert!(detector.observe(&state(11.0, 5.0)));
+    }
+
+    #[test]
+    fn recovers_once_the_session_grows() {
+        let mut detector = StaleDetector::new(0.1, 2);
+        for _ in 0..3 {
+            detector.observe(&state(11.0, 5.0));
+        }
+        assert!(detector.observe(&state(11.0, 5.0)));
+        assert!(!detector.observe(&state(11.0, 5.2)));
+        assert!(!detector.observe(&state(11.0, 5.2)));
+        assert!(detector.observe(&state(11.0, 5.2)));
+    }
+
+    #[test]
+    fn idle_chargers_are_never_stale() {
+        let mut detector = StaleDetector::new(0.1, 1);
+        for _ in 0..5 {
+            assert!(!detector.observe(&state(0.1, 5.0)));
+        }
+        // A drop to zero at a new session isn't growth, but the charger isn't drawing
+        assert!(!detector.observe(&state(0.0, 0.0)));
+    }
+
+    #[test]
+    fn chargers_are_counted_apart_and_forgotten() {
+        let mut detector = StaleDetector::new(0.1, 2);
+        let other = ChargerState::builder("EH000002")
+            .power(11.0)
+            .session(1.0)
+            .build();
+        detector.observe(&state(11.0, 5.0));
+        assert!(!detector.observe(&other));
+        assert!(detector.observe(&state(11.0, 5.0)));
+        detector.forget("EH000001");
+        assert!(!detector.observe(&state(11.0, 5.0)));
+        assert!(detector.observe(&other));
+    }
+}
@@ -1,17 +1,297 @@
-use std::error::Error;
+use std::{
+    error::Error,
+    sync::atomic::{AtomicI32, Ordering},
+};
 
 use chrono::{DateTime, Local, Utc};
 use influxdb::InfluxDbWriteable;
+use rocket::http::Status;
+use serde::{Serialize, Serializer};
+use utoipa::ToSchema;
 
-#[derive(Debug)]
+/// Decimals the JSON routes show values with, set from `json_precision` at launch.
+static JSON_PRECISION: AtomicI32 = AtomicI32::new(3);
+
+pub(crate) fn set_json_precision(precision: i32) {
+    JSON_PRECISION.store(precision, Ordering::Relaxed);
+}
+
+fn serialize_rounded<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    let factor = 10f64.powi(JSON_PRECISION.load(Ordering::Relaxed));
+    serializer.serialize_f64((value * factor).round() / factor)
+}
+
+fn serialize_rounded_opt<S: Serializer>(
+    value: &Option<f64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serialize_rounded(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// The state of a charger. Values are rounded to `json_precision` only when serialized.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[non_exhaustive]
 pub struct ChargerState {
     pub id: String,
+    /// From `CHARGER_NAME_MAP`, else the name set in Easee, else the id.
+    pub name: String,
+    #[serde(serialize_with = "serialize_rounded")]
     pub power: f64,
+    #[serde(serialize_with = "serialize_rounded")]
     pub session: f64,
+    #[serde(serialize_with = "serialize_rounded")]
     pub energy_per_hour: f64,
+    pub reason_for_no_current: Option<i64>,
+    #[serde(serialize_with = "serialize_rounded_opt")]
+    pub output_current: Option<f64>,
+    #[serde(serialize_with = "serialize_rounded_opt")]
+    pub dynamic_charger_current: Option<f64>,
+    #[serde(serialize_with = "serialize_rounded_opt")]
+    pub max_charger_current: Option<f64>,
+    /// kWh delivered over the charger's life.
+    #[serde(serialize_with = "serialize_rounded_opt")]
+    pub lifetime_energy: Option<f64>,
+}
+
+impl ChargerState {
+    pub fn builder(id: &str) -> ChargerStateBuilder {
+        ChargerStateBuilder::new(id)
+    }
+
+    /// Reads a `/chargers/{id}/state` response. The name is the id until one is set.
+    pub fn from_state_json(id: &str, json: &serde_json::Value) -> Result<Self, EaseeError> {
+        let required = |key: &str| json[key].as_f64().ok_or(EaseeError::InvalidResponse);
+        Ok(ChargerState {
+            id: id.to_string(),
+            name: id.to_string(),
+            power: required("totalPower")?,
+            session: required("sessionEnergy")?,
+            // Some firmware leaves it out, which is the same as the zero others report
+            energy_per_hour: json["energyPerHour"].as_f64().unwrap_or(0.0),
+            reason_for_no_current: json["reasonForNoCurrent"].as_i64(),
+            output_current: json["outputCurrent"].as_f64(),
+            dynamic_charger_current: json["dynamicChargerCurrent"].as_f64(),
+            max_charger_current: json["maxChargerCurrent"].as_f64(),
+            lifetime_energy: json["lifetimeEnergy"].as_f64(),
+        })
+    }
+
+    pub fn limiting_factor(&self) -> LimitingFactor {
+        LimitingFactor::from_state(
+            self.reason_for_no_current,
+            self.output_current,
+            self.dynamic_charger_current,
+            self.max_charger_current,
+        )
+    }
+}
+
+/// Reads a state response carrying its charger id as `id` or `chargerId`.
+impl TryFrom<&serde_json::Value> for ChargerState {
+    type Error = EaseeError;
+
+    fn try_from(json: &serde_json::Value) -> Result<Self, Self::Error> {
+        let id = json["id"]
+            .as_str()
+            .or_else(|| json["chargerId"].as_str())
+            .ok_or(EaseeError::InvalidResponse)?;
+        ChargerState::from_state_json(id, json)
+    }
+}
+
+/// Builds a `ChargerState` without fetching one, every value but the id defaulting to zero or none.
+#[derive(Debug, Clone)]
+pub struct ChargerStateBuilder {
+    state: ChargerState,
+}
+
+impl ChargerStateBuilder {
+    pub fn new(id: &str) -> Self {
+        ChargerStateBuilder {
+            state: ChargerState {
+                id: id.to_string(),
+                name: id.to_string(),
+                power: 0.0,
+                session: 0.0,
+                energy_per_hour: 0.0,
+                reason_for_no_current: None,
+                output_current: None,
+                dynamic_charger_current: None,
+                max_charger_current: None,
+                lifetime_energy: None,
+            },
+        }
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.state.name = name.to_string();
+        self
+    }
+
+    pub fn power(mut self, power: f64) -> Self {
+        self.state.power = power;
+        self
+    }
+
+    pub fn session(mut self, session: f64) -> Self {
+        self.state.session = session;
+        self
+    }
+
+    pub fn energy_per_hour(mut self, energy_per_hour: f64) -> Self {
+        self.state.energy_per_hour = energy_per_hour;
+        self
+    }
+
+    pub fn reason_for_no_current(mut self, reason: i64) -> Self {
+        self.state.reason_for_no_current = Some(reason);
+        self
+    }
+
+    pub fn output_current(mut self, current: f64) -> Self {
+        self.state.output_current = Some(current);
+        self
+    }
+
+    pub fn dynamic_charger_current(mut self, current: f64) -> Self {
+        self.state.dynamic_charger_current = Some(current);
+        self
+    }
+
+    pub fn max_charger_current(mut self, current: f64) -> Self {
+        self.state.max_charger_current = Some(current);
+        self
+    }
+
+    pub fn lifetime_energy(mut self, kwh: f64) -> Self {
+        self.state.lifetime_energy = Some(kwh);
+        self
+    }
+
+    pub fn build(self) -> ChargerState {
+        self.state
+    }
+}
+
+/// A charger as listed by Easee.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ChargerInfo {
+    pub id: String,
+    pub name: Option<String>,
+}
+
+/// What a charger is, from `/chargers/{id}/details`, fetched once per charger.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProductInfo {
+    /// The model, e.g. Easee Home or Easee Charge, written as the `model` tag.
+    pub product: Option<String>,
+    pub unit_type: Option<i64>,
+    pub serial_number: Option<String>,
+}
+
+impl ProductInfo {
+    pub fn from_details_json(json: &serde_json::Value) -> Self {
+        ProductInfo {
+            product: json["product"].as_str().map(str::to_string),
+            unit_type: json["unitType"].as_i64(),
+            serial_number: json["serialNumber"].as_str().map(str::to_string),
+        }
+    }
+}
+
+/// Charger settings that rarely change, polled on the slow tier.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChargerConfig {
+    pub id: String,
+    pub is_enabled: Option<bool>,
+    pub max_charger_current: Option<f64>,
+    pub phase_mode: Option<i64>,
+    pub smart_charging: Option<bool>,
+}
+
+/// A finished or ongoing charging session from the sessions history.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChargingSession {
+    pub charger_id: String,
+    pub session_id: Option<i64>,
+    pub car_connected: DateTime<Utc>,
+    pub car_disconnected: Option<DateTime<Utc>>,
+    pub kwh: f64,
+}
+
+/// The constraint currently keeping the charger from delivering more current.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitingFactor {
+    None = 0,
+    Car = 1,
+    Charger = 2,
+    Circuit = 3,
+    Schedule = 4,
+    Unknown = 5,
+}
+
+/// Output current this close to a limit counts as being held by it.
+const CURRENT_TOLERANCE: f64 = 1.0;
+
+impl LimitingFactor {
+    pub fn from_state(
+        reason_for_no_current: Option<i64>,
+        output_current: Option<f64>,
+        dynamic_charger_current: Option<f64>,
+        max_charger_current: Option<f64>,
+    ) -> Self {
+        // Easee's reasonForNoCurrent codes, grouped by what they blame. 27 is the dynamic
+        // circuit current and 28 the equalizer, both limits on the circuit.
+        match reason_for_no_current {
+            Some(1..=4) | Some(25..=30) => return LimitingFactor::Circuit,
+            Some(51) | Some(52) | Some(75) | Some(77) | Some(78) => return LimitingFactor::Charger,
+            Some(54) | Some(76) => return LimitingFactor::Schedule,
+            Some(50) | Some(79) | Some(81) => return LimitingFactor::Car,
+            Some(0) | None => (),
+            Some(_) => return LimitingFactor::Unknown,
+        }
+
+        let output = match output_current {
+            Some(o) => o,
+            None => return LimitingFactor::Unknown,
+        };
+        if let (Some(dynamic), Some(max)) = (dynamic_charger_current, max_charger_current) {
+            if dynamic + CURRENT_TOLERANCE < max && output + CURRENT_TOLERANCE >= dynamic {
+                return LimitingFactor::Charger;
+            }
+        }
+        match dynamic_charger_current {
+            Some(dynamic) if output > 0.0 && output + CURRENT_TOLERANCE < dynamic => {
+                LimitingFactor::Car
+            }
+            _ => LimitingFactor::None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LimitingFactor::None => "none",
+            LimitingFactor::Car => "car",
+            LimitingFactor::Charger => "charger",
+            LimitingFactor::Circuit => "circuit",
+            LimitingFactor::Schedule => "schedule",
+            LimitingFactor::Unknown => "unknown",
+        }
+    }
+}
+
+impl std::fmt::Display for LimitingFactor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct SessionState {
     pub token: Option<String>,
     pub refresh_token: Option<String>,
@@ -34,13 +314,41 @@ impl Default for SessionState {
     }
 }
 
+/// Which halves of the service this process runs, selected by `MODE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Poller,
+    Server,
+    Both,
+}
+
+impl Mode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Poller => "poller",
+            Mode::Server => "server",
+            Mode::Both => "both",
+        }
+    }
+}
+
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum EaseeError {
     Unathorized,
     LoginFailed,
     HttpFailed,
     InvalidResponse,
     RateLimit,
+    Maintenance,
+    /// Easee refused access to a charger the account can list.
+    Forbidden,
+    /// Easee doesn't know a charger it lists, e.g. one that was factory reset.
+    NotFound,
+    /// Not logging in after too many failed logins, see `LOGIN_LOCKOUT_AFTER`.
+    LoginLockout,
+    /// Not fetched, the charger is marked `no_access`, see `NO_ACCESS_AFTER`.
+    NoAccess,
 }
 
 impl std::fmt::Display for EaseeError {
@@ -51,6 +359,46 @@ impl std::fmt::Display for EaseeError {
             EaseeError::HttpFailed => write!(f, "Http failed"),
             EaseeError::InvalidResponse => write!(f, "Invalid response"),
             EaseeError::RateLimit => write!(f, "Rate limit"),
+            EaseeError::Maintenance => write!(f, "Upstream maintenance"),
+            EaseeError::Forbidden => write!(f, "Forbidden"),
+            EaseeError::NotFound => write!(f, "Not found"),
+            EaseeError::LoginLockout => write!(f, "Login locked out"),
+            EaseeError::NoAccess => write!(f, "No access"),
+        }
+    }
+}
+
+impl EaseeError {
+    /// The status the routes answer with. Every variant is listed, so a new one has to be
+    /// given a status here.
+    pub fn http_status(&self) -> Status {
+        match self {
+            EaseeError::Unathorized => Status::Unauthorized,
+            EaseeError::LoginFailed => Status::Unauthorized,
+            EaseeError::HttpFailed => Status::BadGateway,
+            EaseeError::InvalidResponse => Status::BadGateway,
+            EaseeError::RateLimit => Status::TooManyRequests,
+            EaseeError::Maintenance => Status::ServiceUnavailable,
+            EaseeError::Forbidden => Status::Forbidden,
+            EaseeError::NotFound => Status::NotFound,
+            EaseeError::LoginLockout => Status::ServiceUnavailable,
+            EaseeError::NoAccess => Status::Forbidden,
+        }
+    }
+
+    /// What the routes tell the client went wrong.
+    pub fn user_message(&self) -> &'static str {
+        match self {
+            EaseeError::Unathorized => "not authorized by Easee",
+            EaseeError::LoginFailed => "login to Easee failed",
+            EaseeError::HttpFailed => "request to Easee failed",
+            EaseeError::InvalidResponse => "invalid response from Easee",
+            EaseeError::RateLimit => "rate limited by Easee",
+            EaseeError::Maintenance => "upstream maintenance",
+            EaseeError::Forbidden => "no access to the charger",
+            EaseeError::NotFound => "charger not found",
+            EaseeError::LoginLockout => "login locked out after failed logins",
+            EaseeError::NoAccess => "no access to the charger",
         }
     }
 }
@@ -63,6 +411,11 @@ impl Error for EaseeError {
             EaseeError::HttpFailed => "Http failed",
             EaseeError::InvalidResponse => "Invalid response",
             EaseeError::RateLimit => "Rate limit",
+            EaseeError::Maintenance => "Upstream maintenance",
+            EaseeError::Forbidden => "Forbidden",
+            EaseeError::NotFound => "Not found",
+            EaseeError::LoginLockout => "Login locked out",
+            EaseeError::NoAccess => "No access",
         }
     }
 }
@@ -74,3 +427,267 @@ pub struct Variable {
     #[influxdb(tag)]
     pub variable: String,
 }
+
+#[derive(InfluxDbWriteable)]
+pub struct IntegerVariable {
+    pub time: DateTime<Utc>,
+    pub value: i64,
+    #[influxdb(tag)]
+    pub variable: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A recorded `/chargers/{id}/state` response with the charger's configured max current,
+    /// which comes from the config rather than the state.
+    fn fixture(json: &str, max_charger_current: Option<f64>) -> ChargerState {
+        let json: serde_json::Value = serde_json::from_str(json).unwrap();
+        let mut state = ChargerState::from_state_json("EH000001", &json).unwrap();
+        state.max_charger_current = max_charger_current;
+        state
+    }
+
+    #[test]
+    fn full_power_is_not_limited() {
+        let state = fixture(
+            include_str!("../../tests/fixtures/state_charging.json"),
+            Some(16.0),
+        );
+        assert_eq!(state.limiting_factor(), LimitingFactor::None);
+    }
+
+    #[test]
+    fn car_drawing_less_than_offered_is_the_car() {
+        let state = fixture(
+            include_str!("../../tests/fixtures/state_car_limited.json"),
+            Some(16.0),
+        );
+        assert_eq!(state.limiting_factor(), LimitingFactor::Car);
+    }
+
+    #[test]
+    fn lowered_dynamic_current_is_the_charger() {
+        let json = include_str!("../../tests/fixtures/state_dynamic_limited.json");
+        assert_eq!(
+            fixture(json, Some(16.0)).limiting_factor(),
+            LimitingFactor::Charger
+        );
+        // Without the configured max there is nothing to compare the dynamic current to
+        assert_eq!(fixture(json, None).limiting_factor(), LimitingFactor::None);
+    }
+
+    #[test]
+    fn pending_schedule_is_the_schedule() {
+        let state = fixture(
+            include_str!("../../tests/fixtures/state_schedule.json"),
+            Some(16.0),
+        );
+        assert_eq!(state.limiting_factor(), LimitingFactor::Schedule);
+    }
+
+    #[test]
+    fn equalizer_is_the_circuit() {
+        let state = fixture(
+            include_str!("../../tests/fixtures/state_equalizer.json"),
+            Some(16.0),
+        );
+        assert_eq!(state.limiting_factor(), LimitingFactor::Circuit);
+    }
+
+    #[test]
+    fn idle_without_output_current_is_unknown() {
+        let state = fixture(include_str!("../../tests/fixtures/state_idle.json"), None);
+        assert_eq!(state.energy_per_hour, 0.0);
+        assert_eq!(state.limiting_factor(), LimitingFactor::Unknown);
+    }
+
+    #[test]
+    fn reason_codes_blame_the_right_limit() {
+        let cases = [
+            (1, LimitingFactor::Circuit),
+            (4, LimitingFactor::Circuit),
+            (25, LimitingFactor::Circuit),
+            (27, LimitingFactor::Circuit),
+            (28, LimitingFactor::Circuit),
+            (30, LimitingFactor::Circuit),
+            (51, LimitingFactor::Charger),
+            (52, LimitingFactor::Charger),
+            (75, LimitingFactor::Charger),
+            (77, LimitingFactor::Charger),
+            (78, LimitingFactor::Charger),
+            (54, LimitingFactor::Schedule),
+            (76, LimitingFactor::Schedule),
+            (50, LimitingFactor::Car),
+            (79, LimitingFactor::Car),
+            (81, LimitingFactor::Car),
+            (99, LimitingFactor::Unknown),
+        ];
+        for (reason, expected) in cases {
+            let factor =
+                LimitingFactor::from_state(Some(reason), Some(16.0), Some(16.0), Some(16.0));
+            assert_eq!(factor, expected, "reasonForNoCurrent {}", reason);
+        }
+    }
+
+    #[test]
+    fn reason_code_wins_over_the_currents() {
+        // Output well under the dynamic current looks like the car, but Easee blames the circuit
+        let factor = LimitingFactor::from_state(Some(2), Some(6.0), Some(16.0), Some(16.0));
+        assert_eq!(factor, LimitingFactor::Circuit);
+    }
+
+    #[test]
+    fn currents_within_tolerance_are_not_limited() {
+        let factor = LimitingFactor::from_state(Some(0), Some(15.5), Some(16.0), Some(16.0));
+        assert_eq!(factor, LimitingFactor::None);
+        let factor = LimitingFactor::from_state(None, Some(0.0), Some(16.0), Some(16.0));
+        assert_eq!(factor, LimitingFactor::None);
+    }
+
+    #[test]
+    fn written_as_the_enum_discriminant() {
+        assert_eq!(LimitingFactor::Schedule as i64, 4);
+        assert_eq!(LimitingFactor::Unknown.to_string(), "unknown");
+    }
+
+    #[test]
+    fn product_info_from_details() {
+        let json: serde_json::Value =
+            serde_json::from_str(include_str!("../../tests/fixtures/details.json")).unwrap();
+        let product = ProductInfo::from_details_json(&json);
+        assert_eq!(product.product.as_deref(), Some("Easee Home"));
+        assert_eq!(product.unit_type, Some(1));
+        assert_eq!(product.serial_number.as_deref(), Some("EH000001"));
+    }
+
+    #[test]
+    fn product_info_without_details() {
+        let product =
+            ProductInfo::from_details_json(&serde_json::json!({ "chargerId": "EH000001" }));
+        assert_eq!(product.product, None);
+        assert_eq!(product.unit_type, None);
+        assert_eq!(product.serial_number, None);
+    }
+
+    #[test]
+    fn json_rounds_to_three_decimals() {
+        let state = ChargerState::builder("EH000001")
+            .name("Garage")
+            .power(2.3400000000000003)
+            .session(12.34567)
+            .energy_per_hour(7.0)
+            .output_current(15.9996)
+            .lifetime_energy(1234.5)
+            .build();
+        assert_eq!(
+            serde_json::to_string(&state).unwrap(),
+            concat!(
+                r#"{"id":"EH000001","name":"Garage","power":2.34,"session":12.346,"#,
+                r#""energy_per_hour":7.0,"reason_for_no_current":null,"output_current":16.0,"#,
+                r#""dynamic_charger_current":null,"max_charger_current":null,"#,
+                r#""lifetime_energy":1234.5}"#
+            )
+        );
+        // Only the JSON is rounded
+        assert_eq!(state.power, 2.3400000000000003);
+    }
+
+    const STATE_FIXTURES: [&str; 6] = [
+        include_str!("../../tests/fixtures/state_car_limited.json"),
+        include_str!("../../tests/fixtures/state_charging.json"),
+        include_str!("../../tests/fixtures/state_dynamic_limited.json"),
+        include_str!("../../tests/fixtures/state_equalizer.json"),
+        include_str!("../../tests/fixtures/state_idle.json"),
+        include_str!("../../tests/fixtures/state_schedule.json"),
+    ];
+
+    #[test]
+    fn every_fixture_state_converts() {
+        for fixture in STATE_FIXTURES {
+            let mut json: serde_json::Value = serde_json::from_str(fixture).unwrap();
+            json["chargerId"] = "EH000001".into();
+            let state = ChargerState::try_from(&json).unwrap();
+            assert_eq!(state.id, "EH000001");
+            assert_eq!(state.name, "EH000001");
+            assert_eq!(state.power, json["totalPower"].as_f64().unwrap());
+            assert_eq!(state.session, json["sessionEnergy"].as_f64().unwrap());
+            assert_eq!(state.output_current, json["outputCurrent"].as_f64());
+            assert_eq!(
+                state.max_charger_current,
+                json["maxChargerCurrent"].as_f64()
+            );
+        }
+    }
+
+    #[test]
+    fn the_id_is_read_from_either_key() {
+        let json = serde_json::json!({
+            "id": "EH000001",
+            "chargerId": "EH000002",
+            "totalPower": 1.5,
+            "sessionEnergy": 2.0,
+        });
+        assert_eq!(ChargerState::try_from(&json).unwrap().id, "EH000001");
+        let json = serde_json::json!({
+            "chargerId": "EH000002",
+            "totalPower": 1.5,
+            "sessionEnergy": 2.0,
+        });
+        assert_eq!(ChargerState::try_from(&json).unwrap().id, "EH000002");
+    }
+
+    #[test]
+    fn missing_or_mistyped_required_fields_are_invalid() {
+        let valid = serde_json::json!({
+            "id": "EH000001",
+            "totalPower": 1.5,
+            "sessionEnergy": 2.0,
+        });
+        assert!(ChargerState::try_from(&valid).is_ok());
+        for (key, value) in [
+            ("id", serde_json::Value::Null),
+            ("id", 1.into()),
+            ("totalPower", serde_json::Value::Null),
+            ("totalPower", "1.5".into()),
+            ("sessionEnergy", serde_json::Value::Null),
+            ("sessionEnergy", true.into()),
+        ] {
+            let mut json = valid.clone();
+            json[key] = value.clone();
+            assert!(
+                matches!(
+                    ChargerState::try_from(&json),
+                    Err(EaseeError::InvalidResponse)
+                ),
+                "{} = {}",
+                key,
+                value
+            );
+        }
+        assert!(matches!(
+            ChargerState::try_from(&serde_json::json!([])),
+            Err(EaseeError::InvalidResponse)
+        ));
+    }
+
+    #[test]
+    fn optional_fields_of_the_wrong_type_are_left_out() {
+        let json = serde_json::json!({
+            "id": "EH000001",
+            "totalPower": 1.5,
+            "sessionEnergy": 2.0,
+            "energyPerHour": "fast",
+            "reasonForNoCurrent": "none",
+            "outputCurrent": "16",
+            "lifetimeEnergy": null,
+        });
+        let state = ChargerState::try_from(&json).unwrap();
+        assert_eq!(state.energy_per_hour, 0.0);
+        assert_eq!(state.reason_for_no_current, None);
+        assert_eq!(state.output_current, None);
+        assert_eq!(state.dynamic_charger_current, None);
+        assert_eq!(state.lifetime_energy, None);
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    collections::VecDeque,
+    env,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tracing::{debug, warn};
+
+/// Kinds of Easee calls, each with its own budget and Retry-After bookkeeping, so a
+/// 429 on data calls can't hold back a token refresh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointClass {
+    Auth,
+    Data,
+    Commands,
+}
+
+impl EndpointClass {
+    const ALL: [EndpointClass; 3] = [
+        EndpointClass::Auth,
+        EndpointClass::Data,
+        EndpointClass::Commands,
+    ];
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+    fn budget_var(&self) -> (&'static str, u32) {
+        match self {
+            EndpointClass::Auth => ("EASEE_AUTH_PER_MINUTE", 5),
+            EndpointClass::Data => ("EASEE_DATA_PER_MINUTE", 100),
+            EndpointClass::Commands => ("EASEE_COMMANDS_PER_MINUTE", 10),
+        }
+    }
+
+    /// Calls per hour above which we warn, before Easee starts answering 429.
+    fn soft_limit_var(&self) -> (&'static str, u32) {
+        match self {
+            EndpointClass::Auth => ("EASEE_AUTH_PER_HOUR_SOFT", 30),
+            EndpointClass::Data => ("EASEE_DATA_PER_HOUR_SOFT", 3000),
+            EndpointClass::Commands => ("EASEE_COMMANDS_PER_HOUR_SOFT", 100),
+        }
+    }
+}
+
+const MINUTE: Duration = Duration::from_secs(60);
+const HOUR: Duration = Duration::from_secs(3600);
+
+#[derive(Debug)]
+struct ClassState {
+    per_minute: u32,
+    soft_per_hour: u32,
+    /// Calls sent the last hour, oldest first. The minute budget counts the newest of them.
+    sent: VecDeque<Instant>,
+    blocked_until: Option<Instant>,
+    warned_at: Option<Instant>,
+}
+
+impl ClassState {
+    fn new(per_minute: u32, soft_per_hour: u32) -> Self {
+        ClassState {
+            per_minute,
+            soft_per_hour,
+            sent: VecDeque::new(),
+            blocked_until: None,
+            warned_at: None,
+        }
+    }
+
+    fn forget_before(&mut self, now: Instant) {
+        while self
+            .sent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= HOUR)
+        {
+            self.sent.pop_front();
+        }
+    }
+
+    fn sent_within(&self, now: Instant, window: Duration) -> usize {
+        self.sent
+            .iter()
+            .rev()
+            .take_while(|t| now.duration_since(**t) < window)
+            .count()
+    }
+}
+
+/// The state of one class, for reporting.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClassReport {
+    pub class: EndpointClass,
+    pub per_minute: u32,
+    pub sent_last_minute: usize,
+    pub soft_per_hour: u32,
+    pub sent_last_hour: usize,
+    pub blocked_for_secs: Option<u64>,
+}
+
+static LIMITS: OnceLock<Mutex<Vec<ClassState>>> = OnceLock::new();
+
+fn limits() -> &'static Mutex<Vec<ClassState>> {
+    LIMITS.get_or_init(|| {
+        let states = EndpointClass::ALL
+            .iter()
+            .map(|class| {
+                let read = |(var, default): (&str, u32)| {
+                    let value = env::var(var).map_or(default, |b| {
+                        b.parse()
+                            .unwrap_or_else(|_| panic!("Illegal {} format", var))
+                    });
+                    tracing::info!("{}: {}", var, value);
+                    value
+                };
+                ClassState::new(read(class.budget_var()), read(class.soft_limit_var()))
+            })
+            .collect();
+        Mutex::new(states)
+    })
+}
+
+/// Takes a call from the class' budget, or returns false if it is spent or Easee asked
+/// us to wait.
+pub fn acquire(class: EndpointClass) -> bool {
+    limits().lock().unwrap()[class.index()].acquire(class, Instant::now())
+}
+
+/// Records a 429 from Easee, blocking only this class for the Retry-After it sent.
+pub fn rate_limited(class: EndpointClass, retry_after: Option<&reqwest::header::HeaderValue>) {
+    let wait = retry_after
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .map_or(Duration::from_secs(60), Duration::from_secs);
+    warn!(
+        "Easee rate limited {:?} calls for {}s",
+        class,
+        wait.as_secs()
+    );
+    limits().lock().unwrap()[class.index()].block(wait, Instant::now());
+}
+
+pub fn report() -> Vec<ClassReport> {
+    let now = Instant::now();
+    let limits = limits().lock().unwrap();
+    EndpointClass::ALL
+        .iter()
+        .map(|class| limits[class.index()].report(*class, now))
+        .collect()
+}
+
+impl ClassState {
+    fn acquire(&mut self, class: EndpointClass, now: Instant) -> bool {
+        if self.blocked_until.is_some_and(|until| now < until) {
+            debug!("{:?} calls blocked by Retry-After", class);
+            return false;
+        }
+        self.forget_before(now);
+        if self.sent_within(now, MINUTE) >= self.per_minute as usize {
+            warn!(
+                "{:?} call budget of {} per minute spent",
+                class, self.per_minute
+            );
+            return false;
+        }
+        self.sent.push_back(now);
+        if self.sent.len() > self.soft_per_hour as usize
+            && self.warned_at.is_none_or(|t| now.duration_since(t) >= HOUR)
+        {
+            warn!(
+                "{} {:?} calls sent the last hour, above the soft limit of {}",
+                self.sent.len(),
+                class,
+                self.soft_per_hour
+            );
+            self.warned_at = Some(now);
+        }
+        true
+    }
+
+    fn block(&mut self, wait: Duration, now: Instant) {
+        self.blocked_until = Some(now + wait);
+    }
+
+    fn report(&self, class: EndpointClass, now: Instant) -> ClassReport {
+        ClassReport {
+            class,
+            per_minute: self.per_minute,
+            sent_last_minute: self.sent_within(now, MINUTE),
+            soft_per_hour: self.soft_per_hour,
+            sent_last_hour: self.sent_within(now, HOUR),
+            blocked_for_secs: self
+                .blocked_until
+                .filter(|until| now < *until)
+                .map(|until| (until - now).as_secs()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classes(per_minute: u32, soft_per_hour: u32) -> Vec<ClassState> {
+        EndpointClass::ALL
+            .iter()
+            .map(|_| ClassState::new(per_minute, soft_per_hour))
+            .collect()
+    }
+
+    #[test]
+    fn minute_budget_rolls() {
+        let start = Instant::now();
+        let mut state = ClassState::new(3, 1000);
+        for i in 0..3 {
+            assert!(state.acquire(EndpointClass::Data, start + Duration::from_secs(i * 10)));
+        }
+        assert!(!state.acquire(EndpointClass::Data, start + Duration::from_secs(30)));
+        assert!(!state.acquire(EndpointClass::Data, start + Duration::from_secs(59)));
+        // The first call left the window
+        assert!(state.acquire(EndpointClass::Data, start + MINUTE));
+        assert!(!state.acquire(EndpointClass::Data, start + MINUTE));
+        assert!(state.acquire(EndpointClass::Data, start + Duration::from_secs(70)));
+    }
+
+    #[test]
+    fn counts_the_rolling_hour() {
+        let start = Instant::now();
+        let mut state = ClassState::new(100, 5);
+        for i in 0..10 {
+            assert!(state.acquire(EndpointClass::Data, start + MINUTE * i));
+        }
+        let report = state.report(EndpointClass::Data, start + MINUTE * 9);
+        assert_eq!(report.sent_last_minute, 1);
+        assert_eq!(report.sent_last_hour, 10);
+        assert!(state.warned_at.is_some());
+
+        let report = state.report(EndpointClass::Data, start + HOUR + MINUTE * 5);
+        assert_eq!(report.sent_last_hour, 4);
+        state.acquire(EndpointClass::Data, start + HOUR + MINUTE * 5);
+        // Calls older than an hour are dropped
+        assert_eq!(state.sent.len(), 5);
+    }
+
+    #[test]
+    fn retry_after_blocks_until_it_passes() {
+        let start = Instant::now();
+        let mut state = ClassState::new(100, 1000);
+        state.block(Duration::from_secs(30), start);
+        assert!(!state.acquire(EndpointClass::Auth, start + Duration::from_secs(29)));
+        assert_eq!(
+            state
+                .report(EndpointClass::Auth, start + Duration::from_secs(10))
+                .blocked_for_secs,
+            Some(20)
+        );
+        assert!(state.acquire(EndpointClass::Auth, start + Duration::from_secs(30)));
+        assert_eq!(
+            state
+                .report(EndpointClass::Auth, start + Duration::from_secs(30))
+                .blocked_for_secs,
+            None
+        );
+    }
+
+    #[test]
+    fn classes_have_independent_budgets() {
+        let now = Instant::now();
+        let mut limits = classes(2, 1000);
+        let auth = EndpointClass::Auth.index();
+        let data = EndpointClass::Data.index();
+        assert!(limits[auth].acquire(EndpointClass::Auth, now));
+        assert!(limits[auth].acquire(EndpointClass::Auth, now));
+        assert!(!limits[auth].acquire(EndpointClass::Auth, now));
+        assert!(limits[data].acquire(EndpointClass::Data, now));
+
+        // An auth 429 doesn't hold back data calls, nor the other way round
+        limits[auth].block(Duration::from_secs(60), now);
+        assert!(limits[data].acquire(EndpointClass::Data, now));
+        limits[data].block(Duration::from_secs(60), now);
+        let later = now + MINUTE;
+        assert!(limits[auth].acquire(EndpointClass::Auth, later));
+        let commands = EndpointClass::Commands.index();
+        assert!(limits[commands].acquire(EndpointClass::Commands, now));
+    }
+}
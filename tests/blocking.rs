@@ -0,0 +1,42 @@
+//! The synchronous wrappers against a mock Easee, called without a runtime of their own.
+#![cfg(feature = "blocking")]
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{exclusive, MockEasee};
+use easee_status::{blocking, SessionState};
+use tokio::{runtime::Runtime, sync::Mutex};
+
+#[test]
+fn fetches_without_a_runtime() {
+    // Only the mock runs on a runtime, on its own threads, the calls are made from this one
+    let mock_runtime = Runtime::new().unwrap();
+    let _guard = mock_runtime.block_on(exclusive());
+    let easee = mock_runtime.block_on(async {
+        let easee = MockEasee::start().await;
+        easee.login(Duration::ZERO).await;
+        easee.chargers().await;
+        easee.state("EH000001", "state_charging.json").await;
+        easee.state("EH000002", "state_idle.json").await;
+        easee
+    });
+    let session = Arc::new(Mutex::new(SessionState::new()));
+
+    let ids = blocking::charger_ids(session.clone()).unwrap();
+    assert_eq!(ids, vec!["EH000001", "EH000002"]);
+    let state = blocking::charger_state("EH000001", session.clone()).unwrap();
+    assert_eq!(state.id, "EH000001");
+    let states = blocking::all_charger_states(session).unwrap();
+    assert_eq!(states.len(), 2);
+
+    let logins = mock_runtime.block_on(easee.count("POST", "/api/accounts/login"));
+    assert_eq!(logins, 1);
+}
+
+#[tokio::test]
+#[should_panic(expected = "can't be used from within an async runtime")]
+async fn panics_within_a_runtime() {
+    let session = Arc::new(Mutex::new(SessionState::new()));
+    let _ = blocking::charger_ids(session);
+}
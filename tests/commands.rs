@@ -0,0 +1,132 @@
+//! The charge now command and the command history, served against a mock Easee.
+mod common;
+
+use std::{env, sync::Arc, time::Duration};
+
+use common::{exclusive, free_port, server_config, MockEasee};
+use easee_status::prelude::*;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+const KEY: &str = "test-key";
+
+/// Sends the request once the server answers.
+async fn send(request: reqwest::RequestBuilder) -> reqwest::Response {
+    for _ in 0..50 {
+        if let Ok(response) = request.try_clone().unwrap().send().await {
+            return response;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The server never answered");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn charge_now_is_sent_and_kept_in_the_history() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+    easee
+        .post(
+            "/api/chargers/EH000001/commands/override_schedule",
+            200,
+            r#"{"commandId": 1}"#,
+        )
+        .await;
+    easee
+        .post(
+            "/api/chargers/EH000002/commands/override_schedule",
+            409,
+            r#"{"title": "Charger is already charging"}"#,
+        )
+        .await;
+    env::set_var("API_KEY", KEY);
+    env::set_var("API_KEY_SALT", "test-salt");
+
+    let sink = Arc::new(MemorySink::new());
+    let port = free_port();
+    let shutdown = CancellationToken::new();
+    let config = ServerConfig {
+        influx: Some(DbTarget::with_sink(
+            String::from("http://influx.invalid"),
+            String::from("easee"),
+            sink.clone(),
+        )),
+        ..server_config(port, shutdown.clone())
+    };
+    let server = tokio::spawn(serve(
+        config,
+        Arc::new(Mutex::new(SessionState::new())),
+        Arc::new(Cache::new(chrono::Duration::seconds(10))),
+        Arc::new(LastErrors::new()),
+    ));
+    let client = reqwest::Client::new();
+    let url = |path: &str| format!("http://127.0.0.1:{}{}", port, path);
+    let charge_now = |id: &str| {
+        client
+            .post(url(&format!("/charger/{}/charge_now", id)))
+            .header("X-Api-Key", KEY)
+            .header("X-Confirm", "yes")
+    };
+
+    let accepted = send(charge_now("EH000001")).await;
+    assert_eq!(accepted.status(), 202);
+    let body: serde_json::Value = accepted.json().await.unwrap();
+    assert_eq!(body["result"]["commandId"], 1);
+
+    let conflict = send(charge_now("EH000002")).await;
+    assert_eq!(conflict.status(), 409);
+
+    let charge_now_with = |key: &str, confirm: &str| {
+        client
+            .post(url("/charger/EH000001/charge_now"))
+            .header("X-Api-Key", key)
+            .header("X-Confirm", confirm)
+    };
+    let unauthorized = send(charge_now_with("wrong", "yes")).await;
+    assert_eq!(unauthorized.status(), 401);
+    let unconfirmed = send(charge_now_with(KEY, "no")).await;
+    assert_eq!(unconfirmed.status(), 428);
+    // Neither reached Easee
+    assert_eq!(
+        easee
+            .count("POST", "/api/chargers/EH000001/commands/override_schedule")
+            .await,
+        1
+    );
+
+    let history = send(
+        client
+            .get(url("/commands/history"))
+            .header("X-Api-Key", KEY),
+    )
+    .await;
+    assert_eq!(history.status(), 200);
+    let history: serde_json::Value = history.json().await.unwrap();
+    let history = history.as_array().unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0]["charger_id"], "EH000002");
+    assert_eq!(history[0]["status"], 409);
+    assert_eq!(history[1]["charger_id"], "EH000001");
+    assert_eq!(history[1]["status"], 200);
+    assert_eq!(history[0]["key_id"], history[1]["key_id"]);
+    for record in history {
+        assert!(!record.to_string().contains(KEY), "{}", record);
+    }
+    let no_key = send(client.get(url("/commands/history"))).await;
+    assert_eq!(no_key.status(), 401);
+
+    assert_eq!(sink.measurement("commands").len(), 2);
+
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .expect("The server kept running after the shutdown")
+        .unwrap()
+        .unwrap();
+    env::remove_var("API_KEY");
+    env::remove_var("API_KEY_SALT");
+}
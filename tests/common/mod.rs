@@ -0,0 +1,410 @@
+//! A mock Easee and fixtures shared by the integration tests.
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    env,
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use easee_status::prelude::{recorded_responses, MemorySink, ServerConfig, SessionState, Sink};
+use tokio::sync::{Mutex, MutexGuard};
+use tokio_util::sync::CancellationToken;
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    Layer,
+};
+use wiremock::{
+    matchers::{method, path, path_regex},
+    Mock, MockServer, Request, ResponseTemplate,
+};
+
+/// A recorded Easee response from `tests/fixtures`.
+pub fn fixture(name: &str) -> String {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Reading {} failed: {}", path, e))
+}
+
+pub fn json_fixture(name: &str) -> serde_json::Value {
+    serde_json::from_str(&fixture(name)).unwrap()
+}
+
+/// A session as a login would leave it, expiring `expires_in` from now.
+pub fn session(token: &str, refresh_token: &str, expires_in: chrono::Duration) -> SessionState {
+    let mut session = SessionState::new();
+    session.token = Some(token.to_string());
+    session.refresh_token = Some(refresh_token.to_string());
+    session.lifetime = Some(chrono::Local::now() + expires_in);
+    session
+}
+
+/// Held by a test while it points the service at its mock Easee. The service reads its
+/// configuration and keeps state in globals, so tests in one binary take turns.
+pub async fn exclusive() -> MutexGuard<'static, ()> {
+    static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+    LOCK.get_or_init(|| Mutex::new(())).lock().await
+}
+
+/// A port nothing listens on now.
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// A server on localhost without TLS or InfluxDB.
+pub fn server_config(port: u16, shutdown: CancellationToken) -> ServerConfig {
+    ServerConfig {
+        address: Some(String::from("127.0.0.1")),
+        port: Some(port),
+        tls: None,
+        influx: None,
+        shutdown,
+    }
+}
+
+/// An Easee answering with the fixtures, the service pointed at it.
+pub struct MockEasee {
+    pub server: MockServer,
+}
+
+impl MockEasee {
+    /// Starts the mock and points the Easee client at it, with credentials from the
+    /// environment, and budgets and a login lockout no test runs into.
+    pub async fn start() -> Self {
+        let server = MockServer::start().await;
+        env::set_var("EASEE_API_BASE", format!("{}/api", server.uri()));
+        env::set_var("CREDENTIALS_SOURCE", "env");
+        env::set_var("USERNAME", "user@example.com");
+        env::set_var("PASSWORD", "secret");
+        env::set_var("EASEE_AUTH_PER_MINUTE", "1000");
+        env::set_var("EASEE_DATA_PER_MINUTE", "10000");
+        env::set_var("LOGIN_LOCKOUT_AFTER", "1000");
+        MockEasee { server }
+    }
+
+    /// Logins answered with `login.json`, after `delay`.
+    pub async fn login(&self, delay: Duration) -> &Self {
+        Mock::given(method("POST"))
+            .and(path("/api/accounts/login"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(fixture("login.json"), "application/json")
+                    .set_delay(delay),
+            )
+            .mount(&self.server)
+            .await;
+        self
+    }
+
+    /// Logins rejected with `status`, as for wrong credentials.
+    pub async fn reject_login(&self, status: u16) -> &Self {
+        Mock::given(method("POST"))
+            .and(path("/api/accounts/login"))
+            .respond_with(ResponseTemplate::new(status))
+            .mount(&self.server)
+            .await;
+        self
+    }
+
+    /// Token refreshes answered with `refresh.json`, after `delay`.
+    pub async fn refresh(&self, delay: Duration) -> &Self {
+        self.refresh_with(json_fixture("refresh.json"), delay).await
+    }
+
+    /// Token refreshes answered with `refresh.json`, the token living `expires_in` seconds.
+    pub async fn refresh_expiring(&self, expires_in: i64) -> &Self {
+        let mut body = json_fixture("refresh.json");
+        body["expiresIn"] = expires_in.into();
+        self.refresh_with(body, Duration::ZERO).await
+    }
+
+    async fn refresh_with(&self, body: serde_json::Value, delay: Duration) -> &Self {
+        Mock::given(method("POST"))
+            .and(path("/api/accounts/refresh_token"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(body)
+                    .set_delay(delay),
+            )
+            .mount(&self.server)
+            .await;
+        self
+    }
+
+    /// The charger list answered with `chargers.json`.
+    pub async fn chargers(&self) -> &Self {
+        self.get("/api/chargers", 200, &fixture("chargers.json"))
+            .await
+    }
+
+    /// The state of `id` answered with the fixture `state`.
+    pub async fn state(&self, id: &str, state: &str) -> &Self {
+        self.get(&format!("/api/chargers/{}/state", id), 200, &fixture(state))
+            .await
+    }
+
+    /// The config of `id` answered with `config.json`.
+    pub async fn config(&self, id: &str) -> &Self {
+        self.get(
+            &format!("/api/chargers/{}/config", id),
+            200,
+            &fixture("config.json"),
+        )
+        .await
+    }
+
+    /// Every response recorded in `dir` with `RECORD_RESPONSES_DIR`, the latest of each
+    /// endpoint and charger.
+    pub async fn replay(&self, dir: &std::path::Path) -> &Self {
+        let recorded = recorded_responses(dir)
+            .unwrap_or_else(|e| panic!("Reading {} failed: {}", dir.display(), e));
+        for recorded in recorded {
+            let (verb, url) = recorded.route();
+            Mock::given(method(verb))
+                .and(path_regex(format!("^/api{}$", url)))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_raw(recorded.body, "application/json"),
+                )
+                .mount(&self.server)
+                .await;
+        }
+        self
+    }
+
+    pub async fn get(&self, url: &str, status: u16, body: &str) -> &Self {
+        Mock::given(method("GET"))
+            .and(path(url))
+            .respond_with(ResponseTemplate::new(status).set_body_raw(body, "application/json"))
+            .mount(&self.server)
+            .await;
+        self
+    }
+
+    pub async fn post(&self, url: &str, status: u16, body: &str) -> &Self {
+        Mock::given(method("POST"))
+            .and(path(url))
+            .respond_with(ResponseTemplate::new(status).set_body_raw(body, "application/json"))
+            .mount(&self.server)
+            .await;
+        self
+    }
+
+    /// The requests that reached the mock with this method and path.
+    pub async fn received(&self, method: &str, url: &str) -> Vec<Request> {
+        self.server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|r| r.method.as_str() == method && r.url.path() == url)
+            .collect()
+    }
+
+    /// The bearer tokens the requests to this path were sent with.
+    pub async fn tokens(&self, method: &str, url: &str) -> Vec<String> {
+        self.received(method, url)
+            .await
+            .iter()
+            .filter_map(|r| r.headers.get("authorization"))
+            .filter_map(|h| h.to_str().ok())
+            .map(|h| h.trim_start_matches("Bearer ").to_string())
+            .collect()
+    }
+
+    pub async fn count(&self, method: &str, url: &str) -> usize {
+        self.received(method, url).await.len()
+    }
+}
+
+/// A sink that can be taken down or slowed down, keeping what it gets while up.
+#[derive(Debug, Default)]
+pub struct FlakySink {
+    pub memory: MemorySink,
+    down: AtomicBool,
+    /// Writes with a line containing the pattern are delayed.
+    slow: std::sync::Mutex<Option<(String, Duration)>>,
+}
+
+impl FlakySink {
+    pub fn new() -> Self {
+        FlakySink::default()
+    }
+
+    pub fn set_down(&self, down: bool) {
+        self.down.store(down, Ordering::SeqCst);
+    }
+
+    pub fn slow_down(&self, pattern: &str, delay: Duration) {
+        *self.slow.lock().unwrap() = Some((pattern.to_string(), delay));
+    }
+
+    pub fn speed_up(&self) {
+        *self.slow.lock().unwrap() = None;
+    }
+}
+
+#[rocket::async_trait]
+impl Sink for FlakySink {
+    async fn write_lines(&self, lines: &str) -> Result<(), String> {
+        if self.down.load(Ordering::SeqCst) {
+            return Err(String::from("Sink is down"));
+        }
+        let delay = match *self.slow.lock().unwrap() {
+            Some((ref pattern, delay)) if lines.contains(pattern.as_str()) => Some(delay),
+            _ => None,
+        };
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+        self.memory.write_lines(lines).await
+    }
+}
+
+/// The fields of a span or an event, numbers kept as numbers.
+pub type Fields = HashMap<String, serde_json::Value>;
+
+struct FieldVisitor<'a>(&'a mut Fields);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+/// An event with its fields and the spans it happened in, innermost first.
+#[derive(Debug, Clone)]
+pub struct CapturedEvent {
+    pub level: Level,
+    pub fields: Fields,
+    pub spans: Vec<(&'static str, Fields)>,
+}
+
+impl CapturedEvent {
+    pub fn message(&self) -> &str {
+        self.fields
+            .get("message")
+            .and_then(|m| m.as_str())
+            .unwrap_or_default()
+    }
+
+    /// The field of the event, or of the innermost span that has it.
+    pub fn field(&self, name: &str) -> Option<&serde_json::Value> {
+        self.fields
+            .get(name)
+            .or_else(|| self.spans.iter().find_map(|(_, fields)| fields.get(name)))
+    }
+}
+
+/// A layer keeping every event, so tests can look at the fields the logs would carry.
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    events: Arc<std::sync::Mutex<Vec<CapturedEvent>>>,
+    /// The fields of every span that was entered, by name.
+    spans: Arc<std::sync::Mutex<Vec<(&'static str, Fields)>>>,
+}
+
+impl Capture {
+    pub fn events(&self) -> Vec<CapturedEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    pub fn spans(&self, name: &str) -> Vec<Fields> {
+        self.spans
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(span, _)| *span == name)
+            .map(|(_, fields)| fields.clone())
+            .collect()
+    }
+
+    /// A subscriber sending everything, whatever the level, to this capture.
+    pub fn subscriber(&self) -> impl Subscriber + Send + Sync {
+        tracing_subscriber::registry().with(self.clone())
+    }
+}
+
+impl<S> Layer<S> for Capture
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::new();
+        attrs.record(&mut FieldVisitor(&mut fields));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(&mut FieldVisitor(fields));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        let spans = ctx
+            .event_scope(event)
+            .into_iter()
+            .flatten()
+            .map(|span| {
+                let fields = span.extensions().get::<Fields>().cloned();
+                (span.name(), fields.unwrap_or_default())
+            })
+            .collect();
+        self.events.lock().unwrap().push(CapturedEvent {
+            level: *event.metadata().level(),
+            fields,
+            spans,
+        });
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(&id) {
+            let fields = span.extensions().get::<Fields>().cloned();
+            self.spans
+                .lock()
+                .unwrap()
+                .push((span.name(), fields.unwrap_or_default()));
+        }
+    }
+}
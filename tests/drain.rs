@@ -0,0 +1,72 @@
+//! Draining by SIGUSR1 pauses the poller and undraining resumes it. In its own binary, as
+//! draining is process-wide.
+#![cfg(unix)]
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{exclusive, MockEasee};
+use easee_status::prelude::*;
+use tokio_util::sync::CancellationToken;
+
+const INTERVAL: Duration = Duration::from_millis(100);
+
+fn toggle_draining() {
+    let status = std::process::Command::new("kill")
+        .args(["-USR1", &std::process::id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn no_ticks_start_while_drained() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+    easee.config("EH000001").await;
+    easee.config("EH000002").await;
+
+    let sink = Arc::new(MemorySink::new());
+    let target = DbTarget::with_sink(
+        String::from("http://influx.invalid"),
+        String::from("easee"),
+        sink.clone(),
+    );
+    let poller = Poller::builder(Arc::new(vec![target]))
+        .interval(INTERVAL)
+        .deadline(Duration::from_secs(2))
+        .slow_interval(Duration::from_secs(3600))
+        .build();
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn(watch_drain_signal(shutdown.clone()));
+    // Until the handler is installed SIGUSR1 would end the process
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let running = tokio::spawn(poller.run(shutdown.clone()));
+    let ticks = || sink.measurement("poller_heartbeat").len();
+
+    tokio::time::sleep(INTERVAL * 3).await;
+    toggle_draining();
+    tokio::time::sleep(INTERVAL * 3).await;
+    assert!(is_draining());
+    // Ticks already running have finished
+    let drained = ticks();
+    assert!(drained > 0);
+    tokio::time::sleep(INTERVAL * 5).await;
+    assert_eq!(ticks(), drained);
+
+    toggle_draining();
+    tokio::time::sleep(INTERVAL * 4).await;
+    assert!(!is_draining());
+    assert!(ticks() > drained, "{} ticks after {}", ticks(), drained);
+
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(1), running)
+        .await
+        .expect("The poller kept running after the shutdown")
+        .unwrap();
+}
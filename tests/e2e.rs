@@ -0,0 +1,78 @@
+//! The real poller against a mock Easee, writing to a sink in memory.
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{exclusive, MockEasee};
+use easee_status::prelude::*;
+use tokio_util::sync::CancellationToken;
+
+const INTERVAL: Duration = Duration::from_millis(100);
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn polls_easee_into_the_sink() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+    easee.config("EH000001").await;
+    easee.config("EH000002").await;
+
+    let sink = Arc::new(MemorySink::new());
+    let target = DbTarget::with_sink(
+        String::from("http://influx.invalid"),
+        String::from("easee"),
+        sink.clone(),
+    );
+    let poller = Poller::builder(Arc::new(vec![target]))
+        .interval(INTERVAL)
+        .deadline(Duration::from_secs(2))
+        .slow_interval(Duration::from_secs(3600))
+        .build();
+
+    let shutdown = CancellationToken::new();
+    let running = tokio::spawn(poller.run(shutdown.clone()));
+    tokio::time::sleep(INTERVAL * 5 + INTERVAL / 2).await;
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(1), running)
+        .await
+        .expect("The poller kept running after the shutdown")
+        .unwrap();
+    // Ticks already started are left to finish
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let ticks = sink.measurement("poller_heartbeat").len();
+    assert!((4..=7).contains(&ticks), "{} ticks", ticks);
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 1);
+    // There is no list TTL, every tick lists the chargers and so does the one slow tick
+    assert_eq!(easee.count("GET", "/api/chargers").await, ticks + 1);
+    for id in ["EH000001", "EH000002"] {
+        let url = format!("/api/chargers/{}/state", id);
+        assert_eq!(easee.count("GET", &url).await, ticks, "{}", url);
+        let url = format!("/api/chargers/{}/config", id);
+        assert_eq!(easee.count("GET", &url).await, 1, "{}", url);
+    }
+
+    let power = |id: &str| {
+        sink.measurement(id)
+            .into_iter()
+            .filter(|line| line.contains("variable=power"))
+            .collect::<Vec<_>>()
+    };
+    let garage = power("EH000001");
+    assert_eq!(garage.len(), ticks);
+    assert!(garage.iter().all(|line| line.contains("name=Garage")));
+    assert!(garage.iter().all(|line| line.contains("value=11.04")));
+    let driveway = power("EH000002");
+    assert_eq!(driveway.len(), ticks);
+    assert!(driveway.iter().all(|line| line.contains("value=0 ")));
+    let max_current = sink
+        .measurement("EH000001_config")
+        .into_iter()
+        .filter(|line| line.contains("variable=max_charger_current"))
+        .collect::<Vec<_>>();
+    assert_eq!(max_current.len(), 1, "{:?}", sink.lines());
+    assert!(max_current[0].contains("value=16"));
+}
@@ -0,0 +1,59 @@
+//! A tick against an InfluxDB that accepts connections but never answers. A binary of
+//! its own, as the timeout is read once per process.
+mod common;
+
+use std::{
+    env,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use common::{exclusive, MockEasee};
+use easee_status::prelude::*;
+use tokio::sync::Mutex;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn a_stalling_influxdb_cannot_hold_up_the_tick() {
+    let _guard = exclusive().await;
+    env::set_var("INFLUXDB_TIMEOUT_SECS", "1");
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+    let influx = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/write"))
+        .respond_with(ResponseTemplate::new(204).set_delay(Duration::from_secs(60)))
+        .mount(&influx)
+        .await;
+    let poller = Arc::new(PollerState::from_env(Arc::new(LastErrors::new())));
+
+    let started = Instant::now();
+    tick(
+        Arc::new(Mutex::new(SessionState::new())),
+        Arc::new(vec![DbTarget::new(
+            influx.uri(),
+            String::from("easee"),
+            None,
+        )]),
+        None,
+        poller.clone(),
+        // Well beyond the write timeout, so that is what has to end the writes
+        Duration::from_secs(30),
+    )
+    .await;
+    let elapsed = started.elapsed();
+
+    // A few writes one after the other, the lifetime total, the chargers together and the
+    // heartbeat, each given up after a second
+    assert!(elapsed < Duration::from_secs(8), "Took {:?}", elapsed);
+    assert!(!influx.received_requests().await.unwrap().is_empty());
+    // What timed out is kept to be written again
+    assert!(!poller.retry.is_empty().await);
+    env::remove_var("INFLUXDB_TIMEOUT_SECS");
+}
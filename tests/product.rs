@@ -0,0 +1,37 @@
+//! Fetching the product info of the chargers against a mock Easee.
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{exclusive, fixture, MockEasee};
+use easee_status::prelude::*;
+use tokio::sync::Mutex;
+
+#[tokio::test]
+async fn details_are_fetched_once_per_charger() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+    easee
+        .get(
+            "/api/chargers/EH000001/details",
+            200,
+            &fixture("details.json"),
+        )
+        .await;
+    // The details of the other charger fail, and aren't asked for again every time either
+    easee.get("/api/chargers/EH000002/details", 500, "").await;
+    let session = Arc::new(Mutex::new(SessionState::new()));
+
+    for _ in 0..3 {
+        get_charger_states(session.clone()).await.unwrap();
+    }
+
+    for id in ["EH000001", "EH000002"] {
+        let url = format!("/api/chargers/{}/details", id);
+        assert_eq!(easee.count("GET", &url).await, 1, "{}", url);
+    }
+}
@@ -0,0 +1,65 @@
+//! Recording Easee's responses with `RECORD_RESPONSES_DIR` and serving them again.
+mod common;
+
+use std::{env, sync::Arc, time::Duration};
+
+use chrono::Duration as ChronoDuration;
+use common::{exclusive, json_fixture, MockEasee};
+use easee_status::prelude::*;
+use tokio::sync::Mutex;
+
+/// Logs in, then refreshes the expired token and fetches the states.
+async fn fetch_states() -> Vec<serde_json::Value> {
+    let session = Arc::new(Mutex::new(SessionState::new()));
+    force_login(session.clone()).await.unwrap();
+    session.lock().await.lifetime = Some(chrono::Local::now() - ChronoDuration::seconds(1));
+    get_charger_states(session)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(_, state)| serde_json::to_value(state.unwrap()).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn recorded_responses_replay_and_hold_no_tokens() {
+    let _guard = exclusive().await;
+    let dir = env::temp_dir().join(format!("record-replay-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.refresh(Duration::ZERO).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+    env::set_var("RECORD_RESPONSES_DIR", &dir);
+    let states = fetch_states().await;
+    env::remove_var("RECORD_RESPONSES_DIR");
+
+    let recorded = recorded_responses(&dir).unwrap();
+    for endpoint in ["login", "refresh_token"] {
+        let body = &recorded
+            .iter()
+            .find(|r| r.endpoint == endpoint)
+            .unwrap_or_else(|| panic!("No {} recorded", endpoint))
+            .body;
+        assert!(body.contains("REDACTED"), "{}", body);
+        for fixture in ["login.json", "refresh.json"] {
+            for key in ["accessToken", "refreshToken"] {
+                let secret = json_fixture(fixture)[key].as_str().unwrap().to_string();
+                assert!(!body.contains(&secret), "{} in {}", secret, body);
+            }
+        }
+    }
+
+    // A new mock, knowing nothing but the recordings
+    let replayed = MockEasee::start().await;
+    replayed.replay(&dir).await;
+    assert_eq!(fetch_states().await, states);
+    assert_eq!(
+        replayed.count("GET", "/api/chargers/EH000001/state").await,
+        1
+    );
+}
@@ -0,0 +1,51 @@
+//! The scheduled session refresh against a mock Easee handing out short lived tokens.
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::Duration as ChronoDuration;
+use common::{exclusive, session, MockEasee};
+use easee_status::prelude::*;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn refreshes_once_per_expiry_under_load() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    // Tokens living 2s, well within the default margin of 60s
+    easee.refresh_expiring(2).await;
+    easee.chargers().await;
+    let session = Arc::new(Mutex::new(session(
+        "first",
+        "first-refresh",
+        ChronoDuration::seconds(1),
+    )));
+
+    let shutdown = CancellationToken::new();
+    let refresher = tokio::spawn(keep_session_fresh(session.clone(), shutdown.clone()));
+    let load = (0..4).map(|_| {
+        let (session, shutdown) = (session.clone(), shutdown.clone());
+        tokio::spawn(async move {
+            while !shutdown.is_cancelled() {
+                get_charger_states(session.clone()).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+    });
+    let load = futures::future::join_all(load);
+
+    // Half way through each token: at 0.5s, 1.5s and 2.5s
+    tokio::time::sleep(Duration::from_millis(3000)).await;
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(1), refresher)
+        .await
+        .expect("The refresh kept running after the shutdown")
+        .unwrap();
+    for requests in load.await {
+        requests.unwrap();
+    }
+
+    assert_eq!(easee.count("POST", "/api/accounts/refresh_token").await, 3);
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 0);
+}
@@ -0,0 +1,210 @@
+//! The Rocket server launched from the library, alone and next to the poller.
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{exclusive, free_port, server_config, MockEasee};
+use easee_status::prelude::*;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+const INTERVAL: Duration = Duration::from_millis(200);
+
+/// GETs the path once the server answers.
+async fn get(port: u16, path: &str) -> reqwest::Response {
+    let url = format!("http://127.0.0.1:{}{}", port, path);
+    for _ in 0..50 {
+        if let Ok(response) = reqwest::get(&url).await {
+            return response;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The server never answered {}", url);
+}
+
+async fn stop(
+    shutdown: CancellationToken,
+    server: tokio::task::JoinHandle<Result<(), ServerError>>,
+) {
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .expect("The server kept running after the shutdown")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn serves_health_on_the_configured_port() {
+    let port = free_port();
+    let shutdown = CancellationToken::new();
+    let server = tokio::spawn(serve(
+        server_config(port, shutdown.clone()),
+        Arc::new(Mutex::new(SessionState::new())),
+        Arc::new(Cache::fed()),
+        Arc::new(LastErrors::new()),
+    ));
+
+    let response = get(port, "/health").await;
+    assert_eq!(response.status(), 200);
+    let health: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(health["status"], "ok");
+
+    stop(shutdown, server).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn both_halves_share_one_fetch_per_tick() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+    easee.config("EH000001").await;
+    easee.config("EH000002").await;
+
+    let sink = Arc::new(MemorySink::new());
+    let target = DbTarget::with_sink(
+        String::from("http://influx.invalid"),
+        String::from("easee"),
+        sink.clone(),
+    );
+    let cache = Arc::new(Cache::fed());
+    let errors = Arc::new(LastErrors::new());
+    let poller = Poller::builder(Arc::new(vec![target]))
+        .cache(cache.clone())
+        .errors(errors.clone())
+        .interval(INTERVAL)
+        .deadline(Duration::from_secs(2))
+        .slow_interval(Duration::from_secs(3600))
+        .build();
+    let session = poller.session();
+
+    let port = free_port();
+    let shutdown = CancellationToken::new();
+    let server = tokio::spawn(serve(
+        server_config(port, shutdown.clone()),
+        session,
+        cache,
+        errors,
+    ));
+    let poller = tokio::spawn(poller.run(shutdown.clone()));
+
+    // The routes answer from what the poller fetched, however often they are asked
+    get(port, "/health").await;
+    tokio::time::sleep(INTERVAL * 2).await;
+    for _ in 0..10 {
+        let response = get(port, "/power/0").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "11.04");
+        let response = get(port, "/").await;
+        assert_eq!(response.status(), 200);
+        tokio::time::sleep(INTERVAL / 4).await;
+    }
+
+    shutdown.cancel();
+    poller.await.unwrap();
+    stop(shutdown, server).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let ticks = sink.measurement("poller_heartbeat").len();
+    assert!(ticks >= 3, "{} ticks", ticks);
+    for id in ["EH000001", "EH000002"] {
+        let url = format!("/api/chargers/{}/state", id);
+        assert_eq!(easee.count("GET", &url).await, ticks, "{}", url);
+    }
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_misses_share_one_fetch() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    // A slow login keeps the first fetch going while the others miss
+    easee.login(Duration::from_millis(300)).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+
+    let port = free_port();
+    let shutdown = CancellationToken::new();
+    let server = tokio::spawn(serve(
+        server_config(port, shutdown.clone()),
+        Arc::new(Mutex::new(SessionState::new())),
+        Arc::new(Cache::new(chrono::Duration::seconds(60))),
+        Arc::new(LastErrors::new()),
+    ));
+    get(port, "/health").await;
+
+    let (power, session, energy) = tokio::join!(
+        get(port, "/power/0"),
+        get(port, "/session/0"),
+        get(port, "/energy/0"),
+    );
+    for response in [power, session, energy] {
+        assert_eq!(response.status(), 200, "{}", response.url());
+    }
+    assert_eq!(easee.count("GET", "/api/chargers").await, 1);
+    for id in ["EH000001", "EH000002"] {
+        let url = format!("/api/chargers/{}/state", id);
+        assert_eq!(easee.count("GET", &url).await, 1, "{}", url);
+    }
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 1);
+
+    stop(shutdown, server).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn head_answers_from_the_cache_however_stale() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+    let fetches = || easee.count("GET", "/api/chargers/EH000001/state");
+
+    let port = free_port();
+    let shutdown = CancellationToken::new();
+    let server = tokio::spawn(serve(
+        server_config(port, shutdown.clone()),
+        Arc::new(Mutex::new(SessionState::new())),
+        Arc::new(Cache::new(chrono::Duration::seconds(2))),
+        Arc::new(LastErrors::new()),
+    ));
+    let url = format!("http://127.0.0.1:{}/power/0", port);
+    let max_age = |response: &reqwest::Response| -> i64 {
+        let header = response.headers()["Cache-Control"].to_str().unwrap();
+        header.strip_prefix("max-age=").unwrap().parse().unwrap()
+    };
+
+    // Nothing cached yet, and HEAD doesn't fetch it
+    get(port, "/health").await;
+    let head = reqwest::Client::new().head(&url).send().await.unwrap();
+    assert_eq!(head.status(), 503);
+    assert_eq!(fetches().await, 0);
+
+    let response = get(port, "/power/0").await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/plain; charset=utf-8"
+    );
+    assert!((1..=2).contains(&max_age(&response)), "{:?}", response);
+    assert_eq!(fetches().await, 1);
+
+    // Past the TTL a GET would fetch again, HEAD serves the stale snapshot
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    let head = reqwest::Client::new().head(&url).send().await.unwrap();
+    assert_eq!(head.status(), 200);
+    assert_eq!(max_age(&head), 0);
+    assert_eq!(fetches().await, 1);
+
+    let response = get(port, "/power/0").await;
+    assert_eq!(response.status(), 200);
+    assert!((1..=2).contains(&max_age(&response)), "{:?}", response);
+    assert_eq!(fetches().await, 2);
+
+    stop(shutdown, server).await;
+}
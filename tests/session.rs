@@ -0,0 +1,386 @@
+//! Logging in, refreshing and clearing the session against a mock Easee.
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::Duration as ChronoDuration;
+use common::{exclusive, fixture, json_fixture, session, MockEasee};
+use easee_status::prelude::*;
+use tokio::sync::Mutex;
+use tracing::Level;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+fn token(fixture: &str) -> String {
+    json_fixture(fixture)["accessToken"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+#[tokio::test]
+async fn manual_login_replaces_a_stale_token() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    let session = Arc::new(Mutex::new(session(
+        "stale",
+        "stale-refresh",
+        ChronoDuration::hours(1),
+    )));
+
+    let expires = force_login(session.clone()).await.unwrap();
+
+    let session = session.lock().await;
+    assert_eq!(session.token, Some(token("login.json")));
+    assert_eq!(session.refresh_token.as_deref(), Some("refresh-from-login"));
+    assert_eq!(session.lifetime, expires);
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 1);
+}
+
+#[tokio::test]
+async fn an_expired_token_is_refreshed_before_requests() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.refresh(Duration::ZERO).await;
+    easee.chargers().await;
+    let session = Arc::new(Mutex::new(session(
+        "stale",
+        "stale-refresh",
+        ChronoDuration::seconds(-1),
+    )));
+
+    get_charger_states(session.clone()).await.unwrap();
+
+    let refreshed = token("refresh.json");
+    assert_eq!(session.lock().await.token, Some(refreshed.clone()));
+    assert_eq!(easee.tokens("GET", "/api/chargers").await, vec![refreshed]);
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 0);
+}
+
+#[tokio::test]
+async fn a_failed_manual_login_keeps_the_session() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.reject_login(401).await;
+    easee.chargers().await;
+    let session = Arc::new(Mutex::new(session(
+        "working",
+        "working-refresh",
+        ChronoDuration::hours(1),
+    )));
+
+    assert!(matches!(
+        force_login(session.clone()).await,
+        Err(EaseeError::LoginFailed)
+    ));
+
+    assert_eq!(session.lock().await.token.as_deref(), Some("working"));
+    get_charger_states(session.clone()).await.unwrap();
+    assert_eq!(easee.tokens("GET", "/api/chargers").await, vec!["working"]);
+}
+
+#[tokio::test]
+async fn a_cleared_session_logs_in_again() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    let session = Arc::new(Mutex::new(session(
+        "working",
+        "working-refresh",
+        ChronoDuration::hours(1),
+    )));
+
+    clear_session(session.clone()).await;
+    assert_eq!(session.lock().await.token, None);
+
+    get_charger_states(session.clone()).await.unwrap();
+    let login = token("login.json");
+    assert_eq!(easee.tokens("GET", "/api/chargers").await, vec![login]);
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn a_slow_refresh_does_not_block_readers() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.refresh(Duration::from_millis(500)).await;
+    easee.chargers().await;
+    let session = Arc::new(Mutex::new(session(
+        "stale",
+        "stale-refresh",
+        ChronoDuration::seconds(-1),
+    )));
+
+    let request = tokio::spawn(get_charger_states(session.clone()));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let read = tokio::time::timeout(Duration::from_millis(50), async {
+        session.lock().await.token.clone()
+    })
+    .await
+    .expect("Reading the session waited for the refresh");
+    assert_eq!(read.as_deref(), Some("stale"));
+
+    request.await.unwrap().unwrap();
+    assert_eq!(session.lock().await.token, Some(token("refresh.json")));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn requests_on_one_session_overlap() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    let delay = Duration::from_millis(300);
+    Mock::given(method("GET"))
+        .and(path("/api/chargers"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_raw(fixture("chargers.json"), "application/json")
+                .set_delay(delay),
+        )
+        .mount(&easee.server)
+        .await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+    let session = Arc::new(Mutex::new(session(
+        "valid",
+        "valid-refresh",
+        ChronoDuration::hours(1),
+    )));
+
+    let started = std::time::Instant::now();
+    let (first, second) = tokio::join!(
+        get_charger_states(session.clone()),
+        get_charger_states(session.clone()),
+    );
+    let elapsed = started.elapsed();
+    first.unwrap();
+    second.unwrap();
+
+    assert_eq!(easee.count("GET", "/api/chargers").await, 2);
+    // Holding the session across the requests, the second would only start after the first
+    assert!(elapsed < delay * 2, "Took {:?}", elapsed);
+    assert_eq!(
+        easee.tokens("GET", "/api/chargers").await,
+        vec!["valid", "valid"]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn clearing_during_a_refresh_logs_in_again() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.refresh(Duration::from_millis(300)).await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    let session = Arc::new(Mutex::new(session(
+        "stale",
+        "stale-refresh",
+        ChronoDuration::seconds(-1),
+    )));
+
+    let request = tokio::spawn(get_charger_states(session.clone()));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    clear_session(session.clone()).await;
+
+    request.await.unwrap().unwrap();
+    let login = token("login.json");
+    assert_eq!(session.lock().await.token, Some(login.clone()));
+    assert_eq!(easee.tokens("GET", "/api/chargers").await, vec![login]);
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 1);
+}
+
+#[tokio::test]
+async fn the_audit_log_has_the_logins_but_no_secrets() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.refresh(Duration::ZERO).await;
+    easee.chargers().await;
+    let dir = std::env::temp_dir().join(format!("session-audit-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let (subscriber, log_guards) = build_logger(dir.to_str().unwrap(), Level::TRACE);
+
+    {
+        // The test runs on one thread, so everything it awaits logs to this subscriber
+        let _default = tracing::subscriber::set_default(subscriber);
+        let session = Arc::new(Mutex::new(SessionState::new()));
+        force_login(session.clone()).await.unwrap();
+        session.lock().await.lifetime = Some(chrono::Local::now() - ChronoDuration::seconds(1));
+        get_charger_states(session.clone()).await.unwrap();
+    }
+    drop(log_guards);
+
+    let audit = std::fs::read_to_string(dir.join("auth.log")).unwrap();
+    assert!(audit.contains("Login attempt"), "{}", audit);
+    assert!(audit.contains("Login succeeded"), "{}", audit);
+    for secret in [
+        "secret",
+        &token("login.json"),
+        &token("refresh.json"),
+        "refresh-from-login",
+    ] {
+        assert!(!audit.contains(secret), "{} in {}", secret, audit);
+    }
+}
+
+#[tokio::test]
+async fn a_gone_login_path_falls_back_to_the_other() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.post("/api/accounts/login", 404, "").await;
+    easee
+        .post("/api/accounts/token", 200, &fixture("login.json"))
+        .await;
+    let session = Arc::new(Mutex::new(SessionState::new()));
+
+    force_login(session.clone()).await.unwrap();
+    assert_eq!(session.lock().await.token, Some(token("login.json")));
+    // The path that answered is tried first from then on
+    force_login(session.clone()).await.unwrap();
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 1);
+    assert_eq!(easee.count("POST", "/api/accounts/token").await, 2);
+
+    // Back on the first path, which the other tests mock
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    force_login(session.clone()).await.unwrap();
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 1);
+}
+
+#[tokio::test]
+async fn no_login_path_answering_fails_the_login() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    let session = Arc::new(Mutex::new(SessionState::new()));
+
+    assert!(matches!(
+        force_login(session.clone()).await,
+        Err(EaseeError::LoginFailed)
+    ));
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 1);
+    assert_eq!(easee.count("POST", "/api/accounts/token").await, 1);
+    assert_eq!(session.lock().await.token, None);
+}
+
+#[tokio::test]
+async fn a_gone_refresh_path_falls_back_to_the_other() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.post("/api/accounts/refresh_token", 404, "").await;
+    easee
+        .post("/api/accounts/token/refresh", 200, &fixture("refresh.json"))
+        .await;
+    easee.chargers().await;
+    let session = Arc::new(Mutex::new(session(
+        "stale",
+        "stale-refresh",
+        ChronoDuration::seconds(-1),
+    )));
+
+    get_charger_states(session.clone()).await.unwrap();
+    assert_eq!(session.lock().await.token, Some(token("refresh.json")));
+    assert_eq!(easee.count("POST", "/api/accounts/token/refresh").await, 1);
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 0);
+
+    // Back on the first path, which the other tests mock
+    let easee = MockEasee::start().await;
+    easee.refresh(Duration::ZERO).await;
+    easee.chargers().await;
+    session.lock().await.lifetime = Some(chrono::Local::now() - ChronoDuration::seconds(1));
+    get_charger_states(session.clone()).await.unwrap();
+    assert_eq!(easee.count("POST", "/api/accounts/refresh_token").await, 1);
+}
+
+#[tokio::test]
+async fn a_rejected_refresh_logs_in() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.post("/api/accounts/refresh_token", 401, "").await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    let session = Arc::new(Mutex::new(session(
+        "stale",
+        "revoked-refresh",
+        ChronoDuration::seconds(-1),
+    )));
+
+    get_charger_states(session.clone()).await.unwrap();
+
+    let login = token("login.json");
+    assert_eq!(session.lock().await.token, Some(login.clone()));
+    assert_eq!(easee.count("POST", "/api/accounts/refresh_token").await, 1);
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 1);
+    assert_eq!(easee.tokens("GET", "/api/chargers").await, vec![login]);
+}
+
+#[tokio::test]
+async fn a_rate_limited_login_waits_for_retry_after() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/accounts/login"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+        .up_to_n_times(1)
+        .mount(&easee.server)
+        .await;
+    easee.login(Duration::ZERO).await;
+    let session = Arc::new(Mutex::new(SessionState::new()));
+
+    assert!(matches!(
+        force_login(session.clone()).await,
+        Err(EaseeError::RateLimit)
+    ));
+    // Not sent at all until Retry-After has passed
+    assert!(matches!(
+        force_login(session.clone()).await,
+        Err(EaseeError::RateLimit)
+    ));
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 1);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    force_login(session.clone()).await.unwrap();
+    assert_eq!(session.lock().await.token, Some(token("login.json")));
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 2);
+}
+
+#[tokio::test]
+async fn a_rate_limited_listing_backs_off_without_logging_in() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/chargers"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+        .up_to_n_times(1)
+        .mount(&easee.server)
+        .await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+    let session = Arc::new(Mutex::new(session(
+        "valid",
+        "valid-refresh",
+        ChronoDuration::hours(1),
+    )));
+
+    assert!(matches!(
+        get_charger_states(session.clone()).await,
+        Err(EaseeError::RateLimit)
+    ));
+    // Not sent at all until Retry-After has passed
+    assert!(matches!(
+        get_charger_states(session.clone()).await,
+        Err(EaseeError::RateLimit)
+    ));
+    assert_eq!(easee.count("GET", "/api/chargers").await, 1);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(get_charger_states(session.clone()).await.unwrap().len(), 2);
+    // Throttling is not a rejected token, so the session was kept
+    assert_eq!(session.lock().await.token.as_deref(), Some("valid"));
+    assert_eq!(easee.count("POST", "/api/accounts/login").await, 0);
+    assert_eq!(easee.count("POST", "/api/accounts/refresh_token").await, 0);
+}
@@ -0,0 +1,134 @@
+//! Fetching the charger states against a mock Easee failing some of the chargers.
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, FixedOffset};
+use common::{exclusive, free_port, server_config, MockEasee};
+use easee_status::prelude::*;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+/// The charger list with a good charger and one Easee has no state for.
+const WITH_A_RESET_CHARGER: &str = r#"[
+    {"id": "EH000001", "name": "Garage"},
+    {"id": "EH000404", "name": "Reset"}
+]"#;
+
+#[tokio::test]
+async fn a_charger_without_state_fails_alone_until_skipped() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.get("/api/chargers", 200, WITH_A_RESET_CHARGER).await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.get("/api/chargers/EH000404/state", 404, "").await;
+    let session = Arc::new(Mutex::new(SessionState::new()));
+
+    // Refused three times in a row, as NO_ACCESS_AFTER defaults to
+    for _ in 0..3 {
+        let states = get_charger_states(session.clone()).await.unwrap();
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].1.as_ref().unwrap().id, "EH000001");
+        assert!(
+            matches!(states[1].1, Err(EaseeError::NotFound)),
+            "{:?}",
+            states[1].1
+        );
+    }
+
+    let states = get_charger_states(session).await.unwrap();
+    assert!(states[0].1.is_ok());
+    assert!(
+        matches!(states[1].1, Err(EaseeError::NoAccess)),
+        "{:?}",
+        states[1].1
+    );
+    assert_eq!(easee.count("GET", "/api/chargers/EH000404/state").await, 3);
+    // The list is fetched every time, so the charger coming back would show
+    assert_eq!(easee.count("GET", "/api/chargers").await, 4);
+}
+
+/// The JSON body of the path, once the server answers.
+async fn get(port: u16, path: &str) -> serde_json::Value {
+    let url = format!("http://127.0.0.1:{}{}", port, path);
+    for _ in 0..50 {
+        if let Ok(response) = reqwest::get(&url).await {
+            return response.json().await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The server never answered {}", url);
+}
+
+/// When each charger was last fetched, from `/health` and from `/chargers`, which must agree.
+async fn last_success(port: u16) -> serde_json::Value {
+    let health = get(port, "/health").await["last_success"].clone();
+    let chargers = get(port, "/chargers").await;
+    for charger in chargers.as_array().unwrap() {
+        let id = charger["id"].as_str().unwrap();
+        assert_eq!(charger["last_success"], health[id], "{}", charger);
+    }
+    assert_eq!(chargers.as_array().unwrap().len(), 2, "{}", chargers);
+    health
+}
+
+fn at(value: &serde_json::Value) -> DateTime<FixedOffset> {
+    DateTime::parse_from_rfc3339(value.as_str().unwrap()).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn a_failing_charger_falls_behind_in_last_success() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+    let session = Arc::new(Mutex::new(SessionState::new()));
+    let cache = Arc::new(Cache::fed());
+    let port = free_port();
+    let shutdown = CancellationToken::new();
+    let server = tokio::spawn(serve(
+        server_config(port, shutdown.clone()),
+        session.clone(),
+        cache.clone(),
+        Arc::new(LastErrors::new()),
+    ));
+
+    // Published once, so /chargers keeps listing both whatever fails later
+    let (order, states): (Vec<String>, Vec<_>) = get_charger_states(session.clone())
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(charger, state)| (charger.id, state.unwrap()))
+        .unzip();
+    cache.publish(order, states).await;
+    let before = last_success(port).await;
+    assert!(before["EH000001"].is_string(), "{}", before);
+    assert!(before["EH000002"].is_string(), "{}", before);
+
+    // EH000002 starts failing
+    easee.server.reset().await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.get("/api/chargers/EH000002/state", 500, "").await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    get_charger_states(session).await.unwrap();
+
+    let after = last_success(port).await;
+    assert!(
+        at(&after["EH000001"]) > at(&before["EH000001"]),
+        "{}",
+        after
+    );
+    assert_eq!(after["EH000002"], before["EH000002"]);
+
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .expect("The server kept running after the shutdown")
+        .unwrap()
+        .unwrap();
+}
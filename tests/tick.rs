@@ -0,0 +1,509 @@
+//! Ticks against a mock Easee and a sink that fails or stalls.
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use common::{exclusive, fixture, Capture, FlakySink, MockEasee};
+use easee_status::prelude::*;
+use tokio::sync::Mutex;
+use wiremock::{
+    matchers::{method, path, path_regex},
+    Mock, ResponseTemplate,
+};
+
+const DEADLINE: Duration = Duration::from_millis(500);
+
+struct Setup {
+    easee: MockEasee,
+    sink: Arc<FlakySink>,
+    targets: Arc<Vec<DbTarget>>,
+    session: Arc<Mutex<SessionState>>,
+    poller: Arc<PollerState>,
+}
+
+impl Setup {
+    async fn new() -> Self {
+        let easee = MockEasee::start().await;
+        easee.login(Duration::ZERO).await;
+        let sink = Arc::new(FlakySink::new());
+        let target = DbTarget::with_sink(
+            String::from("http://influx.invalid"),
+            String::from("easee"),
+            sink.clone(),
+        );
+        Setup {
+            easee,
+            sink,
+            targets: Arc::new(vec![target]),
+            session: Arc::new(Mutex::new(SessionState::new())),
+            poller: Arc::new(PollerState::from_env(Arc::new(LastErrors::new()))),
+        }
+    }
+
+    async fn tick(&self) {
+        tick(
+            self.session.clone(),
+            self.targets.clone(),
+            None,
+            self.poller.clone(),
+            DEADLINE,
+        )
+        .await;
+    }
+
+    fn outcomes(&self) -> Vec<String> {
+        self.sink
+            .memory
+            .measurement("poller_heartbeat")
+            .into_iter()
+            .filter_map(|line| {
+                let start = line.find("outcome=\"")? + "outcome=\"".len();
+                let end = start + line[start..].find('"')?;
+                Some(line[start..end].to_string())
+            })
+            .collect()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn a_fetch_timeout_still_writes_buffered_points() {
+    let _guard = exclusive().await;
+    let setup = Setup::new().await;
+    let chargers =
+        ResponseTemplate::new(200).set_body_raw(fixture("chargers.json"), "application/json");
+    Mock::given(method("GET"))
+        .and(path("/api/chargers"))
+        .respond_with(chargers.clone())
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&setup.easee.server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/chargers"))
+        .respond_with(chargers.set_delay(DEADLINE * 4))
+        .mount(&setup.easee.server)
+        .await;
+    setup.easee.state("EH000001", "state_charging.json").await;
+    setup.easee.state("EH000002", "state_idle.json").await;
+
+    // The states of the first tick can't be written
+    setup.sink.set_down(true);
+    setup.tick().await;
+    assert!(!setup.poller.retry.is_empty().await);
+    setup.sink.set_down(false);
+
+    setup.tick().await;
+
+    assert!(setup.poller.retry.is_empty().await);
+    assert_eq!(setup.outcomes(), vec!["fetch_timeout"]);
+    let power: Vec<String> = setup
+        .sink
+        .memory
+        .measurement("EH000001")
+        .into_iter()
+        .filter(|line| line.contains("variable=power"))
+        .collect();
+    assert_eq!(power.len(), 1);
+    assert!(power[0].contains("value=11.04"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn a_write_timeout_still_writes_buffered_points() {
+    let _guard = exclusive().await;
+    let setup = Setup::new().await;
+    setup.easee.chargers().await;
+    Mock::given(method("GET"))
+        .and(path_regex("^/api/chargers/[^/]+/state$"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(2)
+        .with_priority(1)
+        .mount(&setup.easee.server)
+        .await;
+    setup.easee.state("EH000001", "state_charging.json").await;
+    setup.easee.state("EH000002", "state_idle.json").await;
+
+    // Both chargers are unreachable and that can't be written
+    setup.sink.set_down(true);
+    setup.tick().await;
+    assert!(!setup.poller.retry.is_empty().await);
+    setup.sink.set_down(false);
+
+    // The states of the second tick take too long to write
+    setup.sink.slow_down("variable=power", DEADLINE * 4);
+    setup.tick().await;
+
+    assert_eq!(setup.outcomes(), vec!["write_timeout"]);
+    for id in ["EH000001", "EH000002"] {
+        let reachable: Vec<String> = setup
+            .sink
+            .memory
+            .measurement(id)
+            .into_iter()
+            .filter(|line| line.contains("variable=reachable"))
+            .collect();
+        assert_eq!(reachable.len(), 1, "{}", id);
+        assert!(reachable[0].contains("value=0i"), "{}", reachable[0]);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn states_that_time_out_are_retried() {
+    let _guard = exclusive().await;
+    let setup = Setup::new().await;
+    setup.easee.chargers().await;
+    setup.easee.state("EH000001", "state_charging.json").await;
+    setup.easee.state("EH000002", "state_idle.json").await;
+
+    setup.sink.slow_down("variable=power", DEADLINE * 4);
+    setup.tick().await;
+    assert_eq!(setup.outcomes(), vec!["write_timeout"]);
+    assert!(!setup.poller.retry.is_empty().await);
+
+    setup.sink.speed_up();
+    setup.tick().await;
+
+    assert!(setup.poller.retry.is_empty().await);
+    // The states of the first tick, from the buffer, and those of the second
+    let power: Vec<String> = setup
+        .sink
+        .memory
+        .measurement("EH000001")
+        .into_iter()
+        .filter(|line| line.contains("variable=power"))
+        .collect();
+    assert_eq!(power.len(), 2, "{:?}", power);
+    assert_ne!(power[0], power[1]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn an_easee_error_still_writes_buffered_points() {
+    let _guard = exclusive().await;
+    let setup = Setup::new().await;
+    let chargers =
+        ResponseTemplate::new(200).set_body_raw(fixture("chargers.json"), "application/json");
+    Mock::given(method("GET"))
+        .and(path("/api/chargers"))
+        .respond_with(chargers)
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&setup.easee.server)
+        .await;
+    setup.easee.get("/api/chargers", 500, "").await;
+    setup.easee.state("EH000001", "state_charging.json").await;
+    setup.easee.state("EH000002", "state_idle.json").await;
+
+    setup.sink.set_down(true);
+    setup.tick().await;
+    assert!(!setup.poller.retry.is_empty().await);
+    setup.sink.set_down(false);
+
+    setup.tick().await;
+
+    assert!(setup.poller.retry.is_empty().await);
+    assert_eq!(setup.outcomes(), vec!["easee_failed"]);
+    let power: Vec<String> = setup
+        .sink
+        .memory
+        .measurement("EH000002")
+        .into_iter()
+        .filter(|line| line.contains("variable=power"))
+        .collect();
+    assert_eq!(power.len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn a_failing_config_leaves_the_others() {
+    let _guard = exclusive().await;
+    let setup = Setup::new().await;
+    setup.easee.chargers().await;
+    setup
+        .easee
+        .get("/api/chargers/EH000001/config", 500, "")
+        .await;
+    setup.easee.config("EH000002").await;
+
+    slow_tick(setup.session.clone(), setup.targets.clone(), DEADLINE).await;
+
+    assert!(setup.sink.memory.measurement("EH000001_config").is_empty());
+    let written = setup.sink.memory.measurement("EH000002_config");
+    assert!(written
+        .iter()
+        .any(|line| line.contains("variable=max_charger_current") && line.contains("value=16")));
+}
+
+/// Records how many writes were in flight at once, each taking `delay`.
+#[derive(Debug, Default)]
+struct OverlapSink {
+    delay: Duration,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    writes: AtomicUsize,
+}
+
+#[rocket::async_trait]
+impl Sink for OverlapSink {
+    async fn write_lines(&self, _lines: &str) -> Result<(), String> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn writes_to_every_target_and_charger_at_once() {
+    let _guard = exclusive().await;
+    let easee = MockEasee::start().await;
+    easee.login(Duration::ZERO).await;
+    easee.chargers().await;
+    easee.state("EH000001", "state_charging.json").await;
+    easee.state("EH000002", "state_idle.json").await;
+
+    let delay = Duration::from_millis(200);
+    let sink = Arc::new(OverlapSink {
+        delay,
+        ..OverlapSink::default()
+    });
+    let targets: Vec<DbTarget> = ["http://influx-a.invalid", "http://influx-b.invalid"]
+        .into_iter()
+        .map(|addr| DbTarget::with_sink(String::from(addr), String::from("easee"), sink.clone()))
+        .collect();
+
+    let started = Instant::now();
+    tick(
+        Arc::new(Mutex::new(SessionState::new())),
+        Arc::new(targets),
+        None,
+        Arc::new(PollerState::from_env(Arc::new(LastErrors::new()))),
+        Duration::from_secs(5),
+    )
+    .await;
+    let elapsed = started.elapsed();
+
+    // Two chargers to two targets, besides the heartbeats
+    let writes = sink.writes.load(Ordering::SeqCst);
+    assert!(writes >= 4, "{} writes", writes);
+    assert_eq!(sink.max_in_flight.load(Ordering::SeqCst), 4);
+    // One after the other, the four charger writes alone would take three delays longer
+    assert!(
+        elapsed < delay * (writes as u32 - 2),
+        "{} writes took {:?}",
+        writes,
+        elapsed
+    );
+}
+
+/// Ticks once with every event captured.
+async fn captured_tick(setup: &Setup) -> Capture {
+    let capture = Capture::default();
+    // A single threaded runtime, so the write tasks log to this subscriber too
+    let _default = tracing::subscriber::set_default(capture.subscriber());
+    setup.tick().await;
+    capture
+}
+
+#[tokio::test]
+async fn fetch_and_write_events_carry_the_charger_id() {
+    let _guard = exclusive().await;
+    let setup = Setup::new().await;
+    setup.easee.chargers().await;
+    setup.easee.state("EH000001", "state_charging.json").await;
+    setup.easee.state("EH000002", "state_idle.json").await;
+
+    let events = captured_tick(&setup).await.events();
+    for id in ["EH000001", "EH000002"] {
+        let id = serde_json::Value::from(id);
+        let fetched = events
+            .iter()
+            .filter(|e| e.message().starts_with("Got charger state"))
+            .filter(|e| e.field("charger_id") == Some(&id));
+        assert_eq!(fetched.count(), 1, "{}", id);
+        let written = events
+            .iter()
+            .filter(|e| e.message().starts_with("Writing power"))
+            .filter(|e| e.field("charger_id") == Some(&id));
+        assert_eq!(written.count(), 1, "{}", id);
+    }
+    // Every charger write is in a span saying which charger it is for
+    for event in events
+        .iter()
+        .filter(|e| e.spans.iter().any(|(name, _)| *name == "write_charger"))
+    {
+        assert!(event.field("charger_id").is_some(), "{:?}", event);
+        assert!(event.field("target").is_some(), "{:?}", event);
+    }
+}
+
+#[tokio::test]
+async fn every_tick_ends_with_one_numeric_summary() {
+    let _guard = exclusive().await;
+    let setup = Setup::new().await;
+    setup.easee.chargers().await;
+    setup.easee.state("EH000001", "state_charging.json").await;
+    setup.easee.state("EH000002", "state_idle.json").await;
+    let summaries = |capture: Capture| -> Vec<common::CapturedEvent> {
+        capture
+            .events()
+            .into_iter()
+            .filter(|e| e.message() == "Tick finished")
+            .collect()
+    };
+
+    let ok = summaries(captured_tick(&setup).await);
+    assert_eq!(ok.len(), 1);
+    let summary = &ok[0];
+    assert_eq!(summary.level, tracing::Level::INFO);
+    for field in [
+        "chargers_total",
+        "chargers_ok",
+        "chargers_failed",
+        "points_written",
+        "points_buffered",
+        "duration_ms",
+    ] {
+        let value = summary.fields.get(field);
+        assert!(value.is_some_and(|v| v.is_u64()), "{}: {:?}", field, value);
+    }
+    assert_eq!(summary.fields["chargers_total"], 2);
+    assert_eq!(summary.fields["chargers_ok"], 2);
+    assert_eq!(summary.fields["outcome"], "ok");
+    assert!(summary.fields["points_written"].as_u64().unwrap() > 0);
+
+    // A failed tick summarizes too
+    setup.easee.server.reset().await;
+    let failed = summaries(captured_tick(&setup).await);
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].fields["outcome"], "easee_failed");
+    assert_eq!(failed[0].fields["points_written"], 0);
+}
+
+#[tokio::test]
+async fn parsing_spans_carry_the_payload_size_and_charger() {
+    let _guard = exclusive().await;
+    let setup = Setup::new().await;
+    setup.easee.chargers().await;
+    setup.easee.state("EH000001", "state_charging.json").await;
+    setup.easee.state("EH000002", "state_idle.json").await;
+
+    let spans = captured_tick(&setup).await.spans("parsing_response");
+    let parsed = |charger_id: &str, fixture_name: &str| {
+        let span = spans
+            .iter()
+            .find(|fields| fields.get("charger_id") == Some(&charger_id.into()))
+            .unwrap_or_else(|| panic!("No parsing span for {:?} in {:?}", charger_id, spans));
+        assert_eq!(span["payload_bytes"], fixture(fixture_name).len());
+        assert!(span["parse_ms"].is_u64(), "{:?}", span);
+    };
+    // The charger list is for no charger in particular
+    parsed("", "chargers.json");
+    parsed("EH000001", "state_charging.json");
+    parsed("EH000002", "state_idle.json");
+}
+
+/// The value and timestamp of the `reachable` point written for the charger.
+fn reachable(setup: &Setup, id: &str) -> Vec<(String, String)> {
+    setup
+        .sink
+        .memory
+        .measurement(id)
+        .into_iter()
+        .filter(|line| line.contains("variable=reachable"))
+        .map(|line| {
+            let fields = line.split(' ').nth(1).unwrap().to_string();
+            let time = line.rsplit(' ').next().unwrap().to_string();
+            (fields, time)
+        })
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn a_failing_charger_is_written_as_unreachable() {
+    let _guard = exclusive().await;
+    let setup = Setup::new().await;
+    setup.easee.chargers().await;
+    setup.easee.state("EH000001", "state_charging.json").await;
+    setup
+        .easee
+        .get("/api/chargers/EH000002/state", 500, "")
+        .await;
+
+    setup.tick().await;
+
+    let ok = reachable(&setup, "EH000001");
+    let failed = reachable(&setup, "EH000002");
+    assert_eq!(ok.len(), 1, "{:?}", ok);
+    assert_eq!(failed.len(), 1, "{:?}", failed);
+    assert_eq!(ok[0].0, "value=1i");
+    assert_eq!(failed[0].0, "value=0i");
+    // Both at the tick's time, though only one charger was fetched
+    assert_eq!(ok[0].1, failed[0].1);
+    assert!(!setup
+        .sink
+        .memory
+        .measurement("EH000002")
+        .iter()
+        .any(|line| line.contains("variable=power")));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn a_failed_tick_still_writes_its_heartbeat() {
+    let _guard = exclusive().await;
+    let setup = Setup::new().await;
+    setup.easee.get("/api/chargers", 500, "").await;
+
+    setup.tick().await;
+    setup.tick().await;
+
+    let heartbeats = setup.sink.memory.measurement("poller_heartbeat");
+    assert_eq!(heartbeats.len(), 2, "{:?}", heartbeats);
+    for heartbeat in &heartbeats {
+        for part in ["value=1i", "outcome=\"easee_failed\"", "chargers=0i"] {
+            assert!(heartbeat.contains(part), "{} in {}", part, heartbeat);
+        }
+    }
+    assert!(setup.sink.memory.measurement("EH000001").is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn a_large_backlog_is_written_in_batches() {
+    let _guard = exclusive().await;
+    let setup = Setup::new().await;
+    setup.easee.get("/api/chargers", 500, "").await;
+    let influx = wiremock::MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/write"))
+        .respond_with(ResponseTemplate::new(204))
+        .mount(&influx)
+        .await;
+    let target = DbTarget::new(influx.uri(), String::from("easee"), None);
+    let backlog: Vec<String> = (0..1200)
+        .map(|i| format!("backlog value={} {}", i, i))
+        .collect();
+    setup.poller.retry.push(&target.addr, backlog).await;
+
+    tick(
+        setup.session.clone(),
+        Arc::new(vec![target]),
+        None,
+        setup.poller.clone(),
+        Duration::from_secs(5),
+    )
+    .await;
+
+    assert!(setup.poller.retry.is_empty().await);
+    let requests = influx.received_requests().await.unwrap();
+    let lines: Vec<usize> = requests
+        .iter()
+        .map(|r| String::from_utf8_lossy(&r.body).lines().count())
+        .collect();
+    // Batches of 500, then the heartbeat, rather than a request per point
+    assert_eq!(lines, vec![500, 500, 200, 1]);
+}